@@ -1,10 +1,16 @@
+use std::borrow::Cow;
 use std::f64;
+use std::ops::Range;
 
-use crate::{EEGData, EEGInfo, Markers};
+use crate::{ChannelType, EEGData, EEGInfo, Error, Markers, Picks};
 use crate::signal;
-use egui_plot::{Line, Plot, PlotPoint, PlotPoints, Text, VLine};
+use crate::io::MappedEeg;
+use egui_plot::{Line, Plot, PlotPoint, PlotPoints, PlotUi, Polygon, Text, VLine};
 use egui::{Key, Vec2};
 
+/// Distance between the baselines of neighbouring channels in plot units
+const CHANNEL_SPACING: f64 = 10.0;
+
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
 //#[derive(serde::Deserialize, serde::Serialize)]
 //#[serde(default)] // if we add new fields, give them default values when deserializing old state
@@ -16,6 +22,8 @@ pub struct TemplateApp {
     info: EEGInfo,
     //#[serde(skip)]
     data: EEGData,
+    /// Memory-mapped recording, decoded into `data` on the first processing step
+    mapped: Option<MappedEeg>,
     //#[serde(skip)]
     markers: Markers,
     selected_channel: usize,
@@ -25,14 +33,24 @@ pub struct TemplateApp {
     y_view_max: f64,
     plot_zoom_factor: Vec2,
     gain: f64,
+    /// Amplitude, in the unit of the data (usually µV), drawn as one channel spacing at gain 1
+    amplitude: f64,
     view_all: bool,
     show_data: bool,
     decimation_factor: usize,
     tmin_cut: f64,
     tmax_cut: f64,
+    /// Description of the TMS pulse markers
+    tms_marker: String,
     lfreq: f64,
     hfreq: f64,
-    n_sfreq: usize
+    n_sfreq: usize,
+    /// Channels the TMS removal and the filters are applied to
+    picks: Picks,
+    /// Channel labels above this impedance (in the unit of the impedance table) are drawn in red
+    impedance_threshold: f64,
+    /// Last failed processing step, shown in a dialog until it is dismissed
+    error: Option<Error>,
 }
 
 impl TemplateApp {
@@ -43,12 +61,14 @@ impl TemplateApp {
         Self{
             info: info,
             data: data,
+            mapped: None,
             markers: markers,
             selected_channel: 0,
             y_view_min: 0.0,
             y_view_max: 600.0,
             x_view: 0.0,
             gain: 1.0,
+            amplitude: 100.0,
             plot_zoom_factor: Vec2::new(1.0, 1.0),
             unselected_channels: Vec::new(),
             view_all: false,
@@ -56,9 +76,13 @@ impl TemplateApp {
             decimation_factor: 100,
             tmin_cut: 0.005,
             tmax_cut: 0.005,
+            tms_marker: String::from(signal::TMS_PULSE),
             lfreq: 1.0,
             hfreq: 45.0,
             n_sfreq: 725,
+            picks: Picks::All,
+            impedance_threshold: 10.0,
+            error: None,
         }
         // Load previous app state (if any).
         // Note that you must enable the `persistence` feature for this to work.
@@ -68,6 +92,73 @@ impl TemplateApp {
          //   Default::default()
        // }
     }
+
+    /// Opens the viewer on a memory-mapped recording, only the visible window is decoded.
+    pub fn with_mapped(cc: &eframe::CreationContext<'_>, info: EEGInfo, mapped: MappedEeg, markers: Markers) -> Self {
+        let mut app = Self::new(cc, info, EEGData { data: ndarray::Array2::zeros((0, 0)) }, markers);
+        app.mapped = Some(mapped);
+        app
+    }
+
+    /// Sets the channels the TMS removal and the filters start out processing.
+    pub fn with_picks(mut self, picks: Picks) -> Self {
+        self.picks = picks;
+        self
+    }
+}
+
+impl TemplateApp {
+    /// Decodes a memory-mapped recording so it can be processed.
+    fn load_mapped(&mut self) {
+        if let Some(mapped) = self.mapped.take() {
+            self.data.data = mapped.to_array();
+        }
+    }
+
+    /// Replaces the data with the result of a processing step, or keeps it and shows the
+    /// error. Returns `true` on success.
+    fn apply(&mut self, result: Result<ndarray::Array2<f32>, Error>) -> bool {
+        match result {
+            Ok(data) => {
+                self.data.data = data;
+                true
+            }
+            Err(err) => {
+                self.error = Some(err);
+                false
+            }
+        }
+    }
+
+    fn error_dialog(&mut self, ctx: &egui::Context) {
+        let Some(error) = &self.error else { return };
+        let message = error.to_string();
+        let mut dismissed = false;
+        egui::Window::new("Error")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(message);
+                dismissed = ui.button("OK").clicked();
+            });
+        if dismissed {
+            self.error = None;
+        }
+    }
+
+    fn n_channels(&self) -> usize {
+        self.mapped.as_ref().map_or(self.data.data.nrows(), MappedEeg::n_channels)
+    }
+
+    /// `true` when the impedance of channel `name` is above the threshold or out of range.
+    fn bad_impedance(&self, name: &str) -> bool {
+        self.info
+            .impedances
+            .as_ref()
+            .and_then(|table| table.get(name))
+            .is_some_and(|impedance| impedance.exceeds(self.impedance_threshold))
+    }
 }
 
 impl TemplateApp {
@@ -88,11 +179,16 @@ impl TemplateApp {
 
 
 impl TemplateApp {
-   fn min_max_decimate(&self, data: &[i16], start_sample: usize, decimation: usize, offset: f64) -> Vec<[f64; 2]>{
+    /// Plot height of `sample` for a channel whose baseline is at `offset`.
+    fn plot_y(&self, sample: f32, offset: f64) -> f64 {
+        f64::from(sample) / self.amplitude * CHANNEL_SPACING * self.gain + offset
+    }
+
+   fn min_max_decimate(&self, data: &[f32], start_sample: usize, decimation: usize, offset: f64) -> Vec<[f64; 2]>{
         if decimation <= 1 {
             return data.into_iter().enumerate().map(|(i, &sample)| {
-                let x = (start_sample + i) as f64 / self.info.sfreq as f64;
-                let y = (sample as f64 / 100.0) * self.gain + offset;
+                let x = (start_sample + i) as f64 / self.info.sfreq;
+                let y = self.plot_y(sample, offset);
 
                 [x, y]
             }).collect();
@@ -100,12 +196,14 @@ impl TemplateApp {
         let mut points = Vec::new();
         for chunk in data.chunks(decimation) {
             let chunk_start = (points.len() / 2) * decimation;
-            let time_base = (start_sample + chunk_start) as f64 / self.info.sfreq as f64;
+            let time_base = (start_sample + chunk_start) as f64 / self.info.sfreq;
 
-            if let (Some(&min_val), Some(&max_val)) = (chunk.iter().min(), chunk.iter().max()) {
-                points.push([time_base, (min_val as f64 / 100.0) + offset]);
-                points.push([time_base + (decimation as f64 * 0.5) / self.info.sfreq as f64,
-                            (max_val as f64 / 100.0) * self.gain + offset]);
+            if !chunk.is_empty() {
+                let min_val = chunk.iter().copied().fold(f32::INFINITY, f32::min);
+                let max_val = chunk.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                points.push([time_base, self.plot_y(min_val, offset)]);
+                points.push([time_base + (decimation as f64 * 0.5) / self.info.sfreq,
+                            self.plot_y(max_val, offset)]);
 
             }
         }
@@ -114,34 +212,9 @@ impl TemplateApp {
     }
 }
 
-impl eframe::App for TemplateApp {
-    /// Called by the framework to save state before shutdown.
-    //fn save(&mut self, storage: &mut dyn eframe::Storage) {
-      //  eframe::set_value(storage, eframe::APP_KEY, self);
-    //}
-
-
-    /// Called each time the UI needs repainting, which may be many times per second.
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Put your widgets into a `SidePanel`, `TopBottomPanel`, `CentralPanel`, `Window` or `Area`.
-        egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
-            // The top panel is often a good place for a menu bar:
-
-            egui::MenuBar::new().ui(ui, |ui| {
-                // NOTE: no File->Quit on web pages!
-                let is_web = cfg!(target_arch = "wasm32");
-                if !is_web {
-                    ui.menu_button("File", |ui| {
-                        if ui.button("Quit").clicked() {
-                            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
-                        }
-                    });
-                    ui.add_space(16.0);
-                }
-                egui::widgets::global_theme_preference_buttons(ui);
-            });
-        });
-
+/// Panels of the plot.
+impl TemplateApp {
+    fn handle_keys(&mut self, ctx: &egui::Context) {
         if ctx.input(|i|i.key_pressed(Key::K)){
             self.y_view_max += 10.0;
             self.y_view_min += 10.0
@@ -169,94 +242,332 @@ impl eframe::App for TemplateApp {
         if ctx.input(|i|i.key_pressed(Key::ArrowDown)){
             self.gain /= 1.1;
         }
+    }
 
+    fn channel_selection(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("EEG channels: ");
+        });
+        let alternatives = &self.info.ch_names;
+        egui::ComboBox::from_label("Select channels to remove").show_index(
+            ui,
+            &mut self.selected_channel,
+            alternatives.len(),
+            |i| &alternatives[i]
+        );
+        if !self.unselected_channels.contains(&(self.selected_channel)){
+            self.unselected_channels.push(self.selected_channel);
+        }
 
-        egui::CentralPanel::default().show(ctx, |ui| {
-            // The central panel the region left after adding TopPanel's and SidePanel's
-            ui.heading("reegui");
-            ui.horizontal(|ui| {
-                ui.label("EEG channels: ");
-                            });
-            let alternatives = &self.info.ch_names;
-            egui::ComboBox::from_label("Select channels to remove").show_index(
-                ui,
-                &mut self.selected_channel,
-                alternatives.len(),
-                |i| &alternatives[i]
+        ui.checkbox(&mut self.view_all, "Show all channels");
+
+        if ui.button("Show EEG data").clicked(){
+            self.show_data = true
+        }
+
+        if self.view_all == true {
+            self.unselected_channels.clear();
+        }
+    }
+
+    /// Samples `range` of channel `ch`, only this window is decoded from a memory-mapped
+    /// recording.
+    fn visible_samples(&self, ch: usize, range: Range<usize>) -> Cow<'_, [f32]> {
+        if let Some(mapped) = &self.mapped {
+            return Cow::Owned(mapped.channel_window(ch, range));
+        }
+        let channel_slice = self.data.data.row(ch).to_slice().unwrap();
+        Cow::Borrowed(&channel_slice[range.start.min(channel_slice.len())..range.end.min(channel_slice.len())])
+    }
+
+    /// Name of channel `ch` at its baseline, red when its impedance is too high.
+    fn channel_label(&self, ch: usize, offset: f64) -> Text {
+        let name = &self.info.ch_names[ch];
+        let text = Text::new(name.clone(), PlotPoint::new(self.x_view + 0.1, offset), name.clone());
+        if self.bad_impedance(name) {
+            text.color(egui::Color32::RED)
+        } else {
+            text
+        }
+    }
+
+    /// Shades the bad segments overlapping `window` (in seconds). Bad segments of all
+    /// channels span the plot, the others their channel only.
+    fn plot_bad_segments(&self, plot_ui: &mut PlotUi<'_>, window: Range<f64>, total_height: f64, channel_offsets: &[(usize, f64)]) {
+        for bad in self.markers.bad_segments() {
+            let span = bad.span();
+            let (start, end) = (span.start as f64 / self.info.sfreq, span.end as f64 / self.info.sfreq);
+            if end < window.start || start > window.end {
+                continue;
+            }
+            let (bottom, top) = if bad.channel == 0 {
+                (-CHANNEL_SPACING, total_height + CHANNEL_SPACING)
+            } else {
+                let Some(&(_, ch_offset)) = channel_offsets.iter().find(|(ch, _)| *ch + 1 == bad.channel) else { continue };
+                (ch_offset - CHANNEL_SPACING / 2.0, ch_offset + CHANNEL_SPACING / 2.0)
+            };
+            let corners = vec![[start, bottom], [end, bottom], [end, top], [start, top]];
+            plot_ui.polygon(
+                Polygon::new(bad.description.clone(), PlotPoints::new(corners))
+                    .fill_color(egui::Color32::from_rgba_unmultiplied(255, 0, 0, 40))
+                    .stroke(egui::Stroke::NONE),
             );
-            if !self.unselected_channels.contains(&(self.selected_channel)){
-            self.unselected_channels.push(self.selected_channel);
+        }
+    }
+
+    fn data_plot(&self, ui: &mut egui::Ui) {
+        let channel_offset = CHANNEL_SPACING;
+        let mut offset = 0.0;
+
+        Plot::new("my_plot")
+            .show_x(true)
+            .show_y(false)
+            .show(ui, |plot_ui| {
+
+            let start_time = self.x_view;
+            let end_time = self.x_view + 10.0;
+            let start_sample = ((start_time * self.info.sfreq) as usize).max(0);
+            let end_sample = (end_time * self.info.sfreq) as usize;
+            let visible_channels = self.n_channels() - self.unselected_channels.len();
+            let total_height = visible_channels as f64 * channel_offset;
+            plot_ui.set_plot_bounds_y(-channel_offset..=(total_height + channel_offset));
+            let mut channel_offsets = Vec::new();
+
+            for ch in 0..self.n_channels(){
+                if !self.unselected_channels.contains(&ch){
+                    let visible_data = self.visible_samples(ch, start_sample..end_sample);
+
+                    if !visible_data.is_empty() {
+                        let adaptive_decimation = self.get_adaptive_decimation();
+                        let points = self.min_max_decimate(&visible_data, start_sample, adaptive_decimation, offset);
+                        let line = Line::new("EEG", points);
+                        plot_ui.line(line);
+                        plot_ui.text(self.channel_label(ch, offset));
+
+                        channel_offsets.push((ch, offset));
+                        offset += channel_offset;
+                    }
+                }
             }
 
-            //let before = self.y_view_max;
 
+            let center_x = self.x_view + 5.0;
+            let center_y = self.y_view_min + 10.0 + (self.y_view_max - self.y_view_min) / 2.0;
+            let center_point = PlotPoint::new(center_x, center_y);
+
+            plot_ui.set_plot_bounds_x(self.x_view..=(self.x_view + 10.0));
+            plot_ui.zoom_bounds(Vec2::new(self.plot_zoom_factor[0], 1.0), center_point);
 
-            ui.checkbox(&mut self.view_all, "Show all channels");
+            let marker_points = &self.markers.markers;
+            for x in 0..marker_points.len(){
+                    let marker_pos = self.markers.markers[x] / self.info.sfreq;
+                    plot_ui.vline(VLine::new("TMS", marker_pos));
+                }
+
+            self.plot_bad_segments(plot_ui, start_time..end_time, total_height, &channel_offsets);
+            });
+    }
+}
 
-            if ui.button("Show EEG data").clicked(){
-                self.show_data = true
+/// Panels of the controls.
+impl TemplateApp {
+    fn y_scale_selector(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Y scale")
+            .selected_text(format!("{:?}", self.y_view_max))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.y_view_max, 200.0, "200");
+                ui.selectable_value(&mut self.y_view_max, 300.0, "300");
+                ui.selectable_value(&mut self.y_view_max, 400.0, "400");
+                ui.selectable_value(&mut self.y_view_max, 500.0, "500");
+                ui.selectable_value(&mut self.y_view_max, 600.0, "600");
+                ui.selectable_value(&mut self.y_view_max, 700.0, "700");
             }
+        );
+    }
 
-            if self.view_all == true {
-                self.unselected_channels.clear();
+    fn amplitude_selector(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Amplitude (µV)")
+            .selected_text(format!("{:?}", self.amplitude))
+            .show_ui(ui, |ui| {
+                for amplitude in [10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0] {
+                    ui.selectable_value(&mut self.amplitude, amplitude, amplitude.to_string());
+                }
             }
+        );
+    }
 
-            if self.show_data {
-                let channel_offset = 10.0;
-                let mut offset = 0.0;
-
-                let plt = Plot::new("my_plot")
-                    .show_x(true)
-                    .show_y(false)
-                    .show(ui, |plot_ui| {
-
-                    let start_time = self.x_view;
-                    let end_time = self.x_view + 10.0;
-                    let start_sample = ((start_time * self.info.sfreq as f64) as usize).max(0);
-                    let end_sample = (end_time * self.info.sfreq as f64) as usize;
-                    let visible_channels = self.data.data.nrows() - self.unselected_channels.len();
-                    let total_height = visible_channels as f64 * channel_offset;
-                    plot_ui.set_plot_bounds_y(-channel_offset..=(total_height + channel_offset));
-
-                    for ch in 0..self.data.data.nrows(){
-                        if !self.unselected_channels.contains(&ch){
-                            let one_channel = self.data.data.row(ch);
-                            let channel_slice = one_channel.as_slice().unwrap();
-
-                            if start_sample < channel_slice.len() {
-                                let actual_end = end_sample.min(channel_slice.len());
-                                let visible_data = &channel_slice[start_sample..actual_end];
-                                let adaptive_decimation = self.get_adaptive_decimation();
-                                let points = self.min_max_decimate(visible_data, start_sample, adaptive_decimation, offset);
-                                let line = Line::new("EEG", points);
-                                plot_ui.line(line);
-                                let text_x = self.x_view + 0.1;
-                                let text_y = offset;
-                                let text_point = PlotPoint::new(text_x, text_y);
-                                let text = Text::new(self.info.ch_names[ch].clone(), text_point, self.info.ch_names[ch].clone());
-                                plot_ui.text(text);
-
-                                offset += channel_offset;
-                            }
-                        }
-                    }
+    fn decimation_selector(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Decmation factor")
+            .selected_text(format!("{:?}", self.decimation_factor))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.decimation_factor, 0, "0");
+                ui.selectable_value(&mut self.decimation_factor, 10, "10");
+                ui.selectable_value(&mut self.decimation_factor, 20, "20");
+                ui.selectable_value(&mut self.decimation_factor, 50, "50");
+                ui.selectable_value(&mut self.decimation_factor, 100, "100");
+
+            }
+        );
+    }
 
+    fn picks_combo(&mut self, ui: &mut egui::Ui) {
+        let eeg = Picks::Types(vec![ChannelType::Eeg]);
+        let physiological = Picks::Types(vec![ChannelType::Eeg, ChannelType::Eog, ChannelType::Ecg]);
+        egui::ComboBox::from_label("Process channels")
+            .selected_text(self.picks.to_string())
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.picks, Picks::All, "all");
+                ui.selectable_value(&mut self.picks, eeg.clone(), eeg.to_string());
+                ui.selectable_value(&mut self.picks, physiological.clone(), physiological.to_string());
+            }
+        );
+    }
 
-                    let center_x = self.x_view + 5.0;
-                    let center_y = self.y_view_min + 10.0 + (self.y_view_max - self.y_view_min) / 2.0;
-                    let center_point = PlotPoint::new(center_x, center_y);
+    fn tms_controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("TMS marker");
+            ui.text_edit_singleline(&mut self.tms_marker);
+        });
 
-                    plot_ui.set_plot_bounds_x(self.x_view..=(self.x_view + 10.0));
-                    plot_ui.zoom_bounds(Vec2::new(self.plot_zoom_factor[0], 1.0), center_point);
+        if ui.button("Remove TMS pulse").clicked(){
+            self.load_mapped();
+            let result = signal::remove_tms_pulse(self.tmin_cut, self.tmax_cut, &self.markers, &self.tms_marker, &self.info, &self.data.data, &self.picks);
+            self.apply(result);
+        }
+
+        if ui.button("Remove and interpolate TMS pulse").clicked(){
+            self.load_mapped();
+            let result = signal::rm_interp_tms_pulse(self.tmin_cut, self.tmax_cut, &self.markers, &self.tms_marker, &self.info, &self.data.data, &self.picks);
+            self.apply(result);
+        }
+    }
+
+    /// The impedance table of the header, if any, with the channels above the threshold in red.
+    fn impedance_panel(&mut self, ui: &mut egui::Ui) {
+        let Some(table) = &self.info.impedances else { return };
+        ui.heading("Impedances");
+        ui.label(format!("Measured at {}", table.time));
+        ui.horizontal(|ui| {
+            ui.label(format!("Threshold [{}]", table.unit));
+            ui.add(egui::DragValue::new(&mut self.impedance_threshold).range(0.0..=1000.0));
+        });
+        egui::Grid::new("impedances").show(ui, |ui| {
+            for impedance in &table.impedances {
+                let value = impedance.value.map_or_else(|| String::from("Out of range"), |value| value.to_string());
+                let color = if impedance.exceeds(self.impedance_threshold) {
+                    egui::Color32::RED
+                } else {
+                    ui.visuals().text_color()
+                };
+                ui.colored_label(color, &impedance.name);
+                ui.colored_label(color, value);
+                ui.end_row();
+            }
+        });
+    }
+
+    fn filter_controls(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Filter settings");
+        egui::ComboBox::from_label("Highpass filter lfreq")
+            .selected_text(format!("{:?}", self.lfreq))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.lfreq, 0.1, "0.1");
+                ui.selectable_value(&mut self.lfreq, 0.2, "0.2");
+                ui.selectable_value(&mut self.lfreq, 0.3, "0.3");
+                ui.selectable_value(&mut self.lfreq, 0.5, "0.5");
+                ui.selectable_value(&mut self.lfreq, 1.0, "1.0");
+                ui.selectable_value(&mut self.lfreq, 2.0, "2.0");
+            }
+        );
+
+        egui::ComboBox::from_label("Lowpass filter hfreq")
+            .selected_text(format!("{:?}", self.hfreq))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.hfreq, 30.0, "30");
+                ui.selectable_value(&mut self.hfreq, 40.0, "40");
+                ui.selectable_value(&mut self.hfreq, 45.0, "45");
+                ui.selectable_value(&mut self.hfreq, 50.0, "50");
+                ui.selectable_value(&mut self.hfreq, 70.0, "70");
+                ui.selectable_value(&mut self.hfreq, 100.0, "100");
+            }
+        );
+
+        if ui.button("Filter data").clicked(){
+            self.load_mapped();
+            let result = signal::hp_filter(self.lfreq, &self.info, &self.data.data, &self.picks)
+                .and_then(|data| signal::lp_filter(self.hfreq, &self.info, &data, &self.picks));
+            self.apply(result);
+        }
+    }
+
+    fn resample_controls(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Resample data");
+        ui.collapsing("Warning!", |ui| { ui.label("Do not resample before removing TMS artefact and filtering data!"); });
+        egui::ComboBox::from_label("New sfreq")
+            .selected_text(format!("{:?}", self.n_sfreq))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.n_sfreq, 100, "100 Hz");
+                ui.selectable_value(&mut self.n_sfreq, 200, "200 Hz");
+                ui.selectable_value(&mut self.n_sfreq, 250 , "250 Hz");
+                ui.selectable_value(&mut self.n_sfreq, 725, "725 Hz");
+                ui.selectable_value(&mut self.n_sfreq, 1000, "1000 Hz");
+                ui.selectable_value(&mut self.n_sfreq, 2000, "2000 Hz");
+            }
+        );
+
+        if ui.button("Apply resampling").clicked(){
+            self.load_mapped();
+            let n_samples = self.data.data.ncols();
+            let result = signal::resample_eeg(self.n_sfreq as f64, &self.info, &self.data.data);
+            if self.apply(result) {
+                let sfreq = signal::resampled_sfreq(self.info.sfreq, n_samples, self.data.data.ncols());
+                self.markers = signal::resample_markers(&self.markers, self.info.sfreq, sfreq);
+                self.info.sfreq = sfreq;
+                self.info.sampling_interval = 1_000_000.0 / sfreq;
+            }
+
+        }
+    }
+}
+
+impl eframe::App for TemplateApp {
+    /// Called by the framework to save state before shutdown.
+    //fn save(&mut self, storage: &mut dyn eframe::Storage) {
+      //  eframe::set_value(storage, eframe::APP_KEY, self);
+    //}
 
-                    let marker_points = &self.markers.markers;
-                    for x in 0..marker_points.len(){
-                            let marker_pos = self.markers.markers[x] / self.info.sfreq as f64;
-                            plot_ui.vline(VLine::new("TMS", marker_pos));
-                        }
 
+    /// Called each time the UI needs repainting, which may be many times per second.
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Put your widgets into a `SidePanel`, `TopBottomPanel`, `CentralPanel`, `Window` or `Area`.
+        egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
+            // The top panel is often a good place for a menu bar:
+
+            egui::MenuBar::new().ui(ui, |ui| {
+                // NOTE: no File->Quit on web pages!
+                let is_web = cfg!(target_arch = "wasm32");
+                if !is_web {
+                    ui.menu_button("File", |ui| {
+                        if ui.button("Quit").clicked() {
+                            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                        }
                     });
+                    ui.add_space(16.0);
+                }
+                egui::widgets::global_theme_preference_buttons(ui);
+            });
+        });
+
+        self.error_dialog(ctx);
+        self.handle_keys(ctx);
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            // The central panel the region left after adding TopPanel's and SidePanel's
+            ui.heading("reegui");
+            self.channel_selection(ui);
 
+            if self.show_data {
+                self.data_plot(ui);
             };
 
             egui::widgets::global_theme_preference_buttons(ui);
@@ -274,89 +585,16 @@ impl eframe::App for TemplateApp {
         });
 
         egui::SidePanel::right("controls_panel").show(ctx, |ui| {
-                ui.heading("Controls");
-                            egui::ComboBox::from_label("Y scale")
-                .selected_text(format!("{:?}", self.y_view_max))
-                .show_ui(ui, |ui| {
-                    ui.selectable_value(&mut self.y_view_max, 200.0, "200");
-                    ui.selectable_value(&mut self.y_view_max, 300.0, "300");
-                    ui.selectable_value(&mut self.y_view_max, 400.0, "400");
-                    ui.selectable_value(&mut self.y_view_max, 500.0, "500");
-                    ui.selectable_value(&mut self.y_view_max, 600.0, "600");
-                    ui.selectable_value(&mut self.y_view_max, 700.0, "700");
-                }
-            );
-
-            egui::ComboBox::from_label("Decmation factor")
-                .selected_text(format!("{:?}", self.decimation_factor))
-                .show_ui(ui, |ui| {
-                    ui.selectable_value(&mut self.decimation_factor, 0, "0");
-                    ui.selectable_value(&mut self.decimation_factor, 10, "10");
-                    ui.selectable_value(&mut self.decimation_factor, 20, "20");
-                    ui.selectable_value(&mut self.decimation_factor, 50, "50");
-                    ui.selectable_value(&mut self.decimation_factor, 100, "100");
-
-                }
-            );
-
-            if ui.button("Remove TMS pulse").clicked(){
-                self.data.data = signal::remove_tms_pulse(self.tmin_cut, self.tmax_cut, &self.markers, &self.info, &self.data.data).expect("Removal failed")
-            }
-
-            if ui.button("Remove and interpolate TMS pulse").clicked(){
-                self.data.data = signal::rm_interp_tms_pulse(self.tmin_cut, self.tmax_cut, &self.markers, &self.info, &self.data.data).expect("Removal failed")
-            }
-
-            ui.heading("Filter settings");
-            egui::ComboBox::from_label("Highpass filter lfreq")
-                .selected_text(format!("{:?}", self.lfreq))
-                .show_ui(ui, |ui| {
-                    ui.selectable_value(&mut self.lfreq, 0.1, "0.1");
-                    ui.selectable_value(&mut self.lfreq, 0.2, "0.2");
-                    ui.selectable_value(&mut self.lfreq, 0.3, "0.3");
-                    ui.selectable_value(&mut self.lfreq, 0.5, "0.5");
-                    ui.selectable_value(&mut self.lfreq, 1.0, "1.0");
-                    ui.selectable_value(&mut self.lfreq, 2.0, "2.0");
-                }
-            );
-
-            egui::ComboBox::from_label("Lowpass filter hfreq")
-                .selected_text(format!("{:?}", self.hfreq))
-                .show_ui(ui, |ui| {
-                    ui.selectable_value(&mut self.hfreq, 30.0, "30");
-                    ui.selectable_value(&mut self.hfreq, 40.0, "40");
-                    ui.selectable_value(&mut self.hfreq, 45.0, "45");
-                    ui.selectable_value(&mut self.hfreq, 50.0, "50");
-                    ui.selectable_value(&mut self.hfreq, 70.0, "70");
-                    ui.selectable_value(&mut self.hfreq, 100.0, "100");
-                }
-            );
-
-            if ui.button("Filter data").clicked(){
-                self.data.data = signal::hp_filter(self.lfreq, &self.info, &self.data.data).expect("Highpass filtering failed");
-                self.data.data = signal::lp_filter(self.hfreq, &self.info, &self.data.data).expect("Lowpass filtering failed");
-            }
-
-            ui.heading("Resample data");
-            ui.collapsing("Warning!", |ui| { ui.label("Do not resample before removing TMS artefact and filtering data!"); });
-            egui::ComboBox::from_label("New sfreq")
-                .selected_text(format!("{:?}", self.n_sfreq))
-                .show_ui(ui, |ui| {
-                    ui.selectable_value(&mut self.n_sfreq, 100, "100 Hz");
-                    ui.selectable_value(&mut self.n_sfreq, 200, "200 Hz");
-                    ui.selectable_value(&mut self.n_sfreq, 250 , "250 Hz");
-                    ui.selectable_value(&mut self.n_sfreq, 725, "725 Hz");
-                    ui.selectable_value(&mut self.n_sfreq, 1000, "1000 Hz");
-                    ui.selectable_value(&mut self.n_sfreq, 2000, "2000 Hz");
-                }
-            );
-
-            if ui.button("Apply resampling").clicked(){
-                self.data.data = signal::resample_eeg(self.n_sfreq, &self.info, &self.data.data).expect("Resampling failed");
-                self.info.sfreq = self.n_sfreq as i32;
-
-            }
-    });
+            ui.heading("Controls");
+            self.y_scale_selector(ui);
+            self.amplitude_selector(ui);
+            self.decimation_selector(ui);
+            self.picks_combo(ui);
+            self.tms_controls(ui);
+            self.impedance_panel(ui);
+            self.filter_controls(ui);
+            self.resample_controls(ui);
+        });
     }
 }
 
@@ -1,59 +1,195 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
 use ndarray::{s, Array2, Array3, Axis};
 
 use crate::io::vec_to_ndarray;
-use crate::{EEGData, EEGInfo, Markers, EpochsData};
+use crate::{EEGInfo, Error, Markers, EpochsData, Picks};
 use crate::signal;
 
-
-pub fn epoch_eeg(
+/// Sample window of the epoch around every trigger, `None` for triggers whose epoch does not
+/// lie entirely inside the data or overlaps a bad segment of all channels or of a `picked`
+/// channel.
+fn epoch_windows<'a>(
     tmin: f64,
     tmax: f64,
     eeg_info: &EEGInfo,
-    eeg_data: &Array2<i16>,
-    markers: &Markers
-) -> Result<Array3<i16>, Box<dyn std::error::Error>> {
-    if eeg_data.is_empty() {
-        return Ok(Array3::from_shape_vec((0, 0, 0), Vec::new()).unwrap());
-    }
-    
-    let mut epochs: Vec<Vec<Vec<i16>>> = Vec::new();
-    
-    let mut data_copy = eeg_data.clone();
-    let n_samples = data_copy.ncols();
-
-    let min_samples = (tmin * eeg_info.sfreq as f64).round() as usize;
-    let max_samples = (tmax * eeg_info.sfreq as f64).round() as usize;
-    
-    for &marker_pos in &markers.markers {
-        let mut current_epoch: Vec<Vec<i16>> = Vec::new();
+    n_samples: usize,
+    markers: &'a Markers,
+    picked: &'a [usize],
+) -> impl Iterator<Item = Option<Range<usize>>> + 'a {
+    let min_samples = (tmin * eeg_info.sfreq).round() as usize;
+    let max_samples = (tmax * eeg_info.sfreq).round() as usize;
+    markers.markers.iter().map(move |&marker_pos| {
         let marker_idx = marker_pos.round() as usize;
-
-        let start_cut = marker_idx.saturating_sub(min_samples);
-        let end_cut = (marker_idx + max_samples).min(n_samples);
-        if start_cut >= end_cut {
-            continue; 
+        // Epochs reaching over either end of the recording would be shorter than the others
+        if marker_idx < min_samples || marker_idx + max_samples > n_samples {
+            return None;
         }
-        for ch_idx in 0..data_copy.nrows() {
-            let slice = data_copy.slice_mut(s![ch_idx, start_cut..end_cut]).to_vec();
-            current_epoch.push(slice);
+        let window = marker_idx - min_samples..marker_idx + max_samples;
+        (!window.is_empty() && !markers.is_bad(&window, picked)).then_some(window)
+    })
+}
+
+/// Sample times in seconds of epochs starting `tmin` seconds before the event.
+pub fn epoch_times(tmin: f64, n_times: usize, sfreq: f64) -> Vec<f64> {
+    let first = (tmin * sfreq).round();
+    (0..n_times).map(|idx| (idx as f64 - first) / sfreq).collect()
+}
+
+/// Code of every trigger description, numbered like MNE numbers `BrainVision` markers:
+/// `S  1` is 1 and `R  1` is 1001, other descriptions get codes from 10001 on in order of
+/// appearance.
+pub fn event_codes(markers: &Markers) -> BTreeMap<String, i64> {
+    let mut codes = BTreeMap::new();
+    let mut next_code = 10001;
+    for event in markers.events.iter().filter(|event| event.is_trigger()) {
+        if codes.contains_key(&event.description) {
+            continue;
         }
-        epochs.push(current_epoch);
+        let description = event.description.trim();
+        let number = description.get(1..).and_then(|number| number.trim().parse::<i64>().ok());
+        let code = match (description.chars().next(), number) {
+            (Some('S'), Some(number)) => number,
+            (Some('R'), Some(number)) => 1000 + number,
+            _ => {
+                next_code += 1;
+                next_code - 1
+            }
+        };
+        codes.insert(event.description.clone(), code);
+    }
+    codes
+}
+
+/// Event codes of named conditions, `conditions` maps a name to a trigger description like
+/// `S 1`, spaces do not matter. Without conditions every description is a condition of
+/// its own.
+pub fn event_id(conditions: &BTreeMap<String, String>, markers: &Markers) -> Result<BTreeMap<String, i64>, Error> {
+    let codes = event_codes(markers);
+    if conditions.is_empty() {
+        return Ok(codes.into_iter().map(|(description, code)| (description.trim().to_owned(), code)).collect());
     }
-    let data = signal::vec_to_ndarray3(epochs);
-    Ok(data)
+    let squeeze = |text: &str| text.split_whitespace().collect::<String>();
+    conditions
+        .iter()
+        .map(|(name, description)| {
+            codes
+                .iter()
+                .find(|(known, _)| squeeze(known) == squeeze(description))
+                .map(|(_, &code)| (name.clone(), code))
+                .ok_or_else(|| Error::NotFound(format!("No trigger {description:?} for condition {name:?}")))
+        })
+        .collect()
 }
 
+/// Reads an event id like `{"single": "S 1", "sici": "S 2"}`, given as JSON text or as the
+/// path of a JSON file.
+pub fn parse_event_id(event_id: &str) -> Result<BTreeMap<String, String>, Error> {
+    let text = if Path::new(event_id).is_file() {
+        fs::read_to_string(event_id).map_err(Error::io(event_id))?
+    } else {
+        event_id.to_owned()
+    };
+    serde_json::from_str(&text).map_err(|err| Error::InvalidParameter(format!("Invalid event id {event_id:?}: {err}")))
+}
+
+/// Cuts epochs x channels x samples around every trigger of a condition of `event_id`, the
+/// channels are the picked ones in the order of `Picks::names`. Epochs overlapping a bad
+/// segment are dropped.
+pub fn epoch_eeg(
+    tmin: f64,
+    tmax: f64,
+    eeg_info: &EEGInfo,
+    eeg_data: &Array2<f32>,
+    markers: &Markers,
+    picks: &Picks,
+    event_id: BTreeMap<String, i64>,
+) -> Result<EpochsData, Error> {
+    let ch_names = picks.names(eeg_info)?;
+    let picked = picks.indices(eeg_info)?;
+    let codes = event_codes(markers);
+    let n_samples = eeg_data.ncols();
+
+    let mut epochs: Vec<Vec<Vec<f32>>> = Vec::new();
+    let mut events: Vec<i64> = Vec::new();
+    let (mut previous, mut n_dropped) = (0, 0);
+    let triggers = markers.events.iter().filter(|event| event.is_trigger());
+    for (event, window) in triggers.zip(epoch_windows(tmin, tmax, eeg_info, n_samples, markers, &picked)) {
+        let code = codes[&event.description];
+        match window {
+            _ if !event_id.values().any(|&id| id == code) => {}
+            Some(window) if !eeg_data.is_empty() => {
+                epochs.push(picked.iter().map(|&ch_idx| eeg_data.slice(s![ch_idx, window.clone()]).to_vec()).collect());
+                events.extend([event.position as i64, previous, code]);
+            }
+            _ => n_dropped += 1,
+        }
+        previous = code;
+    }
+    if n_dropped > 0 {
+        println!("Dropped {n_dropped} epochs outside the data or overlapping bad segments");
+    }
+    let epochs = if epochs.is_empty() { Array3::zeros((0, ch_names.len(), 0)) } else { signal::vec_to_ndarray3(epochs)? };
+    let events = Array2::from_shape_vec((events.len() / 3, 3), events)?;
+    Ok(EpochsData { epochs, ch_names, tmin, tmax, events, event_id })
+}
 
 pub fn evoked_eeg(
     epochs: &EpochsData,
     eeg_info: &EEGInfo,
-) -> Result<Array2<f64>, Box<dyn std::error::Error>> {
+) -> Result<Array2<f64>, Error> {
     if epochs.epochs.is_empty() {
-        return Ok(Array2::from_shape_vec((0, 0), Vec::new()).unwrap());
+        return Ok(Array2::zeros((0, 0)));
     }
-    let default_evoked = Array2::from_shape_vec((0, 0), Vec::new()).unwrap();
+    let default_evoked = Array2::zeros((0, 0));
     let epochs_f64 = epochs.epochs.mapv(|i| i as f64);
     let evoked = epochs_f64.mean_axis(Axis(0)).unwrap_or(default_evoked);
     Ok(evoked)   
 }
     
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ndarray::Array2;
+
+    use super::epoch_eeg;
+    use crate::io::parse_header;
+    use crate::{Error, Marker, Markers, Picks};
+
+    const HEADER: &str = "Brain Vision Data Exchange Header File Version 1.0\n\n\
+        [Common Infos]\nDataFile=test.eeg\nMarkerFile=test.vmrk\nDataOrientation=MULTIPLEXED\n\
+        NumberOfChannels=2\nSamplingInterval=1000\n\n[Binary Infos]\nBinaryFormat=INT_16\n\n\
+        [Channel Infos]\nCh1=Fp1,,0.1,µV\nCh2=Cz,,0.1,µV\n";
+
+    fn stimulus(position: usize) -> Marker {
+        Marker {
+            kind: String::from("Stimulus"),
+            description: String::from("S  1"),
+            position,
+            duration: 1,
+            channel: 0,
+            date: None,
+        }
+    }
+
+    #[test]
+    fn drops_epochs_reaching_over_the_edges() -> Result<(), Error> {
+        let eeg_info = parse_header(&Some(HEADER.to_owned()))?;
+        let data = Array2::from_shape_fn((2, 1000), |(ch, idx)| (ch * 1000 + idx) as f32);
+        // 0.1 s before and 0.2 s after the event are 100 and 200 samples at 1000 Hz
+        let markers = Markers::from_events([50, 500, 800, 900].map(stimulus).to_vec());
+        let event_id = BTreeMap::from([(String::from("S  1"), 1)]);
+
+        let epochs = epoch_eeg(0.1, 0.2, &eeg_info, &data, &markers, &Picks::All, event_id)?;
+
+        assert_eq!(epochs.epochs.dim(), (2, 2, 300));
+        assert_eq!(epochs.events.column(0).to_vec(), vec![500, 800]);
+        assert_eq!(epochs.epochs[[1, 1, 0]], 1700.0);
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+// Errors
+//
+// Every reader, writer and processing function of the crate returns `Result<T, Error>`
+// instead of panicking, so a malformed header line or a bad filter frequency ends up in
+// an error message of the CLI or a dialog of the viewer rather than a crash.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum Error {
+    /// Opening, reading or writing a file failed, `path` is `None` when it is not known
+    Io { path: Option<PathBuf>, source: std::io::Error },
+    /// A field of a `.vhdr`/`.vmrk` or EDF header that could not be parsed,
+    /// `line` is the 1-based line of text headers
+    Header { line: Option<usize>, message: String },
+    /// A binary format, data orientation or file type that cannot be read or written
+    UnsupportedFormat(String),
+    /// Data whose size differs from what its header or the caller describes
+    SizeMismatch { what: String, expected: usize, found: usize },
+    /// A processing or export parameter outside of its valid range
+    InvalidParameter(String),
+    /// Malformed content of the other files: `NumPy` archives, BIDS sidecars, montages
+    Malformed(String),
+    /// A channel, array or recording that was asked for but does not exist
+    NotFound(String),
+}
+
+impl Error {
+    /// `map_err` adapter that attaches `path` to an I/O error.
+    pub fn io(path: impl AsRef<Path>) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.as_ref().to_path_buf();
+        move |source| Self::Io { path: Some(path), source }
+    }
+
+    pub fn header(line: usize, message: impl Into<String>) -> Self {
+        Self::Header { line: Some(line), message: message.into() }
+    }
+
+    pub fn size_mismatch(what: impl Into<String>, expected: usize, found: usize) -> Self {
+        Self::SizeMismatch { what: what.into(), expected, found }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path: Some(path), source } => write!(f, "{}: {source}", path.display()),
+            Self::Io { path: None, source } => write!(f, "{source}"),
+            Self::Header { line: Some(line), message } => write!(f, "Line {line}: {message}"),
+            Self::UnsupportedFormat(format) => write!(f, "Format not supported: {format}"),
+            Self::SizeMismatch { what, expected, found } => write!(f, "{what}: expected {expected}, found {found}"),
+            Self::Header { line: None, message }
+            | Self::InvalidParameter(message)
+            | Self::Malformed(message)
+            | Self::NotFound(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(source: std::io::Error) -> Self {
+        Self::Io { path: None, source }
+    }
+}
+
+impl From<ndarray::ShapeError> for Error {
+    fn from(err: ndarray::ShapeError) -> Self {
+        Self::Malformed(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Malformed(err.to_string())
+    }
+}
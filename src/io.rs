@@ -1,23 +1,39 @@
 use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::BufReader;
+use std::io::{BufReader, BufWriter};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 use ndarray::prelude::*;
 
-use crate::EEGInfo;
-use crate::Markers;
+use crate::{ChannelInfo, ChannelType, EEGData, EEGInfo, Error, Impedance, ImpedanceTable};
+use crate::{Marker, Markers};
+use crate::montage::Montage;
+
+pub mod bids;
+pub use bids::{BidsPath, read_bids, write_bids};
+pub mod edf;
+pub use edf::{parse_bdf, parse_edf, read_edf_selection, write_edf};
+pub mod mmap;
+pub use mmap::MappedEeg;
+pub mod npy;
+pub use npy::write_epochs_npz;
+pub mod select;
+pub use select::{Selection, read_brainvision_selection};
+pub mod table;
+pub use table::{TableLayout, write_epochs_table, write_evoked_table, write_raw_table};
+pub mod validate;
+pub use validate::validate_brainvision;
 
 //fn type_of<T>(_: T) -> &'static str {
 //    type_name::<T>()
 //}
 
-pub fn get_header(fpath: &Option<String>) -> Result<Option<String>, Box<dyn std::error::Error>> {
+pub fn get_header(fpath: &Option<String>) -> Result<Option<String>, Error> {
     match  fpath {
         Some(path) => {
-        let mut file = File::open(path)?;
-        let mut header = String::new();
-        file.read_to_string(&mut header)?;
+        let header = fs::read_to_string(path).map_err(Error::io(path))?;
         Ok(Some(header))
         }
 
@@ -26,110 +42,274 @@ pub fn get_header(fpath: &Option<String>) -> Result<Option<String>, Box<dyn std:
 
 }
 
-pub fn parse_header(header: &Option<String>) -> Result<EEGInfo, Box<dyn std::error::Error>> {
+/// A single `[Section]` of a `BrainVision` header or marker file.
+/// Every line keeps its 1-based line number so that errors can point at it.
+pub struct IniSection {
+    pub name: String,
+    pub lines: Vec<(usize, String)>,
+}
+
+/// Splits the INI-style `BrainVision` text files into their sections.
+/// Lines before the first section (the "Brain Vision Data Exchange ..." banner) are dropped.
+pub fn split_sections(content: &str) -> Vec<IniSection> {
+    let mut sections: Vec<IniSection> = Vec::new();
+    for (idx, raw_line) in content.lines().enumerate() {
+        let line = raw_line.trim_end_matches('\r');
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            sections.push(IniSection {
+                name: trimmed[1..trimmed.len() - 1].trim().to_owned(),
+                lines: Vec::new(),
+            });
+            continue;
+        }
+        if let Some(section) = sections.last_mut() {
+            section.lines.push((idx + 1, line.to_owned()));
+        }
+    }
+    sections
+}
+
+/// Returns `(key, value)` for a `Key=Value` line, ignoring empty and `;` comment lines.
+fn key_value(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with(';') {
+        return None;
+    }
+    trimmed
+        .split_once('=')
+        .map(|(key, value)| (key.trim(), value.trim()))
+}
+
+/// Parses the number in `Ch<number>` keys, returning a 0-based channel index.
+fn channel_index(key: &str, line_no: usize) -> Result<usize, Error> {
+    let number = key
+        .strip_prefix("Ch")
+        .and_then(|n| n.parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .ok_or_else(|| Error::header(line_no, format!("Invalid channel key {key:?}")))?;
+    Ok(number - 1)
+}
+
+fn parse_field<T: std::str::FromStr>(
+    value: &str,
+    field: &str,
+    line_no: usize,
+) -> Result<T, Error> {
+    value
+        .trim()
+        .parse::<T>()
+        .map_err(|_err| Error::header(line_no, format!("Invalid value {value:?} for {field}")))
+}
 
-    let header_content = match header {
-        Some(content) => content,
-        // Return a clear error if the header is needed but not provided.
-        None => return Err("Header content is missing and required for this operation.".into()),
+/// Parses `Ch1=<Name>,<Reference>,<Resolution>,<Unit>`.
+/// Empty resolution means 1.0 and a missing unit means µV, commas in names are coded as `\1`.
+fn parse_channel_info(value: &str, line_no: usize) -> Result<ChannelInfo, Error> {
+    let fields: Vec<&str> = value.split(',').collect();
+    let field = |i: usize| fields.get(i).map_or("", |f| f.trim());
+
+    let name = field(0).replace("\\1", ",");
+    if name.is_empty() {
+        return Err(Error::header(line_no, "Missing channel name"));
+    }
+    let resolution = match field(2) {
+        "" => 1.0,
+        res => parse_field::<f64>(res, "channel resolution", line_no)?,
     };
+    let unit = match field(3) {
+        "" => String::from("µV"),
+        unit => unit.to_owned(),
+    };
+
+    Ok(ChannelInfo {
+        kind: ChannelType::infer(&name, &unit),
+        name,
+        reference: field(1).replace("\\1", ","),
+        resolution,
+        unit,
+        coordinates: None,
+    })
+}
+
+/// Parses `Ch1=<Radius>,<Theta>,<Phi>`.
+fn parse_coordinates(value: &str, line_no: usize) -> Result<(f64, f64, f64), Error> {
+    let fields: Vec<&str> = value.split(',').collect();
+    if fields.len() < 3 {
+        return Err(Error::header(line_no, "Expected radius, theta and phi"));
+    }
+    Ok((
+        parse_field(fields[0], "radius", line_no)?,
+        parse_field(fields[1], "theta", line_no)?,
+        parse_field(fields[2], "phi", line_no)?,
+    ))
+}
+
+/// Checks that `[Channel Infos]` describes exactly `NumberOfChannels` channels
+/// and attaches the `[Coordinates]` entries to them.
+fn collect_channels(
+    num_ch: i32,
+    mut channels: BTreeMap<usize, ChannelInfo>,
+    coordinates: BTreeMap<usize, (f64, f64, f64)>,
+) -> Result<Vec<ChannelInfo>, Error> {
+    if num_ch <= 0 {
+        return Err(Error::Header { line: None, message: "NumberOfChannels is missing or zero in [Common Infos]".into() });
+    }
+    let num_ch = num_ch as usize;
+    if let Some(&ch_idx) = channels.keys().find(|&&ch_idx| ch_idx >= num_ch) {
+        return Err(Error::Header {
+            line: None,
+            message: format!("[Channel Infos] describes Ch{} but NumberOfChannels is {num_ch}", ch_idx + 1),
+        });
+    }
+    if channels.len() != num_ch {
+        return Err(Error::size_mismatch("Channels in [Channel Infos]", num_ch, channels.len()));
+    }
+    for (ch_idx, coords) in coordinates {
+        if let Some(channel) = channels.get_mut(&ch_idx) {
+            channel.coordinates = Some(coords);
+        }
+    }
+    Ok(channels.into_values().collect())
+}
+
+/// Parses the first impedance measurement of the recorder comment:
+///
+/// ```text
+/// Impedance [kOhm] at 10:46:13 :
+/// Fp1:          5
+/// EMG,R:      Out of Range!
+/// ```
+///
+/// The table ends at the first empty line.
+fn parse_impedances(comment: &str) -> Option<ImpedanceTable> {
+    let mut lines = comment.lines().map(str::trim).skip_while(|line| !line.starts_with("Impedance ["));
+    let title = lines.next()?;
+    let (unit, rest) = title.strip_prefix("Impedance [")?.split_once(']')?;
+    let time = rest.trim().trim_start_matches("at").trim_end_matches(':').trim();
+    let impedances = lines
+        .take_while(|line| !line.is_empty())
+        .filter(|line| !line.contains("Impedance Measurement Range"))
+        .filter_map(|line| {
+            let (name, value) = line.rsplit_once(':')?;
+            Some(Impedance { name: name.trim().to_owned(), value: value.trim().parse().ok() })
+        })
+        .collect();
+    Some(ImpedanceTable { unit: unit.to_owned(), time: time.to_owned(), impedances })
+}
 
-    let header_vec: Vec<String> = header_content.split("\n").map(|x| x.to_string()).collect();
+pub fn parse_header(header: &Option<String>) -> Result<EEGInfo, Error> {
+
+    // Return a clear error if the header is needed but not provided.
+    let Some(header_content) = header else {
+        return Err(Error::NotFound("Header content is missing and required for this operation.".into()));
+    };
 
     let mut eeg_info = EEGInfo {
         num_ch: 0,
         ch_namesx: Vec::new(),
         ch_names: Vec::new(),
-        sfreq: 0,
+        channels: Vec::new(),
+        sfreq: 0.0,
+        data_file: String::new(),
+        marker_file: String::new(),
+        codepage: String::new(),
         data_orientation: String::new(),
         binary_format: String::new(),
-        sampling_interval_in: String::new(),
-        sampling_interval: 0,
+        sampling_interval_in: String::from("microseconds"),
+        sampling_interval: 0.0,
+        comment: String::new(),
+        bads: Vec::new(),
+        montage: None,
+        impedances: None,
     };
-    //Prints the whole header
-    //header_vec.iter().for_each(|x| println!("Lines {:?}", x));
-
-    //println!("Header {:?}", header_vec);
-    let mut header_str = String::new();
-    header_vec.iter().for_each(|x| {
-        if x.contains("Sampling Rate [Hz]") {
-            header_str.push_str(x);
-            header_str.push_str("\n");
-            //println!("SFREQ: {}", &x[20..].replace("\r", ""));
-            eeg_info.sfreq = x[20..].replace("\r", "").parse::<i32>().unwrap();
-            println!("Sampling rate {:?}", eeg_info.sfreq);
-        }
-
-        if x.contains("Number of channels:") {
-            header_str.push_str(x);
-            header_str.push_str("\n");
-            //println!("N: {}", &x[20..]);
-            let n_ch = x[20..].replace("\r", "").parse::<i32>().unwrap(); // Should make this more
-            //solid
-            //println!("NCH: {}", n_ch);
-            eeg_info.num_ch = n_ch;
-
-            println!("Number of channels {:?}", n_ch);
 
-            for i in 0..n_ch {
-                eeg_info.ch_namesx.push(format!("Ch{}", i + 1));
+    let mut channels: BTreeMap<usize, ChannelInfo> = BTreeMap::new();
+    let mut coordinates: BTreeMap<usize, (f64, f64, f64)> = BTreeMap::new();
+    let mut comment_sfreq: Option<f64> = None;
+
+    for section in split_sections(header_content) {
+        match section.name.as_str() {
+            "Common Infos" => {
+                for (line_no, line) in &section.lines {
+                    if let Some(unit) = line.trim().strip_prefix("; Sampling interval in") {
+                        eeg_info.sampling_interval_in = unit.trim().to_owned();
+                    }
+                    let Some((key, value)) = key_value(line) else { continue };
+                    match key {
+                        "Codepage" => eeg_info.codepage = value.to_owned(),
+                        "DataFile" => eeg_info.data_file = value.to_owned(),
+                        "MarkerFile" => eeg_info.marker_file = value.to_owned(),
+                        "DataOrientation" => eeg_info.data_orientation = value.to_owned(),
+                        "NumberOfChannels" => {
+                            eeg_info.num_ch = parse_field(value, "NumberOfChannels", *line_no)?;
+                        }
+                        "SamplingInterval" => {
+                            eeg_info.sampling_interval = parse_field(value, "SamplingInterval", *line_no)?;
+                        }
+                        _ => {}
+                    }
+                }
             }
-            //println!("CHANNELSXX: {:?}", eeg_info.ch_namesx);
-        }
-
-        if x.contains("DataOrientation") {
-            header_str.push_str(x);
-            //header_str.push_str("\n");
-            //println!("SFREQ: {}", &x[20..].replace("\r", ""));
-            eeg_info.data_orientation = x.replace("\r", "");
-            println!("Data orientation {:?}", eeg_info.data_orientation);
-        }
-
-        if x.contains("BinaryFormat") {
-            header_str.push_str(x);
-            eeg_info.binary_format = x.replace("BinaryFormat=", "").replace("\r", "");
-            println!("Binary format {:?}", eeg_info.binary_format);
-        }
-
-        if x.contains("Sampling interval in") {
-            header_str.push_str(x);
-            eeg_info.sampling_interval_in = x.replace("Sampling interval in", "").replace("\r", "");
-        }
-
-        if x.contains("SamplingInterval") {
-            header_str.push_str(x);
-            eeg_info.sampling_interval = x
-                .replace("SamplingInterval=", "")
-                .replace("\r", "")
-                .parse::<i32>()
-                .unwrap();
+            "Binary Infos" => {
+                for (_, line) in &section.lines {
+                    if let Some(("BinaryFormat", value)) = key_value(line) {
+                        eeg_info.binary_format = value.to_owned();
+                    }
+                }
+            }
+            "Channel Infos" => {
+                for (line_no, line) in &section.lines {
+                    let Some((key, value)) = key_value(line) else { continue };
+                    let ch_idx = channel_index(key, *line_no)?;
+                    channels.insert(ch_idx, parse_channel_info(value, *line_no)?);
+                }
+            }
+            "Coordinates" => {
+                for (line_no, line) in &section.lines {
+                    let Some((key, value)) = key_value(line) else { continue };
+                    let ch_idx = channel_index(key, *line_no)?;
+                    coordinates.insert(ch_idx, parse_coordinates(value, *line_no)?);
+                }
+            }
+            "Comment" => {
+                for (line_no, line) in &section.lines {
+                    if let Some(rate) = line.trim().strip_prefix("Sampling Rate [Hz]:") {
+                        comment_sfreq = Some(parse_field(rate, "Sampling Rate [Hz]", *line_no)?);
+                    }
+                    eeg_info.comment.push_str(line);
+                    eeg_info.comment.push('\n');
+                }
+            }
+            _ => {}
         }
-    });
+    }
 
-    for x in header_vec.iter() {
+    eeg_info.channels = collect_channels(eeg_info.num_ch, channels, coordinates)?;
+    eeg_info.ch_names = eeg_info.channels.iter().map(|ch| ch.name.clone()).collect();
+    eeg_info.ch_namesx = (1..=eeg_info.channels.len()).map(|i| format!("Ch{i}")).collect();
+    eeg_info.montage = Montage::from_channels(&eeg_info);
+    eeg_info.impedances = parse_impedances(&eeg_info.comment);
+
+    // The interval is authoritative, older files only carry the rate in the recorder comment
+    eeg_info.sfreq = match comment_sfreq {
+        _ if eeg_info.sampling_interval > 0.0 => 1_000_000.0 / eeg_info.sampling_interval,
+        Some(sfreq) if sfreq > 0.0 => sfreq,
+        _ => return Err(Error::Header { line: None, message: "Could not determine the sampling rate from the header".into() }),
+    };
 
-        if x.contains("µV") {
-            //println!("{:?}", x);
-            eeg_info
-                .ch_names
-                .push(x.to_string().replace(",,0.1,µV", "").replace("Ch", ""));
-        }
-        if eeg_info.ch_names.len() == eeg_info.num_ch as usize{
-            break;
-        }
-        };
+    println!("Sampling rate {:?}", eeg_info.sfreq);
+    println!("Number of channels {:?}", eeg_info.num_ch);
+    println!("Data orientation {:?}", eeg_info.data_orientation);
+    println!("Binary format {:?}", eeg_info.binary_format);
 
-    //println!("Header: {:?}", header_str);
-    //println!("{:?}", eeg_info);
     Ok(eeg_info)
 }
 
-pub fn get_vmrk(fpath: &Option<String>) -> Result<Option<String>, Box<dyn std::error::Error>> {
+
+pub fn get_vmrk(fpath: &Option<String>) -> Result<Option<String>, Error> {
     match  fpath {
         Some(path) => {
-        let mut file = File::open(path)?;
-        let mut vmrk = String::new();
-        file.read_to_string(&mut vmrk)?;
+        let vmrk = fs::read_to_string(path).map_err(Error::io(path))?;
         Ok(Some(vmrk))
         }
 
@@ -138,113 +318,218 @@ pub fn get_vmrk(fpath: &Option<String>) -> Result<Option<String>, Box<dyn std::e
 
 }
 
+/// Data and marker files of a `BrainVision` recording, see `resolve_brainvision`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrainVisionFiles {
+    pub header: PathBuf,
+    pub data: PathBuf,
+    /// `None` when the recording has no marker file
+    pub markers: Option<PathBuf>,
+}
 
-pub fn parse_vmrk(vmrk: &Option<String>) -> Result<Markers, Box<dyn std::error::Error>> {
+/// Looks up the file a `DataFile=`/`MarkerFile=` entry names next to the header. Renamed
+/// recordings keep the old name in the entry, so the header's own name with `extension`
+/// and a case-insensitive match of either name are tried next. `Err` holds the paths tried.
+fn resolve_referenced(vhdr: &Path, name: &str, extension: &str) -> Result<PathBuf, Vec<PathBuf>> {
+    let mut candidates = Vec::new();
+    if !name.is_empty() {
+        candidates.push(vhdr.with_file_name(name));
+        // Some writers store the full path of the recording machine, possibly a Windows one
+        let file_name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+        candidates.push(vhdr.with_file_name(file_name));
+    }
+    candidates.push(vhdr.with_extension(extension));
+    candidates.dedup();
+    if let Some(found) = candidates.iter().find(|path| path.is_file()) {
+        return Ok(found.clone());
+    }
 
-    let vmrk_content = match vmrk {
-        Some(content) => content,
-        None => return Err(".VMRK content is missing and required for this operation.".into()),
-    };
-    let vmrk_vec: Vec<String> = vmrk_content.split("\n").map(|x| x.to_string()).collect();
-    let mut markers = Markers {
-        n_markers: 0,
-        markers: Vec::new()
+    let wanted: Vec<String> = candidates
+        .iter()
+        .filter_map(|path| path.file_name())
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .collect();
+    let dir = vhdr.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.is_file() && path.file_name().is_some_and(|name| wanted.contains(&name.to_string_lossy().to_lowercase())))
+        .ok_or(candidates)
+}
+
+/// Resolves the data and marker files of the header `vhdr_path` from its `DataFile=` and
+/// `MarkerFile=` entries, relative to the header.
+///
+/// A missing data file is an error, a missing marker file leaves `markers` empty.
+pub fn resolve_brainvision(vhdr_path: &str) -> Result<BrainVisionFiles, Error> {
+    let Some(header) = get_header(&Some(vhdr_path.to_owned()))? else {
+        return Err(Error::NotFound(format!("{vhdr_path} has no content")));
     };
-    let mut marker_vec: Vec<f64> = Vec::new();
-    vmrk_vec.iter().for_each(|x| {
-        if x.contains("R128") {
-        let chars: Vec<&str> = x.split(",").collect();
-        //println!("CHARS: {:?}", chars[2]);
-        let default: f64 = 0.0;
-        marker_vec.push(chars[2].parse::<f64>().unwrap_or(default));
+    let (mut data_file, mut marker_file) = (String::new(), String::new());
+    for section in split_sections(&header).iter().filter(|section| section.name == "Common Infos") {
+        for (_, line) in &section.lines {
+            match key_value(line) {
+                Some(("DataFile", value)) => data_file = value.to_owned(),
+                Some(("MarkerFile", value)) => marker_file = value.to_owned(),
+                _ => {}
+            }
         }
-});
-    //println!("MARKERS {:?}", marker_vec);
-    markers.markers = marker_vec.clone();
-    markers.n_markers = marker_vec.len();
-    Ok(markers)
-}
+    }
 
+    let vhdr = Path::new(vhdr_path);
+    let data = resolve_referenced(vhdr, &data_file, "eeg").map_err(|tried| {
+        let tried: Vec<String> = tried.iter().map(|path| path.display().to_string()).collect();
+        Error::NotFound(format!("Data file of {vhdr_path} not found, tried {}", tried.join(", ")))
+    })?;
+    let markers = resolve_referenced(vhdr, &marker_file, "vmrk").ok();
 
-pub fn parse_bytes_opt(path: &str, eeg_info: &EEGInfo) -> Result<Vec<Vec<i16>>, Box<dyn std::error::Error>> {
-    match eeg_info.binary_format.as_str() {
-        "INT_16" => {
-            let file = File::open(path)?;
-            let metadata = file.metadata()?;
-            let file_size = metadata.len() as usize;
+    if vhdr.with_file_name(&data_file) != data {
+        println!("DataFile={data_file} not found, using {}", data.display());
+    }
+    match &markers {
+        Some(path) if vhdr.with_file_name(&marker_file) != *path => {
+            println!("MarkerFile={marker_file} not found, using {}", path.display());
+        }
+        None if !marker_file.is_empty() => println!("MarkerFile={marker_file} not found, reading without markers"),
+        _ => {}
+    }
 
-            // Pre-calculate sizes
-            let total_samples = file_size / 2; // 2 bytes per i16
-            let samples_per_channel = total_samples / eeg_info.num_ch as usize;
+    Ok(BrainVisionFiles { header: vhdr.to_path_buf(), data, markers })
+}
 
-            // Pre-allocate all channel vectors
-            let mut channels: Vec<Vec<i16>> = vec![Vec::with_capacity(samples_per_channel); eeg_info.num_ch as usize];
+/// Reads a `BrainVision` recording given only its `.vhdr`, see `resolve_brainvision`.
+pub fn read_brainvision(vhdr_path: &str) -> Result<(EEGInfo, EEGData, Markers), Error> {
+    let files = resolve_brainvision(vhdr_path)?;
+    let eeg_info = parse_header(&get_header(&Some(vhdr_path.to_owned()))?)?;
+    let samples = parse_bytes(&files.data.to_string_lossy(), &eeg_info)?;
+    let data = vec_to_ndarray(split_channels(samples, &eeg_info)?)?;
+    let markers = match &files.markers {
+        Some(path) => parse_vmrk(&get_vmrk(&Some(path.to_string_lossy().into_owned()))?)?,
+        None => Markers { n_markers: 0, markers: Vec::new(), events: Vec::new() },
+    };
+    Ok((eeg_info, EEGData { data }, markers))
+}
 
-            let mut reader = BufReader::new(file);
-            let mut buffer = vec![0u8; 8192]; // 4KB chunks
-            let mut byte_pair = [0u8; 2];
-            let mut channel_idx = 0;
 
-            loop {
-                let bytes_read = reader.read(&mut buffer)?;
-                if bytes_read == 0 { break; }
+/// Parses `Mk<n>=<Type>,<Description>,<Position>,<Size>,<Channel>[,<Date>]`.
+/// Positions in the file are 1-based data points and are stored 0-based.
+fn parse_marker(value: &str, line_no: usize) -> Result<Marker, Error> {
+    let fields: Vec<&str> = value.split(',').collect();
+    if fields.len() < 3 {
+        return Err(Error::header(line_no, "Expected at least type, description and position"));
+    }
+    let field = |i: usize| fields.get(i).map_or("", |f| f.trim());
 
-                // Process in pairs of bytes
-                for chunk in buffer[..bytes_read].chunks_exact(2) {
-                    byte_pair.copy_from_slice(chunk);
-                    let sample = i16::from_le_bytes(byte_pair);
+    let position: usize = parse_field(field(2), "marker position", line_no)?;
+    let duration = match field(3) {
+        "" => 1,
+        size => parse_field(size, "marker size", line_no)?,
+    };
+    let channel = match field(4) {
+        "" => 0,
+        channel => parse_field(channel, "marker channel", line_no)?,
+    };
+    let date = Some(field(5)).filter(|date| !date.is_empty()).map(str::to_owned);
+
+    Ok(Marker {
+        kind: fields[0].trim().replace("\\1", ","),
+        // Descriptions like "S  1" are padded on purpose, keep them as written
+        description: fields[1].replace("\\1", ","),
+        position: position.saturating_sub(1),
+        duration,
+        channel,
+        date,
+    })
+}
 
-                    channels[channel_idx].push(sample);
-                    channel_idx = (channel_idx + 1) % eeg_info.num_ch as usize;
-                }
-            }
+pub fn parse_vmrk(vmrk: &Option<String>) -> Result<Markers, Error> {
 
-            Ok(channels)
+    let Some(vmrk_content) = vmrk else {
+        return Err(Error::NotFound(".VMRK content is missing and required for this operation.".into()));
+    };
+
+    let mut events: Vec<Marker> = Vec::new();
+    for section in split_sections(vmrk_content) {
+        if section.name != "Marker Infos" {
+            continue;
+        }
+        for (line_no, line) in &section.lines {
+            let Some((key, value)) = key_value(line) else { continue };
+            if !key.starts_with("Mk") {
+                continue;
+            }
+            events.push(parse_marker(value, *line_no)?);
         }
-        _ => Err("Format not supported".into()),
     }
+
+    // Stimulus and response triggers are the time locking events for pulse removal and epoching
+    Ok(Markers::from_events(events))
 }
 
 
-pub fn parse_bytes(path: &str, eeg_info: &EEGInfo) -> Result<Vec<i16>, Box<dyn std::error::Error>> {
-    match eeg_info.binary_format.as_str() {
-        "INT_16" => {
-            let f = File::open(path).expect("could not open file");
-            let mut reader: BufReader<File> = BufReader::new(f);
-            let mut buffer = Vec::new();
-            // Read file into vector.
-            reader
-                .read_to_end(&mut buffer)
-                .expect("error while reading file");
+/// Number of bytes of one sample for a `BinaryFormat` of the header.
+pub fn bytes_per_sample(binary_format: &str) -> Result<usize, Error> {
+    match binary_format {
+        "INT_16" => Ok(2),
+        "INT_32" | "IEEE_FLOAT_32" => Ok(4),
+        _ => Err(Error::UnsupportedFormat(binary_format.to_owned())),
+    }
+}
 
-            let bytes = buffer.to_vec();
-            let mut samples: Vec<i16> = Vec::new();
-            for pair in bytes.chunks_exact(2) {
-                let p = pair.try_into().unwrap();
-                samples.push(i16::from_le_bytes(p));
-            }
+/// Turns one little endian sample of `bytes_per_sample` bytes into `f32`.
+type SampleDecoder = fn(&[u8]) -> f32;
 
-            //print!("Samples {:?}", samples.len());
-            Ok(samples)
-        }
-        _ => Err("Format not supported".into()),
+fn sample_decoder(binary_format: &str) -> Result<SampleDecoder, Error> {
+    match binary_format {
+        "INT_16" => Ok(|b: &[u8]| f32::from(i16::from_le_bytes([b[0], b[1]]))),
+        "INT_32" => Ok(|b: &[u8]| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32),
+        "IEEE_FLOAT_32" => Ok(|b: &[u8]| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        _ => Err(Error::UnsupportedFormat(binary_format.to_owned())),
+    }
+}
+
+/// `true` for `DataOrientation=VECTORIZED` (all samples of channel 1, then channel 2 ...).
+/// A missing orientation means `MULTIPLEXED`, the only other value `BrainVision` writes.
+pub fn is_vectorized(eeg_info: &EEGInfo) -> Result<bool, Error> {
+    match eeg_info.data_orientation.as_str() {
+        "MULTIPLEXED" | "" => Ok(false),
+        "VECTORIZED" => Ok(true),
+        other => Err(Error::UnsupportedFormat(format!("data orientation {other}"))),
     }
 }
 
+pub fn parse_bytes(path: &str, eeg_info: &EEGInfo) -> Result<Vec<f32>, Error> {
+    let sample_size = bytes_per_sample(&eeg_info.binary_format)?;
+    let decode = sample_decoder(&eeg_info.binary_format)?;
+
+    let f = File::open(path).map_err(Error::io(path))?;
+    let mut reader: BufReader<File> = BufReader::new(f);
+    let mut buffer = Vec::new();
+    // Read file into vector.
+    reader.read_to_end(&mut buffer).map_err(Error::io(path))?;
+
+    let samples: Vec<f32> = buffer.chunks_exact(sample_size).map(decode).collect();
+
+    //print!("Samples {:?}", samples.len());
+    Ok(samples)
+}
+
 pub fn convert_to_seconds(
-    samples: Vec<i16>,
+    samples: &[f32],
     eeg_info: &EEGInfo,
-) -> Result<Vec<Vec<i16>>, Box<dyn std::error::Error>> {
+) -> Result<Vec<Vec<f32>>, Error> {
     let mut step: i32 = 0; // initialise a samples step counter
-    let mut seconds: Vec<Vec<i16>> = Vec::new(); // create vector of vectors for seconds
+    let mut seconds: Vec<Vec<f32>> = Vec::new(); // create vector of vectors for seconds
     seconds.push(Vec::new()); // Initialise the first vector
 
     let mut n = 0; // initialise second counter
 
-    for s in samples.iter() {
+    for s in samples {
         seconds[n].push(*s);
         step += 1;
-        if step % (eeg_info.sfreq * eeg_info.num_ch) == 0 {
+        if step % ((eeg_info.sfreq.round() as i32).max(1) * eeg_info.num_ch) == 0 {
             n += 1;
             seconds.push(Vec::new());
         }
@@ -254,12 +539,12 @@ pub fn convert_to_seconds(
 
 
 pub fn demultiplex(
-    seconds: Vec<Vec<i16>>,
+    seconds: Vec<Vec<f32>>,
     eeg_info: &EEGInfo,
-) -> Result<Vec<Vec<i16>>, Box<dyn std::error::Error>> {
-    let mut channels: Vec<Vec<i16>> = vec![Vec::new(); eeg_info.num_ch as usize];
+) -> Result<Vec<Vec<f32>>, Error> {
+    let mut channels: Vec<Vec<f32>> = vec![Vec::new(); eeg_info.num_ch as usize];
     if eeg_info.num_ch as usize == 0 {
-        return Err("Number of channels cannot be zero".into());
+        return Err(Error::InvalidParameter("Number of channels cannot be zero".into()));
     }
     for second in seconds {
         let mut counter = 0;
@@ -272,64 +557,355 @@ pub fn demultiplex(
     Ok(channels)
 }
 
-pub fn vec_to_array<T, const N: usize>(v: Vec<T>) -> [T; N] {
-    v.try_into()
-        .unwrap_or_else(|v: Vec<T>| panic!("Expected a Vec of length {} but it was {}", N, v.len()))
+/// Splits a `VECTORIZED` sample stream into `num_ch` equally long channels.
+pub fn devectorize(
+    samples: &[f32],
+    eeg_info: &EEGInfo,
+) -> Result<Vec<Vec<f32>>, Error> {
+    if eeg_info.num_ch <= 0 {
+        return Err(Error::InvalidParameter("Number of channels cannot be zero".into()));
+    }
+    let samples_per_channel = samples.len() / eeg_info.num_ch as usize;
+    if samples_per_channel == 0 {
+        return Ok(vec![Vec::new(); eeg_info.num_ch as usize]);
+    }
+    let channels = samples
+        .chunks_exact(samples_per_channel)
+        .take(eeg_info.num_ch as usize)
+        .map(<[f32]>::to_vec)
+        .collect();
+    Ok(channels)
 }
 
-pub fn vec_to_ndarray<T: Clone>(v: Vec<Vec<T>>) -> Array2<T> {
-    if v.is_empty() {
-        return Array2::from_shape_vec((0, 0), Vec::new()).unwrap();
-    }
-    let nrows = v.len();
-    let ncols = v[0].len();
-    let mut data = Vec::with_capacity(nrows * ncols);
-    for row in &v {
-        assert_eq!(row.len(), ncols);
-        data.extend_from_slice(&row);
+/// Splits the flat sample stream of an `.eeg` file into channels according to the
+/// data orientation of the header and calibrates the raw counts to the unit of every
+/// channel (count times resolution).
+///
+/// A partial sample frame at the end of the file is ignored, like the memory-mapped
+/// reader and the validation report do.
+pub fn split_channels(
+    mut samples: Vec<f32>,
+    eeg_info: &EEGInfo,
+) -> Result<Vec<Vec<f32>>, Error> {
+    let n_channels = usize::try_from(eeg_info.num_ch).unwrap_or(0).max(1);
+    samples.truncate(samples.len() / n_channels * n_channels);
+    let mut channels = if is_vectorized(eeg_info)? {
+        devectorize(&samples, eeg_info)?
+    } else {
+        let seconds = convert_to_seconds(&samples, eeg_info)?;
+        demultiplex(seconds, eeg_info)?
+    };
+    for (channel, resolution) in channels.iter_mut().zip(channel_resolutions(eeg_info)) {
+        channel.iter_mut().for_each(|sample| *sample *= resolution);
     }
-    Array2::from_shape_vec((nrows, ncols), data).unwrap()
+    Ok(channels)
 }
 
+/// Resolution of every channel, the factor from raw counts to its unit.
+pub fn channel_resolutions(eeg_info: &EEGInfo) -> Vec<f32> {
+    eeg_info.channels.iter().map(|channel| channel.resolution as f32).collect()
+}
 
-// EDF
+/// Escapes commas in names and descriptions the way `BrainVision` does.
+fn escape_field(field: &str) -> String {
+    field.replace(',', "\\1")
+}
 
+/// Encodes one raw count for `BinaryFormat`, integer formats are rounded and clamped.
+fn encode_sample(binary_format: &str, sample: f32, out: &mut Vec<u8>) {
+    match binary_format {
+        "INT_16" => out.extend_from_slice(&(sample.round().clamp(f32::from(i16::MIN), f32::from(i16::MAX)) as i16).to_le_bytes()),
+        "INT_32" => out.extend_from_slice(&(f64::from(sample).round().clamp(f64::from(i32::MIN), f64::from(i32::MAX)) as i32).to_le_bytes()),
+        _ => out.extend_from_slice(&sample.to_le_bytes()),
+    }
+}
 
-pub fn parse_edf(path: &str ) -> Result<Vec<i16>, Box<dyn std::error::Error>> {
+fn brainvision_vhdr(eeg_info: &EEGInfo, data_file: &str, marker_file: &str, binary_format: &str) -> String {
+    let sfreq = eeg_info.sfreq;
+    let mut vhdr = String::from("Brain Vision Data Exchange Header File Version 1.0\n; Data written by reegui\n\n");
+    vhdr.push_str(&format!(
+        "[Common Infos]\nCodepage=UTF-8\nDataFile={data_file}\nMarkerFile={marker_file}\nDataFormat=BINARY\n\
+         ; Data orientation: MULTIPLEXED=ch1,pt1, ch2,pt1 ...\nDataOrientation=MULTIPLEXED\n\
+         NumberOfChannels={}\n; Sampling interval in microseconds\nSamplingInterval={}\n\n",
+        eeg_info.channels.len(),
+        1_000_000.0 / sfreq
+    ));
+    vhdr.push_str(&format!("[Binary Infos]\nBinaryFormat={binary_format}\n\n"));
+
+    vhdr.push_str("[Channel Infos]\n; Each entry: Ch<Channel number>=<Name>,<Reference channel name>,\n");
+    vhdr.push_str("; <Resolution in \"Unit\">,<Unit>, Future extensions..\n");
+    vhdr.push_str("; Fields are delimited by commas, some fields might be omitted (empty).\n");
+    vhdr.push_str("; Commas in channel names are coded as \"\\1\".\n");
+    for (idx, channel) in eeg_info.channels.iter().enumerate() {
+        vhdr.push_str(&format!(
+            "Ch{}={},{},{},{}\n",
+            idx + 1,
+            escape_field(&channel.name),
+            escape_field(&channel.reference),
+            channel.resolution,
+            channel.unit
+        ));
+    }
 
-            let mut file = File::open(path).expect("could not open file");
+    if eeg_info.channels.iter().any(|ch| ch.coordinates.is_some()) {
+        vhdr.push_str("\n[Coordinates]\n");
+        for (idx, channel) in eeg_info.channels.iter().enumerate() {
+            let (radius, theta, phi) = channel.coordinates.unwrap_or((0.0, 0.0, 0.0));
+            vhdr.push_str(&format!("Ch{}={radius},{theta},{phi}\n", idx + 1));
+        }
+    }
 
-            let metadata = fs::metadata(path)?;
-            println!("METADATA {:?}", metadata);
+    // Keep the recorder comment but make the rate lines describe the written data
+    vhdr.push_str(&format!("\n[Comment]\nSampling Rate [Hz]: {sfreq}\nSampling Interval [µS]: {}\n", 1_000_000.0 / sfreq));
+    for line in eeg_info.comment.lines() {
+        let trimmed = line.trim();
+        if !trimmed.starts_with("Sampling Rate [Hz]") && !trimmed.starts_with("Sampling Interval [µS]") {
+            vhdr.push_str(line);
+            vhdr.push('\n');
+        }
+    }
+    vhdr
+}
 
-            let mut reader = BufReader::new(file);
-            // The first 256 bytes of an EDF file contain the main header in ASCII.
-            let mut header_bytes = vec![0u8; 256];
-            // Read exactly 256 bytes into the buffer.
-            reader.read_exact(&mut header_bytes)?;
-            // Convert the ASCII bytes to a String.
-            // Using from_utf8_lossy is safe in case of any non-ASCII characters.
-            let header_str = String::from_utf8_lossy(&header_bytes).to_string();
+fn brainvision_vmrk(markers: &Markers, data_file: &str) -> String {
+    let mut vmrk = String::from("Brain Vision Data Exchange Marker File, Version 1.0\n\n");
+    vmrk.push_str(&format!("[Common Infos]\nCodepage=UTF-8\nDataFile={data_file}\n\n"));
+    vmrk.push_str("[Marker Infos]\n; Each entry: Mk<Marker number>=<Type>,<Description>,<Position in data points>,\n");
+    vmrk.push_str("; <Size in data points>, <Channel number (0 = marker is related to all channels)>\n");
+    vmrk.push_str("; Fields are delimited by commas, some fields might be omitted (empty).\n");
+    vmrk.push_str("; Commas in type or description text are coded as \"\\1\".\n");
+    for (idx, event) in markers.events.iter().enumerate() {
+        vmrk.push_str(&format!(
+            "Mk{}={},{},{},{},{}",
+            idx + 1,
+            escape_field(&event.kind),
+            escape_field(&event.description),
+            event.position + 1,
+            event.duration,
+            event.channel
+        ));
+        if let Some(date) = &event.date {
+            vmrk.push(',');
+            vmrk.push_str(date);
+        }
+        vmrk.push('\n');
+    }
+    vmrk
+}
 
-            println!("\n HEADER {:?}", header_str);
+/// Writes a `BrainVision` `.vhdr`/`.vmrk`/`.eeg` triplet, the samples can be appended
+/// block by block so a recording never has to be in memory at once.
+pub struct BrainVisionWriter {
+    writer: BufWriter<File>,
+    binary_format: String,
+    n_channels: usize,
+    /// Inverse of the channel resolutions, physical value times scale is the raw count
+    scales: Vec<f32>,
+    frame: Vec<u8>,
+    summary: String,
+}
 
-            let f = File::open(path).expect("could not open file");
-            let mut reader: BufReader<File> = BufReader::new(f);
-            let mut buffer = Vec::new();
-            // Read file into vector.
-            reader
-                .read_to_end(&mut buffer)
-                .expect("error while reading file");
+impl BrainVisionWriter {
+    /// Writes the header and marker files next to `vhdr_path` and creates the empty `.eeg`.
+    ///
+    /// The samples are written multiplexed in `binary_format` (`INT_16`, `INT_32` or
+    /// `IEEE_FLOAT_32`), converted from the unit of every channel back to raw counts with the
+    /// channel resolutions of `eeg_info`. Marker positions must already match the written
+    /// data, see `signal::resample_markers`.
+    pub fn create(
+        vhdr_path: &str,
+        eeg_info: &EEGInfo,
+        markers: &Markers,
+        binary_format: &str,
+    ) -> Result<Self, Error> {
+        bytes_per_sample(binary_format)?;
+        if eeg_info.sfreq <= 0.0 {
+            return Err(Error::InvalidParameter("Sampling rate must be positive to write a header".into()));
+        }
 
-            println!("DATA {:?}", buffer.len());
+        let vhdr = Path::new(vhdr_path);
+        let stem = vhdr
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .ok_or_else(|| Error::InvalidParameter(format!("Invalid header path {vhdr_path}")))?;
+        let data_file = format!("{stem}.eeg");
+        let marker_file = format!("{stem}.vmrk");
+
+        fs::write(vhdr, brainvision_vhdr(eeg_info, &data_file, &marker_file, binary_format)).map_err(Error::io(vhdr))?;
+        let vmrk = vhdr.with_file_name(&marker_file);
+        fs::write(&vmrk, brainvision_vmrk(markers, &data_file)).map_err(Error::io(&vmrk))?;
+        let eeg = vhdr.with_file_name(&data_file);
+
+        Ok(Self {
+            writer: BufWriter::new(File::create(&eeg).map_err(Error::io(&eeg))?),
+            binary_format: binary_format.to_owned(),
+            n_channels: eeg_info.channels.len(),
+            scales: channel_resolutions(eeg_info).iter().map(|&resolution| 1.0 / resolution).collect(),
+            frame: Vec::with_capacity(eeg_info.channels.len() * 4),
+            summary: format!("Wrote {vhdr_path}, {data_file} and {marker_file}"),
+        })
+    }
 
-            let bytes = buffer.to_vec();
-            let mut samples: Vec<i16> = Vec::new();
-            for pair in bytes.chunks_exact(2) {
-                let p = pair.try_into().unwrap();
-                samples.push(i16::from_le_bytes(p));
+    /// Appends a channels x samples block.
+    pub fn write_block(&mut self, data: &Array2<f32>) -> Result<(), Error> {
+        if data.nrows() != self.n_channels {
+            return Err(Error::size_mismatch("Channels of the written block", self.n_channels, data.nrows()));
+        }
+        for column in data.columns() {
+            self.frame.clear();
+            for (&sample, &scale) in column.iter().zip(&self.scales) {
+                encode_sample(&self.binary_format, sample * scale, &mut self.frame);
             }
+            self.writer.write_all(&self.frame)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        println!("{}", self.summary);
+        Ok(())
+    }
+}
+
+/// Writes `data` as a `BrainVision` `.vhdr`/`.vmrk`/`.eeg` triplet next to `vhdr_path`,
+/// see [`BrainVisionWriter::create`].
+pub fn write_brainvision(
+    vhdr_path: &str,
+    eeg_info: &EEGInfo,
+    data: &Array2<f32>,
+    markers: &Markers,
+    binary_format: &str,
+) -> Result<(), Error> {
+    if data.nrows() != eeg_info.channels.len() {
+        return Err(Error::size_mismatch("Channels of the written data", eeg_info.channels.len(), data.nrows()));
+    }
+    let mut writer = BrainVisionWriter::create(vhdr_path, eeg_info, markers, binary_format)?;
+    writer.write_block(data)?;
+    writer.finish()
+}
 
-            //print!("Samples {:?}", samples.len());
-            Ok(samples)
+pub fn vec_to_array<T, const N: usize>(v: Vec<T>) -> Result<[T; N], Error> {
+    v.try_into()
+        .map_err(|v: Vec<T>| Error::size_mismatch("Vector length", N, v.len()))
+}
+
+/// Stacks equally long rows into a rows x columns array.
+pub fn vec_to_ndarray<T: Clone>(v: Vec<Vec<T>>) -> Result<Array2<T>, Error> {
+    let nrows = v.len();
+    let ncols = v.first().map_or(0, Vec::len);
+    let mut data = Vec::with_capacity(nrows * ncols);
+    for row in &v {
+        if row.len() != ncols {
+            return Err(Error::size_mismatch("Samples per channel", ncols, row.len()));
         }
+        data.extend_from_slice(row);
+    }
+    Ok(Array2::from_shape_vec((nrows, ncols), data)?)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use ndarray::Array2;
+
+    use super::{parse_header, parse_vmrk, read_brainvision, write_brainvision};
+    use crate::{Error, Marker, Markers};
+
+    pub(crate) const HEADER: &str = "Brain Vision Data Exchange Header File Version 1.0\n\
+        ; Data created by the Vision Recorder\n\n\
+        [Common Infos]\r\nCodepage=UTF-8\r\nDataFile=test.eeg\nMarkerFile=test.vmrk\n\
+        DataOrientation=MULTIPLEXED\nNumberOfChannels=3\n; Sampling interval in microseconds\n\
+        SamplingInterval=1000\n\n[Binary Infos]\nBinaryFormat=INT_16\n\n\
+        [Channel Infos]\n; Each entry: Ch<Channel number>=<Name>,<Reference channel name>,\n\
+        Ch1=Fp1,,0.1,µV\nCh3=EMG\\1R,,0.5,µV\nCh2=Cz,,,\n\n\
+        [Coordinates]\nCh1=1,-90,-72\n\n\
+        [Comment]\nImpedance [kOhm] at 10:46:13 :\nFp1:          5\nCz:           12\n\
+        EMG,R:      Out of Range!\n\n";
+
+    const VMRK: &str = "Brain Vision Data Exchange Marker File, Version 1.0\n\n\
+        [Common Infos]\nDataFile=test.eeg\n\n\
+        [Marker Infos]\n; Each entry: Mk<Marker number>=<Type>,<Description>,<Position in data points>,\n\
+        Mk1=New Segment,,1,1,0,20200101000000000000\n\
+        Mk2=Stimulus,S  1,101,1,0\n\
+        Mk3=Comment,bad\\1movement,201,50,2\n";
+
+    /// Path in the temporary directory, unique to the test process.
+    pub(crate) fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("reegui-{}-{name}", std::process::id())).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn parses_the_vhdr_sections() -> Result<(), Error> {
+        let eeg_info = parse_header(&Some(HEADER.to_owned()))?;
+
+        assert_eq!(eeg_info.sfreq, 1000.0);
+        assert_eq!(eeg_info.binary_format, "INT_16");
+        assert_eq!(eeg_info.data_orientation, "MULTIPLEXED");
+        assert_eq!(eeg_info.ch_names, ["Fp1", "Cz", "EMG,R"]);
+        assert_eq!(eeg_info.channels[1].resolution, 1.0);
+        assert_eq!(eeg_info.channels[2].resolution, 0.5);
+        assert_eq!(eeg_info.channels[0].coordinates, Some((1.0, -90.0, -72.0)));
+        let impedances = eeg_info.impedances.ok_or_else(|| Error::NotFound("No impedances parsed".into()))?;
+        assert_eq!(impedances.time, "10:46:13");
+        assert_eq!(impedances.impedances.iter().map(|impedance| impedance.value).collect::<Vec<_>>(), [Some(5.0), Some(12.0), None]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_channels_beyond_the_channel_count() {
+        let header = HEADER.replace("NumberOfChannels=3", "NumberOfChannels=2");
+        assert!(matches!(parse_header(&Some(header)), Err(Error::Header { .. })));
+    }
+
+    #[test]
+    fn parses_the_vmrk_markers() -> Result<(), Error> {
+        let markers = parse_vmrk(&Some(VMRK.to_owned()))?;
+
+        assert_eq!(markers.events.len(), 3);
+        assert_eq!(markers.events[0].date.as_deref(), Some("20200101000000000000"));
+        assert_eq!(markers.events[1].description, "S  1");
+        assert_eq!(markers.events[1].position, 100);
+        let comment = &markers.events[2];
+        assert_eq!((comment.description.as_str(), comment.position, comment.duration, comment.channel), ("bad,movement", 200, 50, 2));
+        assert_eq!(markers.markers, [100.0]);
+        Ok(())
+    }
+
+    /// Writes three channels in `binary_format` and reads them back, the values are multiples
+    /// of every resolution so that integer formats keep them exactly.
+    fn round_trip(binary_format: &str) -> Result<(), Error> {
+        let eeg_info = parse_header(&Some(HEADER.to_owned()))?;
+        let data = Array2::from_shape_fn((3, 250), |(ch, idx)| (idx as f32 - 125.0) * (ch + 1) as f32);
+        let markers = Markers::from_events(vec![Marker {
+            kind: String::from("Stimulus"),
+            description: String::from("S  1"),
+            position: 100,
+            duration: 1,
+            channel: 0,
+            date: None,
+        }]);
+        let vhdr_path = temp_path(&format!("round-trip-{binary_format}.vhdr"));
+
+        write_brainvision(&vhdr_path, &eeg_info, &data, &markers, binary_format)?;
+        let (read_info, read_data, read_markers) = read_brainvision(&vhdr_path)?;
+
+        assert_eq!(read_info.binary_format, binary_format);
+        assert_eq!(read_info.ch_names, eeg_info.ch_names);
+        assert_eq!(read_data.data, data);
+        assert_eq!(read_markers.events, markers.events);
+        for extension in ["vhdr", "vmrk", "eeg"] {
+            std::fs::remove_file(vhdr_path.replace(".vhdr", &format!(".{extension}"))).ok();
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips_multiplexed_int_16() -> Result<(), Error> {
+        round_trip("INT_16")
+    }
+
+    #[test]
+    fn round_trips_multiplexed_ieee_float_32() -> Result<(), Error> {
+        round_trip("IEEE_FLOAT_32")
+    }
+}
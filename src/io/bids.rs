@@ -0,0 +1,481 @@
+// BIDS-EEG datasets
+//
+// A recording lives in `<root>/sub-<label>[/ses-<label>]/eeg/` under a file name built from
+// its entities, e.g. `sub-01_ses-01_task-tms_run-01_eeg.vhdr`, next to sidecars sharing the
+// same prefix: `_eeg.json` (acquisition parameters), `_channels.tsv` (type, unit and status
+// of every channel), `_electrodes.tsv` (positions) and `_events.tsv` (onsets in seconds).
+// Sidecars higher up in the hierarchy (inheritance) are not looked up.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value, json};
+
+use crate::montage::{self, Electrode, Montage};
+use crate::{ChannelType, EEGData, EEGInfo, Error, Marker, Markers};
+
+/// Suffixes of the data files `find` recognises, in order of preference
+const DATA_EXTENSIONS: [&str; 3] = ["vhdr", "edf", "bdf"];
+const BIDS_VERSION: &str = "1.9.0";
+
+/// Entities identifying one recording of a BIDS dataset.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BidsPath {
+    /// Dataset root, the folder holding `dataset_description.json`
+    pub root: PathBuf,
+    pub subject: String,
+    pub session: Option<String>,
+    /// Empty matches any task when searching
+    pub task: String,
+    pub acquisition: Option<String>,
+    pub run: Option<String>,
+}
+
+impl BidsPath {
+    /// File name prefix, e.g. `sub-01_ses-01_task-tms_run-01`.
+    pub fn basename(&self) -> String {
+        let mut name = format!("sub-{}", self.subject);
+        if let Some(session) = &self.session {
+            name.push_str(&format!("_ses-{session}"));
+        }
+        name.push_str(&format!("_task-{}", self.task));
+        if let Some(acquisition) = &self.acquisition {
+            name.push_str(&format!("_acq-{acquisition}"));
+        }
+        if let Some(run) = &self.run {
+            name.push_str(&format!("_run-{run}"));
+        }
+        name
+    }
+
+    /// Folder of the recording, `<root>/sub-<label>[/ses-<label>]/eeg`.
+    pub fn eeg_dir(&self) -> PathBuf {
+        let mut dir = self.root.join(format!("sub-{}", self.subject));
+        if let Some(session) = &self.session {
+            dir.push(format!("ses-{session}"));
+        }
+        dir.join("eeg")
+    }
+
+    /// Path of the file with `suffix`, e.g. `channels.tsv` or `eeg.vhdr`.
+    pub fn file(&self, suffix: &str) -> PathBuf {
+        self.eeg_dir().join(format!("{}_{suffix}", self.basename()))
+    }
+
+    /// Finds the single recording matching the given entities; entities left empty (task) or
+    /// `None` match anything. Returns the entities of the recording found and its data file.
+    pub fn find(&self) -> Result<(Self, PathBuf), Error> {
+        let subject_dir = self.root.join(format!("sub-{}", self.subject));
+        let eeg_dirs: Vec<PathBuf> = if let Some(session) = &self.session {
+            vec![subject_dir.join(format!("ses-{session}")).join("eeg")]
+        } else {
+            let mut dirs = vec![subject_dir.join("eeg")];
+            if let Ok(entries) = fs::read_dir(&subject_dir) {
+                let mut sessions: Vec<PathBuf> = entries
+                    .filter_map(Result::ok)
+                    .filter(|entry| entry.file_name().to_string_lossy().starts_with("ses-"))
+                    .map(|entry| entry.path().join("eeg"))
+                    .collect();
+                sessions.sort();
+                dirs.extend(sessions);
+            }
+            dirs
+        };
+
+        let mut found: Vec<(Self, PathBuf)> = Vec::new();
+        for dir in eeg_dirs.iter().filter(|dir| dir.is_dir()) {
+            let mut entries: Vec<PathBuf> = fs::read_dir(dir).map_err(Error::io(dir))?.filter_map(Result::ok).map(|entry| entry.path()).collect();
+            entries.sort();
+            for path in entries {
+                let Some(name) = path.file_name().and_then(|name| name.to_str()) else { continue };
+                let Some((stem, extension)) = name.rsplit_once('.') else { continue };
+                let Some(prefix) = stem.strip_suffix("_eeg") else { continue };
+                if !DATA_EXTENSIONS.contains(&extension) {
+                    continue;
+                }
+                let candidate = self.with_entities(prefix);
+                if candidate.matches(self) && !found.iter().any(|(other, _)| other == &candidate) {
+                    found.push((candidate, path));
+                }
+            }
+        }
+        match found.len() {
+            1 => Ok(found.remove(0)),
+            0 => Err(Error::NotFound(format!("No EEG recording of {} found in {}", self.basename(), self.root.display()))),
+            _ => Err(Error::InvalidParameter(format!(
+                "{} recordings match, add the session, task or run: {:?}",
+                found.len(),
+                found.iter().map(|(candidate, _)| candidate.basename()).collect::<Vec<_>>()
+            ))),
+        }
+    }
+
+    /// Entities parsed from a file name prefix such as `sub-01_task-tms_run-1`.
+    fn with_entities(&self, prefix: &str) -> Self {
+        let mut parsed = Self { root: self.root.clone(), ..Self::default() };
+        for entity in prefix.split('_') {
+            let Some((key, label)) = entity.split_once('-') else { continue };
+            match key {
+                "sub" => parsed.subject = label.to_owned(),
+                "ses" => parsed.session = Some(label.to_owned()),
+                "task" => parsed.task = label.to_owned(),
+                "acq" => parsed.acquisition = Some(label.to_owned()),
+                "run" => parsed.run = Some(label.to_owned()),
+                _ => {}
+            }
+        }
+        parsed
+    }
+
+    fn matches(&self, query: &Self) -> bool {
+        let optional = |found: &Option<String>, wanted: &Option<String>| wanted.is_none() || found == wanted;
+        self.subject == query.subject
+            && (query.task.is_empty() || self.task == query.task)
+            && optional(&self.session, &query.session)
+            && optional(&self.acquisition, &query.acquisition)
+            && optional(&self.run, &query.run)
+    }
+}
+
+/// One row of a `_channels.tsv` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BidsChannel {
+    pub name: String,
+    /// BIDS channel type, e.g. `EEG`, `EOG`, `EMG` or `TRIG`
+    pub kind: String,
+    pub units: String,
+    /// `good` or `bad`, `None` when the column is missing or `n/a`
+    pub status: Option<String>,
+    pub status_description: Option<String>,
+}
+
+/// One row of an `_electrodes.tsv` file, positions are in the units of `_coordsystem.json`.
+/// `read_bids` also attaches them to `EEGInfo::montage` in metres.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BidsElectrode {
+    pub name: String,
+    pub position: Option<(f64, f64, f64)>,
+}
+
+/// A recording read from a BIDS dataset.
+#[derive(Debug)]
+pub struct BidsRecording {
+    /// Entities of the recording found
+    pub path: BidsPath,
+    /// The `.vhdr`, `.edf` or `.bdf` file
+    pub data_path: PathBuf,
+    /// Channels marked bad in `_channels.tsv` are listed in `bads`
+    pub eeg_info: EEGInfo,
+    pub data: EEGData,
+    /// From `_events.tsv` if present, otherwise the markers of the data file
+    pub markers: Markers,
+    /// Contents of `_eeg.json`
+    pub sidecar: Map<String, Value>,
+    pub channels: Vec<BidsChannel>,
+    pub electrodes: Vec<BidsElectrode>,
+}
+
+/// Parses a TSV file into its header and rows, `n/a` cells become `None`.
+type Tsv = (Vec<String>, Vec<Vec<Option<String>>>);
+
+fn read_tsv(path: &Path) -> Result<Tsv, Error> {
+    let content = fs::read_to_string(path).map_err(Error::io(path))?;
+    let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+    let header: Vec<String> = lines.next().unwrap_or_default().split('\t').map(|column| column.trim().to_owned()).collect();
+    let rows = lines
+        .map(|line| {
+            line.split('\t')
+                .map(|cell| match cell.trim() {
+                    "n/a" | "" => None,
+                    cell => Some(cell.to_owned()),
+                })
+                .collect()
+        })
+        .collect();
+    Ok((header, rows))
+}
+
+/// Cell of `column` in `row`, `None` if the column is missing or the cell is `n/a`.
+fn cell<'r>(header: &[String], row: &'r [Option<String>], column: &str) -> Option<&'r str> {
+    let idx = header.iter().position(|name| name == column)?;
+    row.get(idx)?.as_deref()
+}
+
+fn read_channels(path: &Path) -> Result<Vec<BidsChannel>, Error> {
+    let (header, rows) = read_tsv(path)?;
+    rows.iter()
+        .map(|row| {
+            Ok(BidsChannel {
+                name: cell(&header, row, "name").ok_or_else(|| Error::Malformed(format!("Channel without a name in {}", path.display())))?.to_owned(),
+                kind: cell(&header, row, "type").unwrap_or("MISC").to_owned(),
+                units: cell(&header, row, "units").unwrap_or_default().to_owned(),
+                status: cell(&header, row, "status").map(str::to_lowercase),
+                status_description: cell(&header, row, "status_description").map(str::to_owned),
+            })
+        })
+        .collect()
+}
+
+fn read_electrodes(path: &Path) -> Result<Vec<BidsElectrode>, Error> {
+    let (header, rows) = read_tsv(path)?;
+    Ok(rows
+        .iter()
+        .filter_map(|row| {
+            let coordinate = |axis: &str| cell(&header, row, axis).and_then(|value| value.parse::<f64>().ok());
+            Some(BidsElectrode {
+                name: cell(&header, row, "name")?.to_owned(),
+                position: coordinate("x").zip(coordinate("y")).zip(coordinate("z")).map(|((x, y), z)| (x, y, z)),
+            })
+        })
+        .collect())
+}
+
+/// Metres per unit of the `EEGCoordinateUnits` in `_coordsystem.json`, `None` for `n/a`.
+fn read_coordinate_units(path: &Path) -> Result<Option<f64>, Error> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let coordsystem: Value = serde_json::from_str(&fs::read_to_string(path).map_err(Error::io(path))?)?;
+    Ok(match coordsystem.get("EEGCoordinateUnits").and_then(Value::as_str) {
+        Some("m") => Some(1.0),
+        Some("cm") => Some(0.01),
+        Some("mm") => Some(0.001),
+        _ => None,
+    })
+}
+
+/// Montage of the electrodes with a position; without units they are taken to be on a
+/// unit sphere.
+fn electrode_montage(electrodes: &[BidsElectrode], units: Option<f64>) -> Montage {
+    let scale = units.unwrap_or(montage::HEAD_RADIUS);
+    Montage {
+        electrodes: electrodes
+            .iter()
+            .filter_map(|electrode| {
+                let (x, y, z) = electrode.position?;
+                Some(Electrode { name: electrode.name.clone(), position: [x * scale, y * scale, z * scale] })
+            })
+            .collect(),
+    }
+}
+
+/// Marker types kept as they are when read back from `trial_type`
+fn is_marker_kind(kind: &str) -> bool {
+    matches!(kind, "Stimulus" | "Response" | "Annotation" | "Comment" | "SyncStatus" | "Bad Interval")
+}
+
+/// Reads `_events.tsv`. Rows written by `write_bids` keep their `BrainVision` type in
+/// `trial_type` and description in `value`; other datasets become `Stimulus` markers
+/// described by `value`, or by `trial_type` when there is no value. The `channel` column
+/// scopes an event to one of `ch_names`, events of other channels are dropped.
+fn read_events(path: &Path, sfreq: f64, ch_names: &[String]) -> Result<Markers, Error> {
+    let (header, rows) = read_tsv(path)?;
+    let mut events = Vec::with_capacity(rows.len());
+    for row in &rows {
+        let onset = cell(&header, row, "onset").ok_or_else(|| Error::Malformed(format!("Event without onset in {}", path.display())))?;
+        let onset: f64 = onset.parse().map_err(|_err| Error::Malformed(format!("Invalid onset {onset:?} in {}", path.display())))?;
+        let position = match cell(&header, row, "sample").and_then(|sample| sample.parse::<usize>().ok()) {
+            Some(sample) => sample,
+            None => (onset * sfreq).round().max(0.0) as usize,
+        };
+        let duration = cell(&header, row, "duration").and_then(|duration| duration.parse::<f64>().ok()).unwrap_or(0.0);
+        let channel = match cell(&header, row, "channel") {
+            Some(name) => match ch_names.iter().position(|ch| ch == name) {
+                Some(idx) => idx + 1,
+                None => continue,
+            },
+            None => 0,
+        };
+        let (trial_type, value) = (cell(&header, row, "trial_type"), cell(&header, row, "value"));
+        let (kind, description) = match (trial_type, value) {
+            (Some(kind), value) if is_marker_kind(kind) => (kind, value.unwrap_or_default()),
+            (_, Some(value)) => ("Stimulus", value),
+            (Some(trial_type), None) => ("Stimulus", trial_type),
+            (None, None) => ("Stimulus", ""),
+        };
+        events.push(Marker {
+            kind: kind.to_owned(),
+            description: description.to_owned(),
+            position,
+            duration: ((duration * sfreq).round() as usize).max(1),
+            channel,
+            date: None,
+        });
+    }
+    events.sort_by_key(|event| event.position);
+    Ok(Markers::from_events(events))
+}
+
+/// Reads the recording identified by `bids` (see `BidsPath::find`) with its sidecars.
+pub fn read_bids(bids: &BidsPath) -> Result<BidsRecording, Error> {
+    let (path, data_path) = bids.find()?;
+    let data_file = data_path.to_string_lossy().into_owned();
+    let (mut eeg_info, data, mut markers) = match data_path.extension().and_then(|ext| ext.to_str()) {
+        Some("vhdr") => super::read_brainvision(&data_file)?,
+        Some("bdf") => super::parse_bdf(&data_file)?,
+        _ => super::parse_edf(&data_file)?,
+    };
+
+    let sidecar_path = path.file("eeg.json");
+    let sidecar = if sidecar_path.is_file() {
+        match serde_json::from_str(&fs::read_to_string(&sidecar_path).map_err(Error::io(&sidecar_path))?)? {
+            Value::Object(sidecar) => sidecar,
+            _ => return Err(Error::Malformed(format!("{} is not a JSON object", sidecar_path.display()))),
+        }
+    } else {
+        Map::new()
+    };
+
+    let channels_path = path.file("channels.tsv");
+    let channels = if channels_path.is_file() { read_channels(&channels_path)? } else { Vec::new() };
+    for channel in &channels {
+        if channel.status.as_deref() == Some("bad") && !eeg_info.bads.contains(&channel.name) {
+            eeg_info.bads.push(channel.name.clone());
+        }
+        // Types BIDS has but the crate does not (REF, RESP, TEMP ...) become misc
+        let kind = channel.kind.parse().unwrap_or(ChannelType::Misc);
+        if let Some(info) = eeg_info.channels.iter_mut().find(|info| info.name == channel.name) {
+            info.kind = kind;
+        }
+    }
+
+    let electrodes_path = path.file("electrodes.tsv");
+    let electrodes = if electrodes_path.is_file() { read_electrodes(&electrodes_path)? } else { Vec::new() };
+    if electrodes.iter().any(|electrode| electrode.position.is_some()) {
+        let units = read_coordinate_units(&path.file("coordsystem.json"))?;
+        montage::set_montage(&mut eeg_info, electrode_montage(&electrodes, units));
+    }
+
+    let events_path = path.file("events.tsv");
+    if events_path.is_file() {
+        markers = read_events(&events_path, eeg_info.sfreq, &eeg_info.ch_names)?;
+    }
+
+    Ok(BidsRecording { path, data_path, eeg_info, data, markers, sidecar, channels, electrodes })
+}
+
+/// Replaces the value of every `key=` line, e.g. `DataFile=` in a header or marker file.
+fn replace_key(content: &str, key: &str, value: &str) -> String {
+    let mut replaced = content
+        .lines()
+        .map(|line| if line.trim_start().starts_with(&format!("{key}=")) { format!("{key}={value}") } else { line.to_owned() })
+        .collect::<Vec<_>>()
+        .join("\n");
+    replaced.push('\n');
+    replaced
+}
+
+/// `fs::write` naming the file in its error.
+fn write_file(path: &Path, contents: &str) -> Result<(), Error> {
+    fs::write(path, contents).map_err(Error::io(path))
+}
+
+fn write_json(path: &Path, value: &Value) -> Result<(), Error> {
+    let mut text = serde_json::to_string_pretty(value)?;
+    text.push('\n');
+    write_file(path, &text)
+}
+
+/// Copies the `BrainVision` recording `vhdr_path` into the dataset at the entities of
+/// `bids` and writes its sidecars. `dataset_description.json` is created if missing.
+///
+/// The marker file and `_events.tsv` are written from `markers`, so annotations added
+/// since reading the recording are kept. `power_line_frequency` (Hz) is required by BIDS,
+/// `None` writes `n/a`.
+pub fn write_bids(bids: &BidsPath, vhdr_path: &str, markers: &Markers, power_line_frequency: Option<f64>) -> Result<(), Error> {
+    if bids.subject.is_empty() || bids.task.is_empty() {
+        return Err(Error::InvalidParameter("A BIDS recording needs at least a subject and a task".into()));
+    }
+    let header = super::get_header(&Some(vhdr_path.to_owned()))?;
+    let eeg_info = super::parse_header(&header)?;
+    // Renamed or missing DataFile entries are resolved like `read_brainvision` does
+    let files = super::resolve_brainvision(vhdr_path)?;
+
+    fs::create_dir_all(bids.eeg_dir()).map_err(Error::io(bids.eeg_dir()))?;
+    let description = bids.root.join("dataset_description.json");
+    if !description.exists() {
+        let name = bids.root.file_name().map_or_else(|| String::from("EEG dataset"), |name| name.to_string_lossy().into_owned());
+        write_json(&description, &json!({ "Name": name, "BIDSVersion": BIDS_VERSION, "DatasetType": "raw" }))?;
+    }
+
+    // The header and marker files must point at the renamed files
+    let file_name = |suffix: &str| format!("{}_{suffix}", bids.basename());
+    let (eeg_name, vmrk_name) = (file_name("eeg.eeg"), file_name("eeg.vmrk"));
+    let eeg_path = bids.file("eeg.eeg");
+    fs::copy(&files.data, &eeg_path).map_err(Error::io(&files.data))?;
+    let header = header.unwrap_or_default();
+    write_file(&bids.file("eeg.vhdr"), &replace_key(&replace_key(&header, "DataFile", &eeg_name), "MarkerFile", &vmrk_name))?;
+    write_file(&bids.file("eeg.vmrk"), &super::brainvision_vmrk(markers, &eeg_name))?;
+
+    let sfreq = eeg_info.sfreq;
+    let n_samples = fs::metadata(&eeg_path).map_err(Error::io(&eeg_path))?.len() as usize / super::bytes_per_sample(&eeg_info.binary_format)? / eeg_info.channels.len().max(1);
+    let types: Vec<&str> = eeg_info.channels.iter().map(|channel| channel.kind.bids_name()).collect();
+    let count = |kind: &str| types.iter().filter(|&&t| t == kind).count();
+    let reference = match eeg_info.channels.first().map(|ch| ch.reference.as_str()) {
+        Some(reference) if !reference.is_empty() && eeg_info.channels.iter().all(|ch| ch.reference == reference) => reference,
+        _ => "n/a",
+    };
+    write_json(
+        &bids.file("eeg.json"),
+        &json!({
+            "TaskName": bids.task,
+            "SamplingFrequency": sfreq,
+            "EEGReference": reference,
+            "PowerLineFrequency": power_line_frequency.map_or_else(|| json!("n/a"), |freq| json!(freq)),
+            "SoftwareFilters": "n/a",
+            "EEGChannelCount": count("EEG"),
+            "EOGChannelCount": count("EOG"),
+            "ECGChannelCount": count("ECG"),
+            "EMGChannelCount": count("EMG"),
+            "TriggerChannelCount": count("TRIG"),
+            "RecordingDuration": n_samples as f64 / sfreq,
+            "RecordingType": "continuous",
+        }),
+    )?;
+
+    let mut channels = String::from("name\ttype\tunits\tstatus\n");
+    for (channel, kind) in eeg_info.channels.iter().zip(&types) {
+        let status = if eeg_info.bads.contains(&channel.name) { "bad" } else { "good" };
+        channels.push_str(&format!("{}\t{kind}\t{}\t{status}\n", channel.name, channel.unit));
+    }
+    write_file(&bids.file("channels.tsv"), &channels)?;
+
+    if let Some(montage) = &eeg_info.montage {
+        let mut electrodes = String::from("name\tx\ty\tz\n");
+        for name in &eeg_info.ch_names {
+            match montage.position(name) {
+                Some([x, y, z]) => electrodes.push_str(&format!("{name}\t{x:.6}\t{y:.6}\t{z:.6}\n")),
+                None => electrodes.push_str(&format!("{name}\tn/a\tn/a\tn/a\n")),
+            }
+        }
+        write_file(&bids.file("electrodes.tsv"), &electrodes)?;
+        write_json(
+            &bids.file("coordsystem.json"),
+            &json!({
+                "EEGCoordinateSystem": "Other",
+                "EEGCoordinateUnits": "m",
+                "EEGCoordinateSystemDescription": "Head coordinates: x towards the right ear, y towards the nose, z towards the vertex",
+            }),
+        )?;
+    }
+
+    // "New Segment" markers only carry the recording start
+    let mut sorted: Vec<&Marker> = markers.events.iter().filter(|event| event.kind != "New Segment").collect();
+    sorted.sort_by_key(|event| event.position);
+    let mut events = String::from("onset\tduration\tsample\ttrial_type\tvalue\tchannel\n");
+    for event in sorted {
+        let channel = event.channel.checked_sub(1).and_then(|idx| eeg_info.ch_names.get(idx));
+        events.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\n",
+            event.position as f64 / sfreq,
+            event.duration as f64 / sfreq,
+            event.position,
+            event.kind,
+            if event.description.is_empty() { "n/a" } else { event.description.as_str() },
+            channel.map_or("n/a", String::as_str)
+        ));
+    }
+    write_file(&bids.file("events.tsv"), &events)?;
+    println!("Wrote {}", bids.file("eeg.vhdr").display());
+    Ok(())
+}
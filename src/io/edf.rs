@@ -0,0 +1,894 @@
+// EDF, EDF+ and BDF
+//
+// A 256 byte fixed header is followed by one 256 byte block per signal (stored field by
+// field, i.e. all labels, then all transducers ...) and then the data records. Each data
+// record holds `samples_per_record` little endian samples of every signal in turn.
+// EDF samples are 16 bit, BioSemi BDF files use the same layout with 24 bit samples and
+// mark themselves with a 0xFF byte followed by "BIOSEMI" as version.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use ndarray::{Array2, s};
+
+use crate::{ChannelInfo, ChannelType, EEGData, EEGInfo, Error, Marker, Markers};
+use crate::signal::crop_markers;
+use super::select::Selection;
+use super::vec_to_ndarray;
+
+pub const ANNOTATION_LABEL: &str = "EDF Annotations";
+/// Label of the annotation signal of BDF+ files
+pub const BDF_ANNOTATION_LABEL: &str = "BDF Annotations";
+/// Separates the text of an annotation from the channel it belongs to
+const CHANNEL_SEPARATOR: &str = "@@";
+/// Label of the BDF trigger channel
+pub const STATUS_LABEL: &str = "Status";
+
+#[derive(Debug, Clone)]
+pub struct EdfHeader {
+    pub version: String,
+    /// 2 for EDF, 3 for BDF
+    pub sample_bytes: usize,
+    pub patient: String,
+    pub recording: String,
+    /// dd.mm.yy
+    pub start_date: String,
+    /// hh.mm.ss
+    pub start_time: String,
+    pub header_bytes: usize,
+    /// `EDF+C` or `EDF+D` for EDF+ files, empty for plain EDF
+    pub reserved: String,
+    pub n_records: usize,
+    /// Duration of one data record in seconds
+    pub record_duration: f64,
+    pub signals: Vec<EdfSignal>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EdfSignal {
+    pub label: String,
+    pub transducer: String,
+    pub physical_dimension: String,
+    pub physical_min: f64,
+    pub physical_max: f64,
+    pub digital_min: f64,
+    pub digital_max: f64,
+    pub prefiltering: String,
+    pub samples_per_record: usize,
+}
+
+impl EdfSignal {
+    /// Physical units per digital count.
+    pub fn gain(&self) -> f64 {
+        (self.physical_max - self.physical_min) / (self.digital_max - self.digital_min)
+    }
+
+    pub fn offset(&self) -> f64 {
+        self.physical_max - self.gain() * self.digital_max
+    }
+
+    /// Physical units per count of the channel once read. Counts are physical value over
+    /// resolution, without an offset as `BrainVision` files have none, so signals with an
+    /// offset of more than a count get the resolution that keeps their physical range
+    /// within the digital range instead of the gain.
+    pub fn resolution(&self) -> f64 {
+        let gain = self.gain();
+        if self.offset().abs() <= gain.abs() {
+            return gain;
+        }
+        let reach = self.physical_min.abs().max(self.physical_max.abs());
+        let counts = self.digital_min.abs().max(self.digital_max.abs());
+        reach / counts
+    }
+
+    /// `true` for the `EDF Annotations` signal of EDF+ and the `BDF Annotations` of BDF+.
+    pub fn is_annotation(&self) -> bool {
+        self.label == ANNOTATION_LABEL || self.label == BDF_ANNOTATION_LABEL
+    }
+}
+
+impl EdfHeader {
+    /// `true` for EDF+ and BDF+ files.
+    pub fn is_edf_plus(&self) -> bool {
+        self.reserved.starts_with("EDF+") || self.reserved.starts_with("BDF+")
+    }
+
+    pub fn is_bdf(&self) -> bool {
+        self.sample_bytes == 3
+    }
+
+    /// `false` for the annotation signal and the BDF trigger channel.
+    pub fn is_data_signal(&self, signal: &EdfSignal) -> bool {
+        !(signal.is_annotation() || self.is_bdf() && signal.label == STATUS_LABEL)
+    }
+
+    /// Number of bytes of one data record.
+    pub fn record_bytes(&self) -> usize {
+        self.signals.iter().map(|sig| sig.samples_per_record).sum::<usize>() * self.sample_bytes
+    }
+
+    /// Byte offset of every signal inside a data record.
+    pub fn signal_offsets(&self) -> Vec<usize> {
+        let mut offsets = Vec::with_capacity(self.signals.len());
+        let mut offset = 0;
+        for signal in &self.signals {
+            offsets.push(offset);
+            offset += signal.samples_per_record * self.sample_bytes;
+        }
+        offsets
+    }
+
+    /// Sampling rate of one signal in Hz.
+    pub fn sfreq(&self, signal: &EdfSignal) -> f64 {
+        signal.samples_per_record as f64 / self.record_duration
+    }
+}
+
+/// Reads one little endian 16 bit (EDF) or 24 bit (BDF) sample.
+fn digital_sample(bytes: &[u8]) -> i32 {
+    match *bytes {
+        [b0, b1] => i32::from(i16::from_le_bytes([b0, b1])),
+        // Sign extend the 24 bit value through the top byte
+        [b0, b1, b2] => i32::from_le_bytes([b0, b1, b2, if b2 & 0x80 == 0 { 0 } else { 0xFF }]),
+        _ => 0,
+    }
+}
+
+fn ascii_field(bytes: &[u8], start: usize, len: usize) -> String {
+    String::from_utf8_lossy(&bytes[start..start + len]).trim().to_owned()
+}
+
+/// EDF headers have no lines, messages point at the byte offset instead.
+fn header_error(message: impl Into<String>) -> Error {
+    Error::Header { line: None, message: message.into() }
+}
+
+fn number_field<T: std::str::FromStr>(
+    bytes: &[u8],
+    start: usize,
+    len: usize,
+    field: &str,
+) -> Result<T, Error> {
+    let value = ascii_field(bytes, start, len);
+    value
+        .parse::<T>()
+        .map_err(|_err| header_error(format!("Invalid {field} {value:?} in EDF header at byte {start}")))
+}
+
+/// Parses the fixed header and the per-signal header block.
+/// `file_size` is used when the number of data records is still -1 (recording not closed).
+pub fn parse_edf_header(bytes: &[u8], file_size: usize) -> Result<EdfHeader, Error> {
+    if bytes.len() < 256 {
+        return Err(header_error("File is too short to hold an EDF header"));
+    }
+    let ns: usize = number_field(bytes, 252, 4, "number of signals")?;
+    let header_bytes = 256 + ns * 256;
+    if bytes.len() < header_bytes {
+        return Err(header_error(format!("EDF header announces {ns} signals but the file is too short")));
+    }
+
+    // Field widths of the signal block, every field is repeated ns times
+    let field_start = |offset: usize, width: usize, idx: usize| 256 + offset * ns + idx * width;
+    let mut signals = Vec::with_capacity(ns);
+    for idx in 0..ns {
+        signals.push(EdfSignal {
+            label: ascii_field(bytes, field_start(0, 16, idx), 16),
+            transducer: ascii_field(bytes, field_start(16, 80, idx), 80),
+            physical_dimension: ascii_field(bytes, field_start(96, 8, idx), 8),
+            physical_min: number_field(bytes, field_start(104, 8, idx), 8, "physical minimum")?,
+            physical_max: number_field(bytes, field_start(112, 8, idx), 8, "physical maximum")?,
+            digital_min: number_field(bytes, field_start(120, 8, idx), 8, "digital minimum")?,
+            digital_max: number_field(bytes, field_start(128, 8, idx), 8, "digital maximum")?,
+            prefiltering: ascii_field(bytes, field_start(136, 80, idx), 80),
+            samples_per_record: number_field(bytes, field_start(216, 8, idx), 8, "samples per record")?,
+        });
+    }
+
+    let is_bdf = bytes[0] == 0xFF;
+    let mut header = EdfHeader {
+        version: if is_bdf { ascii_field(bytes, 1, 7) } else { ascii_field(bytes, 0, 8) },
+        sample_bytes: if is_bdf { 3 } else { 2 },
+        patient: ascii_field(bytes, 8, 80),
+        recording: ascii_field(bytes, 88, 80),
+        start_date: ascii_field(bytes, 168, 8),
+        start_time: ascii_field(bytes, 176, 8),
+        header_bytes: number_field(bytes, 184, 8, "header size")?,
+        reserved: ascii_field(bytes, 192, 44),
+        n_records: 0,
+        record_duration: number_field(bytes, 244, 8, "data record duration")?,
+        signals,
+    };
+    if let Some(signal) = header.signals.iter().find(|signal| signal.digital_max == signal.digital_min) {
+        return Err(header_error(format!(
+            "Signal {:?} has the same digital minimum and maximum {}",
+            signal.label, signal.digital_min
+        )));
+    }
+    if header.header_bytes != header_bytes {
+        return Err(header_error(format!(
+            "EDF header size is {} bytes but {ns} signals need {header_bytes}",
+            header.header_bytes
+        )));
+    }
+
+    let record_bytes = header.record_bytes();
+    if record_bytes == 0 {
+        return Err(header_error("EDF data records are empty"));
+    }
+    let records_in_file = file_size.saturating_sub(header_bytes) / record_bytes;
+    header.n_records = match number_field::<i64>(bytes, 236, 8, "number of data records")? {
+        -1 => records_in_file,
+        n if n < 0 => return Err(header_error(format!("Invalid number of data records {n}"))),
+        n => (n as usize).min(records_in_file),
+    };
+    Ok(header)
+}
+
+/// Decodes the data records into one vector of physical values per selected signal.
+pub fn decode_records(
+    bytes: &[u8],
+    header: &EdfHeader,
+    selected: &[usize],
+) -> Vec<Vec<f32>> {
+    let offsets = header.signal_offsets();
+    let record_bytes = header.record_bytes();
+    let sample_bytes = header.sample_bytes;
+
+    selected
+        .iter()
+        .map(|&sig_idx| {
+            let signal = &header.signals[sig_idx];
+            let (gain, sig_offset) = (signal.gain(), signal.offset());
+            let mut channel = Vec::with_capacity(header.n_records * signal.samples_per_record);
+            for record in 0..header.n_records {
+                let start = header.header_bytes + record * record_bytes + offsets[sig_idx];
+                let end = start + signal.samples_per_record * sample_bytes;
+                channel.extend(bytes[start..end].chunks_exact(sample_bytes).map(|sample| {
+                    let digital = f64::from(digital_sample(sample));
+                    (digital * gain + sig_offset) as f32
+                }));
+            }
+            channel
+        })
+        .collect()
+}
+
+/// One time-stamped annotation list of an `EDF Annotations` signal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tal {
+    /// Seconds relative to the start of the recording
+    pub onset: f64,
+    /// Seconds, 0 when the TAL has no duration
+    pub duration: f64,
+    /// Empty for the time-keeping TAL that starts every data record
+    pub texts: Vec<String>,
+}
+
+/// Parses the TALs of one data record: `+Onset[\x15Duration]\x14Text\x14...\x14\x00`.
+/// Unused bytes at the end of the record are zero.
+pub fn parse_tals(bytes: &[u8]) -> Result<Vec<Tal>, Error> {
+    let mut tals = Vec::new();
+    for tal in bytes.split(|&b| b == 0).filter(|tal| !tal.is_empty()) {
+        let mut parts = tal.split(|&b| b == 0x14);
+        let timing = String::from_utf8_lossy(parts.next().unwrap_or_default()).into_owned();
+        let (onset, duration) = match timing.split_once('\u{15}') {
+            Some((onset, duration)) => (onset, duration),
+            None => (timing.as_str(), ""),
+        };
+        let onset: f64 = onset
+            .trim()
+            .parse()
+            .map_err(|_err| Error::Malformed(format!("Invalid TAL onset {onset:?}")))?;
+        let duration: f64 = match duration.trim() {
+            "" => 0.0,
+            d => d.parse().map_err(|_err| Error::Malformed(format!("Invalid TAL duration {d:?}")))?,
+        };
+        let texts = parts
+            .filter(|text| !text.is_empty())
+            .map(|text| String::from_utf8_lossy(text).into_owned())
+            .collect();
+        tals.push(Tal { onset, duration, texts });
+    }
+    Ok(tals)
+}
+
+/// Turns the annotations of all data records into markers of kind `Annotation`.
+///
+/// Onsets are placed relative to the time-keeping TAL of their record, which keeps them
+/// on the right sample for discontinuous (`EDF+D`) files where records are concatenated.
+/// Gaps between records become `New Segment` markers.
+pub fn annotation_markers(
+    bytes: &[u8],
+    header: &EdfHeader,
+    samples_per_record: usize,
+) -> Result<Markers, Error> {
+    let mut events = Vec::new();
+    let Some(annot_idx) = header.signals.iter().position(EdfSignal::is_annotation) else {
+        return Ok(Markers { n_markers: 0, markers: Vec::new(), events });
+    };
+    let sfreq = samples_per_record as f64 / header.record_duration;
+    let annot_offset = header.signal_offsets()[annot_idx];
+    let annot_bytes = header.signals[annot_idx].samples_per_record * header.sample_bytes;
+
+    let mut expected_start: Option<f64> = None;
+    for record in 0..header.n_records {
+        let start = header.header_bytes + record * header.record_bytes() + annot_offset;
+        let tals = parse_tals(&bytes[start..start + annot_bytes])?;
+        let record_start = tals.first().map_or(record as f64 * header.record_duration, |tal| tal.onset);
+        let first_sample = record * samples_per_record;
+
+        if expected_start.is_some_and(|expected| (record_start - expected).abs() > 0.5 / sfreq) {
+            events.push(Marker {
+                kind: String::from("New Segment"),
+                description: String::new(),
+                position: first_sample,
+                duration: 1,
+                channel: 0,
+                date: None,
+            });
+        }
+        expected_start = Some(record_start + header.record_duration);
+
+        for tal in &tals {
+            let position = first_sample as f64 + (tal.onset - record_start) * sfreq;
+            for text in &tal.texts {
+                events.push(Marker {
+                    kind: String::from("Annotation"),
+                    description: text.clone(),
+                    position: position.round().max(0.0) as usize,
+                    duration: ((tal.duration * sfreq).round() as usize).max(1),
+                    channel: 0,
+                    date: None,
+                });
+            }
+        }
+    }
+
+    Ok(Markers::from_events(events))
+}
+
+/// Moves the channel of annotations written as `<text>@@<channel name>` into the marker,
+/// as MNE writes annotations of single channels. Annotations of channels that were not
+/// read are dropped.
+fn scope_annotations(events: Vec<Marker>, ch_names: &[String]) -> Vec<Marker> {
+    events
+        .into_iter()
+        .filter_map(|mut event| {
+            let Some((text, name)) = event.description.rsplit_once(CHANNEL_SEPARATOR) else {
+                return Some(event);
+            };
+            event.channel = ch_names.iter().position(|ch| ch == name)? + 1;
+            event.description = text.to_owned();
+            Some(event)
+        })
+        .collect()
+}
+
+/// Decodes the trigger bits (lowest 16 bits) of the BDF `Status` channel into `Stimulus`
+/// markers named like `BrainVision` stimuli, e.g. code 1 becomes `S  1`.
+///
+/// A marker starts whenever the code changes to a non-zero value and lasts while it is held.
+pub fn status_markers(bytes: &[u8], header: &EdfHeader, samples_per_record: usize) -> Vec<Marker> {
+    let Some(status_idx) = header
+        .signals
+        .iter()
+        .position(|sig| header.is_bdf() && sig.label == STATUS_LABEL)
+    else {
+        return Vec::new();
+    };
+    let status = &header.signals[status_idx];
+    let start = header.signal_offsets()[status_idx];
+    let record_bytes = header.record_bytes();
+    // The Status channel normally runs at the data rate, rescale positions if it does not
+    let scale = samples_per_record as f64 / status.samples_per_record as f64;
+
+    let mut events: Vec<Marker> = Vec::new();
+    let mut previous = 0;
+    let mut idx = 0;
+    for record in 0..header.n_records {
+        let offset = header.header_bytes + record * record_bytes + start;
+        let end = offset + status.samples_per_record * header.sample_bytes;
+        for sample in bytes[offset..end].chunks_exact(header.sample_bytes) {
+            let code = digital_sample(sample) & 0xFFFF;
+            if code != previous && code != 0 {
+                events.push(Marker {
+                    kind: String::from("Stimulus"),
+                    description: format!("S{code:>3}"),
+                    position: (idx as f64 * scale).round() as usize,
+                    duration: 1,
+                    channel: 0,
+                    date: None,
+                });
+            } else if code == previous && code != 0 {
+                if let Some(event) = events.last_mut() {
+                    event.duration = ((idx as f64 * scale).round() as usize + 1).saturating_sub(event.position);
+                }
+            }
+            previous = code;
+            idx += 1;
+        }
+    }
+    events
+}
+
+/// Indices of the ordinary signals that share the highest sampling rate.
+/// `EEGData` is a channels x samples matrix, so slower signals cannot be part of it.
+fn data_signals(header: &EdfHeader) -> Vec<usize> {
+    let max_spr = header
+        .signals
+        .iter()
+        .filter(|sig| header.is_data_signal(sig))
+        .map(|sig| sig.samples_per_record)
+        .max()
+        .unwrap_or(0);
+    header
+        .signals
+        .iter()
+        .enumerate()
+        .filter(|(_, sig)| header.is_data_signal(sig) && sig.samples_per_record == max_spr)
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Builds the `EEGInfo` for the selected signals. The data is read in physical units, the
+/// resolution of a channel is the physical size of one digital step of its signal.
+pub fn edf_info(path: &str, header: &EdfHeader, selected: &[usize]) -> EEGInfo {
+    let channels: Vec<ChannelInfo> = selected
+        .iter()
+        .map(|&idx| {
+            let signal = &header.signals[idx];
+            ChannelInfo {
+                name: signal.label.clone(),
+                reference: String::new(),
+                resolution: signal.resolution(),
+                unit: signal.physical_dimension.clone(),
+                coordinates: None,
+                kind: ChannelType::infer(&signal.label, &signal.physical_dimension),
+            }
+        })
+        .collect();
+
+    let sfreq = selected.first().map_or(0.0, |&idx| header.sfreq(&header.signals[idx]));
+    let mut comment = format!(
+        "Patient: {}\nRecording: {}\nStart: {} {}\n",
+        header.patient, header.recording, header.start_date, header.start_time
+    );
+    for &idx in selected {
+        let signal = &header.signals[idx];
+        comment.push_str(&format!("{}: {} {}\n", signal.label, signal.transducer, signal.prefiltering));
+    }
+
+    EEGInfo {
+        num_ch: channels.len() as i32,
+        ch_namesx: (1..=channels.len()).map(|i| format!("Ch{i}")).collect(),
+        ch_names: channels.iter().map(|ch| ch.name.clone()).collect(),
+        channels,
+        sfreq,
+        data_file: Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        marker_file: String::new(),
+        codepage: String::new(),
+        data_orientation: String::new(),
+        binary_format: String::from(if header.is_bdf() { "INT_24" } else { "INT_16" }),
+        sampling_interval_in: String::from("microseconds"),
+        sampling_interval: if sfreq > 0.0 { 1_000_000.0 / sfreq } else { 0.0 },
+        comment,
+        bads: Vec::new(),
+        montage: None,
+        impedances: None,
+    }
+}
+
+fn read_edf_file(path: &str, selection: &Selection) -> Result<(EEGInfo, EEGData, Markers, EdfHeader), Error> {
+    let mut reader = BufReader::new(File::open(path).map_err(Error::io(path))?);
+    let file_size = reader.get_ref().metadata().map_err(Error::io(path))?.len() as usize;
+
+    // Fixed header first, it holds the number of signals and so the size of the rest
+    let mut buffer = vec![0u8; 256];
+    reader.read_exact(&mut buffer).map_err(|_err| header_error("File is too short to hold an EDF header"))?;
+    let ns: usize = number_field(&buffer, 252, 4, "number of signals")?;
+    buffer.resize(256 + ns * 256, 0);
+    reader
+        .read_exact(&mut buffer[256..])
+        .map_err(|_err| header_error(format!("EDF header announces {ns} signals but the file is too short")))?;
+
+    let mut header = parse_edf_header(&buffer, file_size)?;
+    let kind = if header.is_edf_plus() { &header.reserved } else if header.is_bdf() { "BDF" } else { "EDF" };
+    println!("EDF version {:?} ({kind})", header.version);
+    println!("Number of data records {:?} of {:?} s", header.n_records, header.record_duration);
+
+    let data_idx = data_signals(&header);
+    for (idx, signal) in header.signals.iter().enumerate() {
+        if header.is_data_signal(signal) && !data_idx.contains(&idx) {
+            println!("Skipping signal {:?} sampled at {:?} Hz", signal.label, header.sfreq(signal));
+        }
+    }
+    let names: Vec<String> = data_idx.iter().map(|&idx| header.signals[idx].label.clone()).collect();
+    let selected: Vec<usize> = selection.channel_indices(&names)?.into_iter().map(|idx| data_idx[idx]).collect();
+
+    let eeg_info = edf_info(path, &header, &selected);
+    println!("Sampling rate {:?}", eeg_info.sfreq);
+    println!("Number of channels {:?}", eeg_info.num_ch);
+
+    // Only the data records overlapping the window are read. Decoding works on a copy of
+    // the header that starts at the first of them, so positions are relative to it.
+    let samples_per_record = data_idx.first().map_or(0, |&idx| header.signals[idx].samples_per_record);
+    let n_samples = header.n_records * samples_per_record;
+    let window = if selection.is_everything() || samples_per_record == 0 {
+        0..n_samples
+    } else {
+        selection.sample_range(samples_per_record as f64 / header.record_duration, n_samples)?
+    };
+    let first_record = window.start / samples_per_record.max(1);
+    let last_record = window.end.div_ceil(samples_per_record.max(1));
+    if first_record > 0 {
+        reader.seek_relative((first_record * header.record_bytes()) as i64).map_err(Error::io(path))?;
+    }
+    let mut records = vec![0u8; (last_record - first_record) * header.record_bytes()];
+    reader.read_exact(&mut records).map_err(Error::io(path))?;
+    buffer.extend(records);
+    let full_header = header.clone();
+    header.n_records = last_record - first_record;
+
+    let skip = window.start - first_record * samples_per_record;
+    let data = vec_to_ndarray(decode_records(&buffer, &header, &selected))?;
+    let data = data.slice(s![.., skip..skip + window.len()]).to_owned();
+    let mut events = annotation_markers(&buffer, &header, samples_per_record)?.events;
+    events.extend(status_markers(&buffer, &header, samples_per_record));
+    events.sort_by_key(|event| event.position);
+    let events = scope_annotations(events, &eeg_info.ch_names);
+    let markers = crop_markers(&Markers::from_events(events), skip..skip + window.len());
+    println!("Number of annotations {:?}", markers.events.len());
+    Ok((eeg_info, EEGData { data }, markers, full_header))
+}
+
+/// Reads an EDF or EDF+ file. Signals sampled slower than the fastest signal are skipped,
+/// the `EDF Annotations` signal is decoded into markers.
+pub fn parse_edf(path: &str) -> Result<(EEGInfo, EEGData, Markers), Error> {
+    let (eeg_info, eeg_data, markers, header) = read_edf_file(path, &Selection::default())?;
+    if header.is_bdf() {
+        return Err(Error::UnsupportedFormat(format!("{path} is a BDF file, use the bdf format")));
+    }
+    Ok((eeg_info, eeg_data, markers))
+}
+
+/// Reads the selected channels and time window of an EDF, EDF+ or BDF file.
+/// Marker positions are relative to the start of the window.
+pub fn read_edf_selection(path: &str, selection: &Selection) -> Result<(EEGInfo, EEGData, Markers), Error> {
+    let (eeg_info, eeg_data, markers, _header) = read_edf_file(path, selection)?;
+    Ok((eeg_info, eeg_data, markers))
+}
+
+/// Reads a `BioSemi` BDF file, the trigger codes of the `Status` channel become markers.
+pub fn parse_bdf(path: &str) -> Result<(EEGInfo, EEGData, Markers), Error> {
+    let (eeg_info, eeg_data, markers, header) = read_edf_file(path, &Selection::default())?;
+    if !header.is_bdf() {
+        return Err(Error::UnsupportedFormat(format!("{path} is not a BDF file")));
+    }
+    Ok((eeg_info, eeg_data, markers))
+}
+
+/// Formats `value` into an EDF header number field of `width` characters, dropping decimals
+/// until it fits. Minima are rounded down and maxima up so no sample is clipped.
+/// The value is parsed back from the text so the header holds exactly what is written.
+fn fit_number(value: f64, width: usize, round_up: bool) -> Result<f64, Error> {
+    for decimals in (0..=6).rev() {
+        let factor = 10f64.powi(decimals);
+        let rounded = if round_up { (value * factor).ceil() } else { (value * factor).floor() } / factor;
+        let text = format!("{rounded:.prec$}", prec = decimals as usize);
+        if text.len() <= width {
+            return text.parse().map_err(|_err| Error::InvalidParameter(format!("{value} is not a valid EDF header number")));
+        }
+    }
+    Err(Error::InvalidParameter(format!("{value} does not fit into an EDF header field")))
+}
+
+/// Pads or truncates an ASCII header field, other characters are replaced (µV becomes uV).
+fn header_field(text: &str, width: usize) -> Vec<u8> {
+    let mut field: Vec<u8> = text
+        .chars()
+        .map(|c| match c {
+            'µ' | 'μ' => b'u',
+            c if c.is_ascii() && !c.is_ascii_control() => c as u8,
+            _ => b'_',
+        })
+        .take(width)
+        .collect();
+    field.resize(width, b' ');
+    field
+}
+
+/// Start date and time (dd.mm.yy, hh.mm.ss) from the `YYYYMMDDhhmmss...` date of the first
+/// `New Segment` marker, or the EDF+ default for unknown dates.
+fn start_date_time(markers: &Markers) -> (String, String) {
+    let date = markers
+        .events
+        .iter()
+        .find_map(|event| event.date.as_deref())
+        .filter(|date| date.len() >= 14 && date.is_ascii());
+    match date {
+        Some(d) => (
+            format!("{}.{}.{}", &d[6..8], &d[4..6], &d[2..4]),
+            format!("{}.{}.{}", &d[8..10], &d[10..12], &d[12..14]),
+        ),
+        None => (String::from("01.01.85"), String::from("00.00.00")),
+    }
+}
+
+/// The TALs of every data record: the time-keeping TAL followed by the markers starting
+/// inside the record. `New Segment` markers are dropped, the file is written continuous.
+/// Markers of one channel are written as `<text>@@<channel name>`.
+fn record_tals(markers: &Markers, ch_names: &[String], n_records: usize, samples_per_record: usize, sfreq: f64) -> Vec<Vec<u8>> {
+    let mut tals: Vec<Vec<u8>> = (0..n_records)
+        .map(|record| format!("+{}\u{14}\u{14}\u{0}", record as f64 * samples_per_record as f64 / sfreq).into_bytes())
+        .collect();
+    for event in markers.events.iter().filter(|event| event.kind != "New Segment") {
+        let record = (event.position / samples_per_record).min(n_records.saturating_sub(1));
+        let Some(tal) = tals.get_mut(record) else { continue };
+        let mut text = event.annotation_text();
+        if let Some(name) = event.channel.checked_sub(1).and_then(|idx| ch_names.get(idx)) {
+            text = format!("{text}{CHANNEL_SEPARATOR}{name}");
+        }
+        let onset = event.position as f64 / sfreq;
+        if event.duration > 1 {
+            tal.extend(format!("+{onset}\u{15}{}\u{14}{text}\u{14}\u{0}", event.duration as f64 / sfreq).bytes());
+        } else {
+            tal.extend(format!("+{onset}\u{14}{text}\u{14}\u{0}").bytes());
+        }
+    }
+    tals
+}
+
+/// Samples per data record and the record duration as written to the header for `n_samples`
+/// samples at `sfreq`.
+///
+/// Records get the longest duration of up to about a second that fits the 8 character field
+/// exactly, so the rate read back from the file is the one written, e.g. 1000 samples in
+/// 0.333 s for a 333 µs interval. The record length also divides `n_samples`, the file has
+/// no room for the true length and a padded last record would lengthen the recording, so
+/// 2500 samples at 1000 Hz go into records of 500 samples.
+fn record_layout(sfreq: f64, n_samples: usize) -> (usize, String) {
+    let per_second = (sfreq.round() as usize).max(1);
+    let duration_text = |spr: usize| {
+        let duration = spr as f64 / sfreq;
+        (0..=6)
+            .rev()
+            .map(|decimals| {
+                let text = format!("{duration:.decimals$}");
+                if text.contains('.') { text.trim_end_matches('0').trim_end_matches('.').to_owned() } else { text }
+            })
+            .find(|text| text.len() <= 8)
+    };
+    let layout = |spr: usize| {
+        let text = duration_text(spr)?;
+        let duration: f64 = text.parse().ok()?;
+        ((spr as f64 / duration - sfreq).abs() <= sfreq * 1e-9).then_some((spr, text))
+    };
+    // The number of records has to fit its 8 character field too
+    let divides = |spr: &usize| n_samples % spr == 0 && n_samples / spr <= 99_999_999;
+    (1..=per_second)
+        .rev()
+        .filter(divides)
+        .find_map(layout)
+        .or_else(|| (1..=per_second).rev().find_map(layout))
+        .unwrap_or_else(|| (per_second, duration_text(per_second).unwrap_or_else(|| String::from("1"))))
+}
+
+/// Writes `data` with its markers as EDF+ (`bdf == false`, 16 bit) or BDF+ (24 bit).
+///
+/// Every channel gets its own physical range from the data, which is in the unit of the
+/// channel, data records last up to a second and hold the recording without padding (see
+/// `record_layout`) and the markers go into an `EDF Annotations` signal.
+pub fn write_edf(
+    path: &str,
+    eeg_info: &EEGInfo,
+    data: &Array2<f32>,
+    markers: &Markers,
+    bdf: bool,
+) -> Result<(), Error> {
+    if data.nrows() != eeg_info.channels.len() {
+        return Err(Error::size_mismatch("Channels of the written data", eeg_info.channels.len(), data.nrows()));
+    }
+    if eeg_info.sfreq <= 0.0 {
+        return Err(Error::InvalidParameter("Sampling rate must be positive to write an EDF file".into()));
+    }
+    let sample_bytes = if bdf { 3 } else { 2 };
+    let (digital_min, digital_max) = if bdf { (-8_388_608.0, 8_388_607.0) } else { (-32768.0, 32767.0) };
+    let sfreq = eeg_info.sfreq;
+    let (samples_per_record, record_duration) = record_layout(sfreq, data.ncols());
+    let n_records = data.ncols().div_ceil(samples_per_record);
+
+    let tals = record_tals(markers, &eeg_info.ch_names, n_records, samples_per_record, sfreq);
+    let annot_spr = tals.iter().map(Vec::len).max().unwrap_or(0).div_ceil(sample_bytes);
+
+    // Physical ranges, parsed back from the header text so the scaling matches the file
+    let mut signals = Vec::with_capacity(eeg_info.channels.len() + 1);
+    for (channel, row) in eeg_info.channels.iter().zip(data.rows()) {
+        let (mut low, mut high) = row.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| {
+            (lo.min(f64::from(v)), hi.max(f64::from(v)))
+        });
+        // Flat (or empty) channels still need a valid physical range
+        if high <= low {
+            (low, high) = (low.min(0.0) - 1.0, high.max(0.0) + 1.0);
+        }
+        signals.push(EdfSignal {
+            label: channel.name.clone(),
+            transducer: String::new(),
+            physical_dimension: channel.unit.clone(),
+            physical_min: fit_number(low, 8, false)?,
+            physical_max: fit_number(high, 8, true)?,
+            digital_min,
+            digital_max,
+            prefiltering: String::new(),
+            samples_per_record,
+        });
+    }
+    signals.push(EdfSignal {
+        label: String::from(if bdf { BDF_ANNOTATION_LABEL } else { ANNOTATION_LABEL }),
+        transducer: String::new(),
+        physical_dimension: String::new(),
+        physical_min: -1.0,
+        physical_max: 1.0,
+        digital_min,
+        digital_max,
+        prefiltering: String::new(),
+        samples_per_record: annot_spr,
+    });
+
+    let ns = signals.len();
+    let (start_date, start_time) = start_date_time(markers);
+    let mut header: Vec<u8> = Vec::with_capacity(256 * (ns + 1));
+    if bdf {
+        header.push(0xFF);
+        header.extend(header_field("BIOSEMI", 7));
+    } else {
+        header.extend(header_field("0", 8));
+    }
+    header.extend(header_field("X X X X", 80));
+    header.extend(header_field("Startdate X X X X", 80));
+    header.extend(header_field(&start_date, 8));
+    header.extend(header_field(&start_time, 8));
+    header.extend(header_field(&(256 * (ns + 1)).to_string(), 8));
+    header.extend(header_field(if bdf { "BDF+C" } else { "EDF+C" }, 44));
+    header.extend(header_field(&n_records.to_string(), 8));
+    header.extend(header_field(&record_duration, 8));
+    header.extend(header_field(&ns.to_string(), 4));
+    for sig in &signals { header.extend(header_field(&sig.label, 16)); }
+    for sig in &signals { header.extend(header_field(&sig.transducer, 80)); }
+    for sig in &signals { header.extend(header_field(&sig.physical_dimension, 8)); }
+    for sig in &signals { header.extend(header_field(&sig.physical_min.to_string(), 8)); }
+    for sig in &signals { header.extend(header_field(&sig.physical_max.to_string(), 8)); }
+    for sig in &signals { header.extend(header_field(&sig.digital_min.to_string(), 8)); }
+    for sig in &signals { header.extend(header_field(&sig.digital_max.to_string(), 8)); }
+    for sig in &signals { header.extend(header_field(&sig.prefiltering, 80)); }
+    for sig in &signals { header.extend(header_field(&sig.samples_per_record.to_string(), 8)); }
+    for _ in &signals { header.extend(header_field("", 32)); }
+
+    let mut writer = BufWriter::new(File::create(path).map_err(Error::io(path))?);
+    writer.write_all(&header).map_err(Error::io(path))?;
+    let mut record_buffer: Vec<u8> = Vec::new();
+    for (record, tal) in tals.iter().enumerate() {
+        record_buffer.clear();
+        let start = record * samples_per_record;
+        for (ch_idx, signal) in signals.iter().take(eeg_info.channels.len()).enumerate() {
+            let (gain, offset) = (signal.gain(), signal.offset());
+            for idx in start..start + samples_per_record {
+                // Only padded when no record length divides the samples
+                let phys = data.get([ch_idx, idx]).map_or(0.0, |&v| f64::from(v));
+                let digital = ((phys - offset) / gain).round().clamp(digital_min, digital_max) as i32;
+                record_buffer.extend_from_slice(&digital.to_le_bytes()[..sample_bytes]);
+            }
+        }
+        let mut annotation = tal.clone();
+        annotation.resize(annot_spr * sample_bytes, 0);
+        record_buffer.extend(annotation);
+        writer.write_all(&record_buffer).map_err(Error::io(path))?;
+    }
+    writer.flush().map_err(Error::io(path))?;
+
+    println!("Wrote {path} with {n_records} data records");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EdfHeader, EdfSignal, STATUS_LABEL, parse_edf, parse_tals, status_markers, write_edf};
+    use crate::io::parse_header;
+    use crate::io::tests::{HEADER, temp_path};
+    use crate::{Error, Marker, Markers};
+
+    fn marker(kind: &str, description: &str, position: usize, duration: usize, channel: usize) -> Marker {
+        Marker {
+            kind: kind.to_owned(),
+            description: description.to_owned(),
+            position,
+            duration,
+            channel,
+            date: None,
+        }
+    }
+
+    #[test]
+    fn parses_tal_onsets_and_durations() -> Result<(), Error> {
+        let tals = parse_tals(b"+0\x14\x14\0+1.5\x152.25\x14S  1\x14bad\x14\0-0.5\x14x\x14\0\0\0")?;
+
+        assert_eq!(tals.len(), 3);
+        assert!(tals[0].texts.is_empty());
+        assert_eq!((tals[1].onset, tals[1].duration), (1.5, 2.25));
+        assert_eq!(tals[1].texts, ["S  1", "bad"]);
+        assert_eq!((tals[2].onset, tals[2].duration), (-0.5, 0.0));
+        assert!(parse_tals(b"+1.5\x15long\x14x\x14\0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips_edf_plus_with_annotations() -> Result<(), Error> {
+        let eeg_info = parse_header(&Some(HEADER.to_owned()))?;
+        let data = ndarray::Array2::from_shape_fn((3, 2500), |(ch, idx)| ((idx % 200) as f32 - 100.0) * (ch + 1) as f32);
+        let markers = Markers::from_events(vec![
+            marker("Stimulus", "S  1", 100, 1, 0),
+            marker("Comment", "bad movement", 1200, 500, 2),
+        ]);
+        let path = temp_path("round-trip.edf");
+
+        write_edf(&path, &eeg_info, &data, &markers, false)?;
+        let (read_info, read_data, read_markers) = parse_edf(&path)?;
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(read_info.ch_names, eeg_info.ch_names);
+        assert_eq!(read_data.data.dim(), (3, 2500));
+        // 16 bit samples resolve the 600 units of the widest channel to about 0.01
+        let error = (&read_data.data - &data).mapv(f32::abs).fold(0.0, |max: f32, &x| max.max(x));
+        assert!(error < 0.01, "{error}");
+        let read: Vec<_> = read_markers
+            .events
+            .iter()
+            .map(|event| (event.description.as_str(), event.position, event.duration, event.channel))
+            .collect();
+        assert_eq!(read, [("S  1", 100, 1, 0), ("bad movement", 1200, 500, 2)]);
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_bdf_status_triggers() {
+        let status = EdfSignal {
+            label: String::from(STATUS_LABEL),
+            transducer: String::new(),
+            physical_dimension: String::from("Boolean"),
+            physical_min: -8_388_608.0,
+            physical_max: 8_388_607.0,
+            digital_min: -8_388_608.0,
+            digital_max: 8_388_607.0,
+            prefiltering: String::new(),
+            samples_per_record: 4,
+        };
+        let header = EdfHeader {
+            version: String::from("\u{ff}BIOSEMI"),
+            sample_bytes: 3,
+            patient: String::new(),
+            recording: String::new(),
+            start_date: String::new(),
+            start_time: String::new(),
+            header_bytes: 0,
+            reserved: String::from("24BIT"),
+            n_records: 2,
+            record_duration: 1.0,
+            signals: vec![status],
+        };
+        // Bits above the lowest 16 are status flags of the amplifier, not trigger codes
+        let codes: [u32; 8] = [0, 1, 1, 0, 0x01_0002, 2, 0, 0xFF_0001];
+        let bytes: Vec<u8> = codes.iter().flat_map(|code| code.to_le_bytes()[..3].to_vec()).collect();
+
+        let events = status_markers(&bytes, &header, 4);
+
+        assert_eq!(events, [
+            marker("Stimulus", "S  1", 1, 2, 0),
+            marker("Stimulus", "S  2", 4, 2, 0),
+            marker("Stimulus", "S  1", 7, 1, 0),
+        ]);
+    }
+}
@@ -0,0 +1,170 @@
+// Memory-mapped BrainVision data
+//
+// The .eeg file is mapped into memory instead of being read, so opening it costs nothing
+// regardless of its size. Samples are only decoded and calibrated (and their pages only
+// touched by the OS) when a window of them is requested, e.g. the ten seconds shown by
+// the viewer.
+
+use std::fs::File;
+use std::ops::Range;
+
+use memmap2::Mmap;
+use ndarray::{Array2, ArrayView2};
+
+use crate::{EEGInfo, Error};
+use super::{SampleDecoder, bytes_per_sample, channel_resolutions, is_vectorized, sample_decoder};
+
+/// Sample types that can be viewed in place for the `BinaryFormat` of the same name.
+pub trait MappedSample: bytemuck::Pod {
+    const BINARY_FORMAT: &'static str;
+}
+
+impl MappedSample for i16 {
+    const BINARY_FORMAT: &'static str = "INT_16";
+}
+
+impl MappedSample for i32 {
+    const BINARY_FORMAT: &'static str = "INT_32";
+}
+
+impl MappedSample for f32 {
+    const BINARY_FORMAT: &'static str = "IEEE_FLOAT_32";
+}
+
+/// A `BrainVision` .eeg file mapped into memory.
+pub struct MappedEeg {
+    mmap: Mmap,
+    binary_format: String,
+    sample_bytes: usize,
+    decode: SampleDecoder,
+    /// Resolution of every channel
+    resolutions: Vec<f32>,
+    vectorized: bool,
+    n_channels: usize,
+    n_samples: usize,
+}
+
+impl MappedEeg {
+    /// Maps the data file described by `eeg_info`. A trailing partial sample (multiplexed)
+    /// or partial channel (vectorized) is ignored.
+    pub fn open(path: &str, eeg_info: &EEGInfo) -> Result<Self, Error> {
+        let sample_bytes = bytes_per_sample(&eeg_info.binary_format)?;
+        let decode = sample_decoder(&eeg_info.binary_format)?;
+        let vectorized = is_vectorized(eeg_info)?;
+        if eeg_info.num_ch <= 0 {
+            return Err(Error::InvalidParameter("Number of channels cannot be zero".into()));
+        }
+        let n_channels = eeg_info.num_ch as usize;
+
+        let file = File::open(path).map_err(Error::io(path))?;
+        #[expect(unsafe_code, reason = "memmap2 has no safe constructor")]
+        // SAFETY: the map is read only; like every reader we assume the recording is not
+        // truncated by another process while it is open.
+        let mmap = unsafe { Mmap::map(&file).map_err(Error::io(path))? };
+        let n_samples = mmap.len() / sample_bytes / n_channels;
+
+        Ok(Self {
+            mmap,
+            binary_format: eeg_info.binary_format.clone(),
+            sample_bytes,
+            decode,
+            resolutions: channel_resolutions(eeg_info),
+            vectorized,
+            n_channels,
+            n_samples,
+        })
+    }
+
+    pub fn n_channels(&self) -> usize {
+        self.n_channels
+    }
+
+    /// Number of samples per channel
+    pub fn n_samples(&self) -> usize {
+        self.n_samples
+    }
+
+    /// Byte offset of sample `idx` of channel `ch`
+    fn offset(&self, ch: usize, idx: usize) -> usize {
+        let position = if self.vectorized {
+            ch * self.n_samples + idx
+        } else {
+            idx * self.n_channels + ch
+        };
+        position * self.sample_bytes
+    }
+
+    /// Resolution of channel `ch`, 1 for channels the header does not describe.
+    fn resolution(&self, ch: usize) -> f32 {
+        self.resolutions.get(ch).copied().unwrap_or(1.0)
+    }
+
+    /// Decodes a single sample in the unit of its channel.
+    pub fn sample(&self, ch: usize, idx: usize) -> Option<f32> {
+        if ch >= self.n_channels || idx >= self.n_samples {
+            return None;
+        }
+        let start = self.offset(ch, idx);
+        Some((self.decode)(&self.mmap[start..start + self.sample_bytes]) * self.resolution(ch))
+    }
+
+    /// Decodes the samples of channel `ch` in `range` (clamped to the recording) in the
+    /// unit of the channel.
+    pub fn channel_window(&self, ch: usize, range: Range<usize>) -> Vec<f32> {
+        let end = range.end.min(self.n_samples);
+        if ch >= self.n_channels || range.start >= end {
+            return Vec::new();
+        }
+        let resolution = self.resolution(ch);
+        if self.vectorized {
+            let bytes = &self.mmap[self.offset(ch, range.start)..self.offset(ch, end)];
+            bytes.chunks_exact(self.sample_bytes).map(|sample| (self.decode)(sample) * resolution).collect()
+        } else {
+            let frame = self.n_channels * self.sample_bytes;
+            let bytes = &self.mmap[self.offset(0, range.start)..self.offset(0, end)];
+            bytes
+                .chunks_exact(frame)
+                .map(|frame| (self.decode)(&frame[ch * self.sample_bytes..(ch + 1) * self.sample_bytes]) * resolution)
+                .collect()
+        }
+    }
+
+    /// Decodes all channels in `range` into a channels x samples array.
+    pub fn window(&self, range: Range<usize>) -> Array2<f32> {
+        let start = range.start.min(self.n_samples);
+        let end = range.end.clamp(start, self.n_samples);
+        let mut out = Array2::zeros((self.n_channels, end - start));
+        for (ch, mut row) in out.rows_mut().into_iter().enumerate() {
+            for (value, sample) in row.iter_mut().zip(self.channel_window(ch, start..end)) {
+                *value = sample;
+            }
+        }
+        out
+    }
+
+    /// Decodes the whole recording.
+    pub fn to_array(&self) -> Array2<f32> {
+        self.window(0..self.n_samples)
+    }
+
+    /// Zero-copy channels x samples view of the raw (uncalibrated) samples.
+    ///
+    /// `T` must match the `BinaryFormat` of the header. Fails on big endian targets,
+    /// where the little endian samples cannot be used in place.
+    pub fn view<T: MappedSample>(&self) -> Result<ArrayView2<'_, T>, Error> {
+        if self.binary_format != T::BINARY_FORMAT {
+            return Err(Error::UnsupportedFormat(format!("{} data viewed as {}", self.binary_format, T::BINARY_FORMAT)));
+        }
+        if cfg!(target_endian = "big") {
+            return Err(Error::UnsupportedFormat("zero-copy views on a big endian target".into()));
+        }
+        let used = self.n_channels * self.n_samples * self.sample_bytes;
+        let samples: &[T] = bytemuck::try_cast_slice(&self.mmap[..used]).map_err(|err| Error::Malformed(format!("{err:?}")))?;
+        let view = if self.vectorized {
+            ArrayView2::from_shape((self.n_channels, self.n_samples), samples)?
+        } else {
+            ArrayView2::from_shape((self.n_samples, self.n_channels), samples)?.reversed_axes()
+        };
+        Ok(view)
+    }
+}
@@ -0,0 +1,454 @@
+// NumPy .npy and .npz files
+//
+// A .npy file is a magic string, a Python dict literal describing dtype, memory order and
+// shape, padded to a multiple of 64 bytes, followed by the raw little endian array. A .npz
+// file is a zip archive of .npy files; like `np.savez` the members are stored uncompressed,
+// so neither side needs a deflate implementation. `np.load` reads both directly.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use ndarray::{ArrayD, ArrayViewD, IxDyn};
+
+use crate::epochs::epoch_times;
+use crate::{EpochsData, Error, EvokedData};
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4b50;
+const ZIP_CENTRAL_HEADER: u32 = 0x0201_4b50;
+const ZIP_END_RECORD: u32 = 0x0605_4b50;
+
+/// An array to be written, borrowed from the data it describes.
+pub enum NpyView<'a> {
+    F32(ArrayViewD<'a, f32>),
+    F64(ArrayViewD<'a, f64>),
+    I64(ArrayViewD<'a, i64>),
+    /// Written as a 1-d unicode array (`<U`)
+    Str(&'a [String]),
+}
+
+/// An array read from a .npy file. Integer arrays are read as `F64`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NpyArray {
+    F32(ArrayD<f32>),
+    F64(ArrayD<f64>),
+    Str(Vec<String>),
+}
+
+impl NpyArray {
+    fn into_f64(self, name: &str) -> Result<ArrayD<f64>, Error> {
+        match self {
+            Self::F32(array) => Ok(array.mapv(f64::from)),
+            Self::F64(array) => Ok(array),
+            Self::Str(_) => Err(Error::Malformed(format!("{name} holds text, expected numbers"))),
+        }
+    }
+
+    fn into_strings(self, name: &str) -> Result<Vec<String>, Error> {
+        match self {
+            Self::Str(strings) => Ok(strings),
+            _ => Err(Error::Malformed(format!("{name} holds numbers, expected text"))),
+        }
+    }
+}
+
+fn shape_literal(shape: &[usize]) -> String {
+    match shape {
+        [n] => format!("({n},)"),
+        _ => format!("({})", shape.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+/// Serialises one array in .npy format (version 1.0).
+pub fn npy_bytes(array: &NpyView<'_>) -> Vec<u8> {
+    let (descr, shape) = match array {
+        NpyView::F32(a) => (String::from("<f4"), a.shape().to_vec()),
+        NpyView::F64(a) => (String::from("<f8"), a.shape().to_vec()),
+        NpyView::I64(a) => (String::from("<i8"), a.shape().to_vec()),
+        NpyView::Str(s) => {
+            let width = s.iter().map(|text| text.chars().count()).max().unwrap_or(0).max(1);
+            (format!("<U{width}"), vec![s.len()])
+        }
+    };
+    let mut header = format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': {}, }}", shape_literal(&shape));
+    // Magic, version and header length take 10 bytes, the header ends with a newline
+    let padding = (64 - (10 + header.len() + 1) % 64) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    let mut bytes = Vec::with_capacity(10 + header.len());
+    bytes.extend_from_slice(NPY_MAGIC);
+    bytes.extend_from_slice(&[1, 0]);
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    // `iter` walks in logical (C) order whatever the memory layout of the view
+    match array {
+        NpyView::F32(a) => a.iter().for_each(|x| bytes.extend_from_slice(&x.to_le_bytes())),
+        NpyView::F64(a) => a.iter().for_each(|x| bytes.extend_from_slice(&x.to_le_bytes())),
+        NpyView::I64(a) => a.iter().for_each(|x| bytes.extend_from_slice(&x.to_le_bytes())),
+        NpyView::Str(s) => {
+            let width = s.iter().map(|text| text.chars().count()).max().unwrap_or(0).max(1);
+            for text in *s {
+                let mut chars = text.chars().map(u32::from).collect::<Vec<_>>();
+                chars.resize(width, 0);
+                chars.iter().for_each(|c| bytes.extend_from_slice(&c.to_le_bytes()));
+            }
+        }
+    }
+    bytes
+}
+
+/// Value of `key` in the header dict, e.g. `'<f4'`, `False` or `(3, 4)`.
+fn header_value<'h>(header: &'h str, key: &str) -> Result<&'h str, Error> {
+    let start = header
+        .find(&format!("'{key}':"))
+        .ok_or_else(|| Error::Malformed(format!("npy header has no {key}: {header}")))?
+        + key.len()
+        + 3;
+    let rest = header[start..].trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|idx| idx + 1)
+    } else {
+        rest.find([',', '}'])
+    };
+    Ok(rest[..end.unwrap_or(rest.len())].trim())
+}
+
+/// Parses an array in .npy format (version 1, 2 or 3).
+pub fn parse_npy(bytes: &[u8]) -> Result<NpyArray, Error> {
+    if bytes.len() < 10 || !bytes.starts_with(NPY_MAGIC) {
+        return Err(Error::UnsupportedFormat("not a .npy file".into()));
+    }
+    let (header_len, header_start) = match bytes[6] {
+        1 => (usize::from(u16::from_le_bytes([bytes[8], bytes[9]])), 10),
+        2 | 3 if bytes.len() >= 12 => (u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize, 12),
+        version => return Err(Error::UnsupportedFormat(format!(".npy version {version}"))),
+    };
+    let data_start = header_start + header_len;
+    let header = bytes.get(header_start..data_start).ok_or_else(|| Error::Malformed("Truncated .npy header".into()))?;
+    let header = std::str::from_utf8(header).map_err(|_err| Error::Malformed("The .npy header is not UTF-8".into()))?;
+    let descr = header_value(header, "descr")?.trim_matches(['\'', '"']);
+    let fortran = header_value(header, "fortran_order")? == "True";
+    let shape: Vec<usize> = header_value(header, "shape")?
+        .trim_matches(['(', ')'])
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse().map_err(|_err| Error::Malformed(format!("Invalid .npy shape {header}"))))
+        .collect::<Result<_, _>>()?;
+    let count: usize = shape.iter().product();
+    let data = &bytes[data_start..];
+
+    // Fortran ordered data is the C ordered transpose
+    let dims = if fortran { shape.iter().rev().copied().collect() } else { shape.clone() };
+    let to_array = |values: Vec<f64>| -> Result<ArrayD<f64>, Error> {
+        let array = ArrayD::from_shape_vec(IxDyn(&dims), values)?;
+        Ok(if fortran { array.reversed_axes() } else { array })
+    };
+    let width: usize = descr.get(2..).unwrap_or_default().parse().unwrap_or(0);
+    let needed = count * if descr.starts_with("<U") { width * 4 } else { width };
+    if data.len() < needed {
+        return Err(Error::size_mismatch(".npy data bytes", needed, data.len()));
+    }
+    match descr {
+        "<f4" => {
+            let values = data.chunks_exact(4).take(count).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+            let array = ArrayD::from_shape_vec(IxDyn(&dims), values.collect())?;
+            Ok(NpyArray::F32(if fortran { array.reversed_axes() } else { array }))
+        }
+        "<f8" => to_array(data.chunks_exact(8).take(count).map(|b| f64::from_le_bytes(b.try_into().unwrap_or_default())).collect()).map(NpyArray::F64),
+        "<i4" => to_array(data.chunks_exact(4).take(count).map(|b| f64::from(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))).collect()).map(NpyArray::F64),
+        "<i8" => to_array(data.chunks_exact(8).take(count).map(|b| i64::from_le_bytes(b.try_into().unwrap_or_default()) as f64).collect()).map(NpyArray::F64),
+        _ if descr.starts_with("<U") && shape.len() == 1 => Ok(NpyArray::Str(
+            data.chunks_exact(width * 4)
+                .take(count)
+                .map(|text| {
+                    text.chunks_exact(4)
+                        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                        .take_while(|&c| c != 0)
+                        .filter_map(char::from_u32)
+                        .collect()
+                })
+                .collect(),
+        )),
+        _ => Err(Error::UnsupportedFormat(format!(".npy dtype {descr} with shape {shape:?}"))),
+    }
+}
+
+pub fn write_npy(path: &str, array: &NpyView<'_>) -> Result<(), Error> {
+    fs::write(path, npy_bytes(array)).map_err(Error::io(path))
+}
+
+pub fn read_npy(path: &str) -> Result<NpyArray, Error> {
+    parse_npy(&fs::read(path).map_err(Error::io(path))?)
+}
+
+/// CRC-32 (IEEE) as used by zip.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn truncated_zip() -> Error {
+    Error::Malformed("Truncated zip archive".into())
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16, Error> {
+    let b = bytes.get(offset..offset + 2).ok_or_else(truncated_zip)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, Error> {
+    let b = bytes.get(offset..offset + 4).ok_or_else(truncated_zip)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Writes the arrays as `<name>.npy` members of an uncompressed .npz archive.
+pub fn write_npz(path: &str, arrays: &[(&str, NpyView<'_>)]) -> Result<(), Error> {
+    let mut archive: Vec<u8> = Vec::new();
+    let mut central: Vec<u8> = Vec::new();
+    for (name, array) in arrays {
+        let file_name = format!("{name}.npy");
+        let data = npy_bytes(array);
+        let (Ok(size), Ok(offset)) = (u32::try_from(data.len()), u32::try_from(archive.len())) else {
+            return Err(Error::UnsupportedFormat(format!("{name} is too large for a .npz archive, write it with write_npy")));
+        };
+        let crc = crc32(&data);
+        // Version 2.0, no flags, stored, 1980-01-01 00:00
+        let mut fields = Vec::with_capacity(26);
+        fields.extend_from_slice(&20u16.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+        fields.extend_from_slice(&0x21u16.to_le_bytes());
+        fields.extend_from_slice(&crc.to_le_bytes());
+        fields.extend_from_slice(&size.to_le_bytes());
+        fields.extend_from_slice(&size.to_le_bytes());
+        fields.extend_from_slice(&(file_name.len() as u16).to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+
+        archive.extend_from_slice(&ZIP_LOCAL_HEADER.to_le_bytes());
+        archive.extend_from_slice(&fields);
+        archive.extend_from_slice(file_name.as_bytes());
+        archive.extend_from_slice(&data);
+
+        central.extend_from_slice(&ZIP_CENTRAL_HEADER.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes());
+        central.extend_from_slice(&fields);
+        // Comment length, disk, internal and external attributes
+        central.extend_from_slice(&[0; 10]);
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(file_name.as_bytes());
+    }
+    let central_offset = u32::try_from(archive.len())
+        .map_err(|_err| Error::UnsupportedFormat("archives larger than 4 GiB as .npz".into()))?;
+    let entries = arrays.len() as u16;
+    archive.extend_from_slice(&central);
+    archive.extend_from_slice(&ZIP_END_RECORD.to_le_bytes());
+    archive.extend_from_slice(&[0; 4]);
+    archive.extend_from_slice(&entries.to_le_bytes());
+    archive.extend_from_slice(&entries.to_le_bytes());
+    archive.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&central_offset.to_le_bytes());
+    archive.extend_from_slice(&0u16.to_le_bytes());
+    fs::write(path, archive).map_err(Error::io(path))
+}
+
+/// Reads every `.npy` member of a .npz archive, keyed by name without the extension.
+/// Archives written by `np.savez_compressed` are not supported.
+pub fn read_npz(path: &str) -> Result<BTreeMap<String, NpyArray>, Error> {
+    let bytes = fs::read(path).map_err(Error::io(path))?;
+    let end = (0..bytes.len().saturating_sub(21))
+        .rev()
+        .find(|&idx| u32_at(&bytes, idx).is_ok_and(|sig| sig == ZIP_END_RECORD))
+        .ok_or_else(|| Error::UnsupportedFormat(format!("{path} is not a zip archive")))?;
+    let entries = u16_at(&bytes, end + 10)?;
+    let mut offset = u32_at(&bytes, end + 16)? as usize;
+
+    let mut arrays = BTreeMap::new();
+    for _ in 0..entries {
+        if u32_at(&bytes, offset)? != ZIP_CENTRAL_HEADER {
+            return Err(Error::Malformed(format!("Corrupt zip central directory in {path}")));
+        }
+        let method = u16_at(&bytes, offset + 10)?;
+        let size = u32_at(&bytes, offset + 20)? as usize;
+        let name_len = usize::from(u16_at(&bytes, offset + 28)?);
+        let extra_len = usize::from(u16_at(&bytes, offset + 30)?);
+        let comment_len = usize::from(u16_at(&bytes, offset + 32)?);
+        let local = u32_at(&bytes, offset + 42)? as usize;
+        let name_bytes = bytes.get(offset + 46..offset + 46 + name_len).ok_or_else(truncated_zip)?;
+        let name = String::from_utf8_lossy(name_bytes).into_owned();
+        offset += 46 + name_len + extra_len + comment_len;
+
+        if method != 0 {
+            return Err(Error::UnsupportedFormat(format!("{name} in {path} is compressed, save it with np.savez instead of np.savez_compressed")));
+        }
+        let data_start = local + 30 + usize::from(u16_at(&bytes, local + 26)?) + usize::from(u16_at(&bytes, local + 28)?);
+        let data = bytes.get(data_start..data_start + size).ok_or_else(truncated_zip)?;
+        let key = name.strip_suffix(".npy").unwrap_or(&name).to_owned();
+        arrays.insert(key, parse_npy(data)?);
+    }
+    Ok(arrays)
+}
+
+/// Writes epochs and/or evoked data with their times and channel names to a .npz archive
+/// (`epochs`, `evoked`, `times`, `ch_names`, `sfreq`).
+///
+/// Epochs come with their events array (`events`, epochs x 3) and event id (`event_names`
+/// and `event_codes`).
+pub fn write_epochs_npz(
+    path: &str,
+    epochs: Option<&EpochsData>,
+    evoked: Option<&EvokedData>,
+    sfreq: f64,
+) -> Result<(), Error> {
+    let (tmin, n_times, ch_names) = match (epochs, evoked) {
+        (Some(epochs), _) => (epochs.tmin, epochs.epochs.dim().2, &epochs.ch_names),
+        (None, Some(evoked)) => (evoked.tmin, evoked.evoked.ncols(), &evoked.ch_names),
+        (None, None) => return Err(Error::InvalidParameter("Nothing to export, neither epochs nor evoked data given".into())),
+    };
+    let times = ndarray::Array1::from(epoch_times(tmin, n_times, sfreq)).into_dyn();
+    let sfreq = ndarray::arr0(sfreq).into_dyn();
+    let event_names: Vec<String> = epochs.map(|epochs| epochs.event_id.keys().cloned().collect()).unwrap_or_default();
+    let event_codes = ndarray::Array1::from_iter(epochs.iter().flat_map(|epochs| epochs.event_id.values().copied())).into_dyn();
+    let mut arrays = vec![
+        ("times", NpyView::F64(times.view())),
+        ("ch_names", NpyView::Str(ch_names)),
+        ("sfreq", NpyView::F64(sfreq.view())),
+    ];
+    if let Some(epochs) = epochs {
+        arrays.push(("epochs", NpyView::F32(epochs.epochs.view().into_dyn())));
+        arrays.push(("events", NpyView::I64(epochs.events.view().into_dyn())));
+        arrays.push(("event_names", NpyView::Str(&event_names)));
+        arrays.push(("event_codes", NpyView::I64(event_codes.view())));
+    }
+    if let Some(evoked) = evoked {
+        arrays.push(("evoked", NpyView::F64(evoked.evoked.view().into_dyn())));
+    }
+    write_npz(path, &arrays)?;
+    println!("Wrote {path}");
+    Ok(())
+}
+
+/// Contents of a .npz archive written by `write_epochs_npz`.
+#[derive(Debug)]
+pub struct EpochsNpz {
+    pub epochs: Option<EpochsData>,
+    pub evoked: Option<EvokedData>,
+    pub times: Vec<f64>,
+    pub sfreq: f64,
+}
+
+pub fn read_epochs_npz(path: &str) -> Result<EpochsNpz, Error> {
+    let mut arrays = read_npz(path)?;
+    let mut take = |name: &str| arrays.remove(name).ok_or_else(|| Error::NotFound(format!("{path} has no {name} array")));
+
+    let times = take("times")?.into_f64("times")?.iter().copied().collect::<Vec<_>>();
+    let ch_names = take("ch_names")?.into_strings("ch_names")?;
+    let sfreq = take("sfreq")?.into_f64("sfreq")?.iter().copied().next().ok_or_else(|| Error::Malformed("Empty sfreq array".into()))?;
+    // EpochsData counts tmin before and tmax after the event
+    let tmin = -times.first().copied().unwrap_or(0.0);
+    let tmax = (times.len() as f64 - (tmin * sfreq).round()) / sfreq;
+
+    let epochs = match take("epochs") {
+        Ok(array) => {
+            let epochs = match array {
+                NpyArray::F32(array) => array.into_dimensionality()?,
+                other => other.into_f64("epochs")?.mapv(|x| x as f32).into_dimensionality()?,
+            };
+            // Integer arrays are read as f64, codes and samples are exact below 2^53
+            let events = take("events")?.into_f64("events")?.mapv(|x| x as i64).into_dimensionality()?;
+            let names = take("event_names")?.into_strings("event_names")?;
+            let codes = take("event_codes")?.into_f64("event_codes")?;
+            let event_id = names.into_iter().zip(codes.iter().map(|&code| code as i64)).collect();
+            Some(EpochsData { epochs, ch_names: ch_names.clone(), tmin, tmax, events, event_id })
+        }
+        Err(_) => None,
+    };
+    let evoked = match take("evoked") {
+        Ok(array) => Some(EvokedData { evoked: array.into_f64("evoked")?.into_dimensionality()?, ch_names, tmin, tmax }),
+        Err(_) => None,
+    };
+    Ok(EpochsNpz { epochs, evoked, times, sfreq })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ndarray::{Array2, Array3, array};
+
+    use super::{NpyArray, NpyView, npy_bytes, parse_npy, read_epochs_npz, read_npz, write_epochs_npz, write_npz};
+    use crate::io::tests::temp_path;
+    use crate::{EpochsData, Error, EvokedData};
+
+    #[test]
+    fn pads_the_npy_header_to_64_bytes() -> Result<(), Error> {
+        let array = array![[1.5_f64, -2.0, 3.25], [4.0, 5.0, 6.0]].into_dyn();
+        let bytes = npy_bytes(&NpyView::F64(array.view()));
+
+        assert_eq!((10 + usize::from(u16::from_le_bytes([bytes[8], bytes[9]]))) % 64, 0);
+        assert_eq!(parse_npy(&bytes)?, NpyArray::F64(array));
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips_npz_arrays() -> Result<(), Error> {
+        let floats = array![[1.0_f32, 2.0], [3.0, 4.0]].into_dyn();
+        let codes = array![1_i64, 1001, 10001].into_dyn();
+        let names = vec![String::from("Fp1"), String::from("EMG,R µ")];
+        let path = temp_path("arrays.npz");
+
+        write_npz(&path, &[
+            ("floats", NpyView::F32(floats.view())),
+            ("codes", NpyView::I64(codes.view())),
+            ("names", NpyView::Str(&names)),
+        ])?;
+        let arrays = read_npz(&path)?;
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(arrays.keys().collect::<Vec<_>>(), ["codes", "floats", "names"]);
+        assert_eq!(arrays["floats"], NpyArray::F32(floats));
+        // Integer arrays are read as F64
+        assert_eq!(arrays["codes"], NpyArray::F64(codes.mapv(|code| code as f64)));
+        assert_eq!(arrays["names"], NpyArray::Str(names));
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips_epochs_and_evoked() -> Result<(), Error> {
+        let ch_names = vec![String::from("Fp1"), String::from("Cz")];
+        let epochs = EpochsData {
+            epochs: Array3::from_shape_fn((3, 2, 5), |(epoch, ch, idx)| (epoch * 100 + ch * 10 + idx) as f32),
+            ch_names: ch_names.clone(),
+            tmin: 0.002,
+            tmax: 0.003,
+            events: array![[100, 0, 1], [300, 1, 1001], [500, 1001, 1]],
+            event_id: BTreeMap::from([(String::from("single"), 1), (String::from("paired"), 1001)]),
+        };
+        let evoked = EvokedData { evoked: Array2::from_elem((2, 5), 0.5), ch_names, tmin: 0.002, tmax: 0.003 };
+        let path = temp_path("epochs.npz");
+
+        write_epochs_npz(&path, Some(&epochs), Some(&evoked), 1000.0)?;
+        let read = read_epochs_npz(&path)?;
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(read.times.len(), 5);
+        assert!((read.times[0] + 0.002).abs() < 1e-12);
+        let read_epochs = read.epochs.ok_or_else(|| Error::NotFound("No epochs read back.".into()))?;
+        assert_eq!(read_epochs.epochs, epochs.epochs);
+        assert_eq!(read_epochs.ch_names, epochs.ch_names);
+        assert_eq!(read_epochs.events, epochs.events);
+        assert_eq!(read_epochs.event_id, epochs.event_id);
+        assert!((read_epochs.tmin - 0.002).abs() < 1e-12 && (read_epochs.tmax - 0.003).abs() < 1e-12);
+        let read_evoked = read.evoked.ok_or_else(|| Error::NotFound("No evoked data read back.".into()))?;
+        assert_eq!(read_evoked.evoked, evoked.evoked);
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+// Selective loading
+//
+// Reads a subset of the channels and a time window straight from disk instead of loading
+// the whole recording first. BrainVision files are memory-mapped so only the pages of the
+// window are read, EDF/BDF files only read the data records that overlap the window.
+
+use std::ops::Range;
+
+use ndarray::Array2;
+
+use crate::{EEGData, EEGInfo, Error, Marker, Markers};
+use crate::signal::crop_markers;
+use super::MappedEeg;
+
+/// Channels and time window to read.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Selection {
+    /// Channel names to read, in this order. Empty reads every channel
+    pub channels: Vec<String>,
+    /// Start of the window in seconds
+    pub start: f64,
+    /// End of the window in seconds, `None` reads up to the end of the recording
+    pub stop: Option<f64>,
+}
+
+impl Selection {
+    /// `true` when the whole recording is selected.
+    pub fn is_everything(&self) -> bool {
+        self.channels.is_empty() && self.start <= 0.0 && self.stop.is_none()
+    }
+
+    /// Indices of the selected channels in `names`.
+    pub fn channel_indices(&self, names: &[String]) -> Result<Vec<usize>, Error> {
+        if self.channels.is_empty() {
+            return Ok((0..names.len()).collect());
+        }
+        self.channels
+            .iter()
+            .map(|wanted| {
+                names
+                    .iter()
+                    .position(|name| name == wanted)
+                    .ok_or_else(|| Error::NotFound(format!("Channel {wanted:?} not found, available channels: {names:?}")))
+            })
+            .collect()
+    }
+
+    /// Sample range of the window, the end is clamped to the `n_samples` of the recording.
+    pub fn sample_range(&self, sfreq: f64, n_samples: usize) -> Result<Range<usize>, Error> {
+        if self.start < 0.0 || self.stop.is_some_and(|stop| stop <= self.start) {
+            return Err(Error::InvalidParameter(format!("Invalid time window {:?} - {:?} s", self.start, self.stop)));
+        }
+        let start = (self.start * sfreq).round() as usize;
+        let end = self.stop.map_or(n_samples, |stop| ((stop * sfreq).round() as usize).min(n_samples));
+        if start >= end {
+            return Err(Error::InvalidParameter(format!("Window starts at {:?} s but the recording lasts {:?} s", self.start, n_samples as f64 / sfreq)));
+        }
+        Ok(start..end)
+    }
+}
+
+/// Header of the channels at `indices`, in that order.
+pub fn pick_channels(eeg_info: &EEGInfo, indices: &[usize]) -> EEGInfo {
+    let mut picked = eeg_info.clone();
+    picked.channels = indices.iter().map(|&idx| eeg_info.channels[idx].clone()).collect();
+    picked.ch_names = indices.iter().map(|&idx| eeg_info.ch_names[idx].clone()).collect();
+    picked.ch_namesx = indices.iter().filter_map(|&idx| eeg_info.ch_namesx.get(idx).cloned()).collect();
+    picked.num_ch = indices.len() as i32;
+    picked.bads.retain(|bad| picked.ch_names.contains(bad));
+    picked
+}
+
+/// Markers of the channels at `indices`: channel numbers point into the picked channels,
+/// markers of channels that were not picked are dropped.
+pub fn pick_markers(markers: &Markers, indices: &[usize]) -> Markers {
+    let events = markers
+        .events
+        .iter()
+        .filter_map(|event| {
+            let channel = match event.channel {
+                0 => 0,
+                channel => indices.iter().position(|&idx| idx + 1 == channel)? + 1,
+            };
+            Some(Marker { channel, ..event.clone() })
+        })
+        .collect();
+    Markers::from_events(events)
+}
+
+/// Reads the selected channels and window of a `BrainVision` .eeg file.
+///
+/// `eeg_info` and `markers` describe the whole recording, the returned ones the selection
+/// (marker positions are relative to the start of the window).
+pub fn read_brainvision_selection(
+    path: &str,
+    eeg_info: &EEGInfo,
+    markers: &Markers,
+    selection: &Selection,
+) -> Result<(EEGInfo, EEGData, Markers), Error> {
+    let mapped = MappedEeg::open(path, eeg_info)?;
+    let indices = selection.channel_indices(&eeg_info.ch_names)?;
+    let range = selection.sample_range(eeg_info.sfreq, mapped.n_samples())?;
+
+    let mut data = Array2::zeros((indices.len(), range.len()));
+    for (mut row, &ch) in data.rows_mut().into_iter().zip(&indices) {
+        for (value, sample) in row.iter_mut().zip(mapped.channel_window(ch, range.clone())) {
+            *value = sample;
+        }
+    }
+    Ok((pick_channels(eeg_info, &indices), EEGData { data }, crop_markers(&pick_markers(markers, &indices), range)))
+}
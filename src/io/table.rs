@@ -0,0 +1,235 @@
+// CSV/TSV export
+//
+// Writes continuous data, epochs and evoked responses as plain text tables for R,
+// pandas or a spreadsheet. Values are in the physical unit of every channel, as they are
+// held in memory, and times are in seconds relative to the start of the recording or to
+// the event. Two layouts are available:
+// - wide: one row per time point and one column per channel
+// - long: one row per time point and channel, with `channel`, `value` and `unit` columns
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufWriter, Write as _};
+use std::path::Path;
+
+use crate::epochs::epoch_times;
+use crate::{EEGInfo, EpochsData, Error, EvokedData};
+
+/// Shape of the written table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TableLayout {
+    /// One column per channel
+    #[default]
+    Wide,
+    /// One row per sample of every channel
+    Long,
+}
+
+impl std::str::FromStr for TableLayout {
+    type Err = Error;
+
+    fn from_str(layout: &str) -> Result<Self, Self::Err> {
+        match layout {
+            "wide" => Ok(Self::Wide),
+            "long" => Ok(Self::Long),
+            _ => Err(Error::InvalidParameter(format!("Unknown table layout {layout:?}, expected wide or long"))),
+        }
+    }
+}
+
+struct TableWriter {
+    out: BufWriter<File>,
+    separator: char,
+    layout: TableLayout,
+    ch_names: Vec<String>,
+    units: Vec<String>,
+}
+
+impl TableWriter {
+    /// Tab separated for `.tsv` and `.txt` paths, comma separated otherwise.
+    fn create(path: &str, eeg_info: &EEGInfo, ch_names: &[String], layout: TableLayout) -> Result<Self, Error> {
+        let separator = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("tsv") || ext.eq_ignore_ascii_case("txt") => '\t',
+            _ => ',',
+        };
+        // Channels are matched by name so picked or reordered data keeps its units
+        let channel = |name: &String| eeg_info.channels.iter().find(|channel| &channel.name == name);
+        Ok(Self {
+            out: BufWriter::new(File::create(path).map_err(Error::io(path))?),
+            separator,
+            layout,
+            ch_names: ch_names.to_vec(),
+            units: ch_names.iter().map(|name| channel(name).map_or_else(|| String::from("µV"), |ch| ch.unit.clone())).collect(),
+        })
+    }
+
+    /// Quotes fields containing the separator or quotes, like channel `EMG,R`.
+    fn field(&self, text: &str) -> String {
+        if text.contains([self.separator, '"', '\n']) {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        } else {
+            text.to_owned()
+        }
+    }
+
+    fn header(&mut self, with_epoch: bool) -> std::io::Result<()> {
+        let mut columns: Vec<String> = Vec::new();
+        if with_epoch {
+            columns.extend(["epoch", "condition"].map(String::from));
+        }
+        columns.push(String::from("time"));
+        match self.layout {
+            TableLayout::Wide => columns.extend(self.ch_names.iter().map(|name| self.field(name))),
+            TableLayout::Long => columns.extend(["channel", "value", "unit"].map(String::from)),
+        }
+        writeln!(self.out, "{}", columns.join(&self.separator.to_string()))
+    }
+
+    /// Writes one row (wide) or one row per channel (long) for every time point;
+    /// `value(ch, idx)` is the physical value of channel `ch` at sample `idx`.
+    fn rows<T: Display>(
+        &mut self,
+        epoch: Option<(usize, &str)>,
+        times: &[f64],
+        value: impl Fn(usize, usize) -> T,
+    ) -> std::io::Result<()> {
+        let sep = self.separator;
+        let prefix = epoch.map_or_else(String::new, |(epoch, condition)| format!("{epoch}{sep}{}{sep}", self.field(condition)));
+        for (idx, time) in times.iter().enumerate() {
+            match self.layout {
+                TableLayout::Wide => {
+                    write!(self.out, "{prefix}{time}")?;
+                    for ch in 0..self.ch_names.len() {
+                        write!(self.out, "{sep}{}", value(ch, idx))?;
+                    }
+                    writeln!(self.out)?;
+                }
+                TableLayout::Long => {
+                    for ch in 0..self.ch_names.len() {
+                        let (name, unit) = (self.field(&self.ch_names[ch]), self.field(&self.units[ch]));
+                        writeln!(self.out, "{prefix}{time}{sep}{name}{sep}{}{sep}{unit}", value(ch, idx))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self, path: &str) -> Result<(), Error> {
+        self.out.flush().map_err(Error::io(path))?;
+        println!("Wrote {path}");
+        Ok(())
+    }
+}
+
+/// Writes continuous channels x samples `data` with the channel names of `eeg_info`.
+pub fn write_raw_table(
+    path: &str,
+    eeg_info: &EEGInfo,
+    data: &ndarray::Array2<f32>,
+    layout: TableLayout,
+) -> Result<(), Error> {
+    if eeg_info.sfreq <= 0.0 {
+        return Err(Error::InvalidParameter("Sampling rate must be positive to write times".into()));
+    }
+    let sfreq = eeg_info.sfreq;
+    let mut table = TableWriter::create(path, eeg_info, &eeg_info.ch_names, layout)?;
+    let times: Vec<f64> = (0..data.ncols()).map(|idx| idx as f64 / sfreq).collect();
+    table.header(false).map_err(Error::io(path))?;
+    table.rows(None, &times, |ch, idx| data[[ch, idx]]).map_err(Error::io(path))?;
+    table.finish(path)
+}
+
+/// Writes every epoch with an `epoch` column counting from 0 and its `condition`, `n/a` for
+/// events without a name; times are relative to the event.
+pub fn write_epochs_table(
+    path: &str,
+    eeg_info: &EEGInfo,
+    epochs: &EpochsData,
+    layout: TableLayout,
+) -> Result<(), Error> {
+    let mut table = TableWriter::create(path, eeg_info, &epochs.ch_names, layout)?;
+    let (n_epochs, _, n_times) = epochs.epochs.dim();
+    let times = epoch_times(epochs.tmin, n_times, eeg_info.sfreq);
+    table.header(true).map_err(Error::io(path))?;
+    let conditions = epochs.conditions();
+    for epoch in 0..n_epochs {
+        let condition = conditions.get(epoch).copied().flatten().unwrap_or("n/a");
+        table.rows(Some((epoch, condition)), &times, |ch, idx| epochs.epochs[[epoch, ch, idx]]).map_err(Error::io(path))?;
+    }
+    table.finish(path)
+}
+
+/// Writes the evoked response; times are relative to the event.
+pub fn write_evoked_table(
+    path: &str,
+    eeg_info: &EEGInfo,
+    evoked: &EvokedData,
+    layout: TableLayout,
+) -> Result<(), Error> {
+    let mut table = TableWriter::create(path, eeg_info, &evoked.ch_names, layout)?;
+    let times = epoch_times(evoked.tmin, evoked.evoked.ncols(), eeg_info.sfreq);
+    table.header(false).map_err(Error::io(path))?;
+    table.rows(None, &times, |ch, idx| evoked.evoked[[ch, idx]]).map_err(Error::io(path))?;
+    table.finish(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    use ndarray::{Array2, Array3, array};
+
+    use super::{TableLayout, write_epochs_table, write_raw_table};
+    use crate::io::parse_header;
+    use crate::io::tests::{HEADER, temp_path};
+    use crate::{EpochsData, Error};
+
+    #[test]
+    fn writes_wide_csv_with_quoted_channels() -> Result<(), Error> {
+        let eeg_info = parse_header(&Some(HEADER.to_owned()))?;
+        let data: Array2<f32> = array![[1.0, 2.0], [3.0, 4.5], [-5.0, 6.0]];
+        let path = temp_path("raw.csv");
+
+        write_raw_table(&path, &eeg_info, &data, TableLayout::Wide)?;
+        let text = fs::read_to_string(&path).map_err(Error::io(&path))?;
+        fs::remove_file(&path).ok();
+
+        assert_eq!(text, "time,Fp1,Cz,\"EMG,R\"\n0,1,3,-5\n0.001,2,4.5,6\n");
+        Ok(())
+    }
+
+    #[test]
+    fn writes_long_tsv_epochs_with_conditions() -> Result<(), Error> {
+        let eeg_info = parse_header(&Some(HEADER.to_owned()))?;
+        let epochs = EpochsData {
+            epochs: Array3::from_shape_fn((2, 1, 2), |(epoch, _, idx)| (epoch * 10 + idx) as f32),
+            ch_names: vec![String::from("Cz")],
+            tmin: 0.001,
+            tmax: 0.001,
+            events: array![[100, 0, 1], [300, 1, 2]],
+            event_id: BTreeMap::from([(String::from("single"), 1)]),
+        };
+        let path = temp_path("epochs.tsv");
+
+        write_epochs_table(&path, &eeg_info, &epochs, TableLayout::Long)?;
+        let text = fs::read_to_string(&path).map_err(Error::io(&path))?;
+        fs::remove_file(&path).ok();
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines, [
+            "epoch\tcondition\ttime\tchannel\tvalue\tunit",
+            "0\tsingle\t-0.001\tCz\t0\tµV",
+            "0\tsingle\t0\tCz\t1\tµV",
+            "1\tn/a\t-0.001\tCz\t10\tµV",
+            "1\tn/a\t0\tCz\t11\tµV",
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_layouts() {
+        assert!("tall".parse::<TableLayout>().is_err());
+    }
+}
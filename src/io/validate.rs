@@ -0,0 +1,346 @@
+// Header/data consistency checks
+//
+// Compares what a `BrainVision` header describes with the files on disk before anything is
+// loaded: the `.eeg` size against the channel count and binary format, a trailing partial
+// sample frame, markers beyond the end of the data, duplicate channel names and data or
+// marker files the header refers to but that do not exist. Problems are collected into a
+// report instead of failing on the first one, so a broken recording can be diagnosed in
+// one go.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::{EEGInfo, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The recording can be read but something is off
+    Warning,
+    /// The recording cannot be read as described by its header
+    Error,
+}
+
+/// What a finding is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    /// `DataFile=`/`MarkerFile=` point at a file that does not exist, or only a fallback does
+    MissingFile,
+    /// Size of the `.eeg` file against channel count and binary format
+    DataSize,
+    /// The `.eeg` file ends in the middle of a sample frame (or channel, when vectorized)
+    PartialFrame,
+    /// The marker file cannot be parsed
+    Markers,
+    /// Markers positioned at or after the last sample
+    MarkersBeyondData,
+    /// The same name is used by more than one channel
+    DuplicateChannels,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub check: Check,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Result of `validate_brainvision`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ValidationReport {
+    pub header_file: PathBuf,
+    /// `DataFile=` resolved next to the header, or the fallback the readers use
+    pub data_file: Option<PathBuf>,
+    /// `MarkerFile=` resolved next to the header, or the fallback the readers use
+    pub marker_file: Option<PathBuf>,
+    pub n_channels: usize,
+    pub binary_format: String,
+    /// Size of the data file in bytes, `None` when it is missing
+    pub data_bytes: Option<u64>,
+    /// Complete samples per channel in the data file
+    pub n_samples: Option<usize>,
+    pub n_markers: Option<usize>,
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    /// `true` when there is no finding of severity `Error`.
+    pub fn is_valid(&self) -> bool {
+        self.findings.iter().all(|finding| finding.severity != Severity::Error)
+    }
+
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    fn push(&mut self, check: Check, severity: Severity, message: String) {
+        self.findings.push(Finding { check, severity, message });
+    }
+
+    /// Resolves a header entry the way `resolve_brainvision` does. Reading a fallback
+    /// instead of the file the entry names is a warning.
+    fn resolve(&mut self, entry: &str, name: &str, extension: &str) -> Option<PathBuf> {
+        let referenced = referenced_file(&self.header_file, name);
+        match super::resolve_referenced(&self.header_file, name, extension) {
+            Ok(path) if referenced.as_ref() == Some(&path) => Some(path),
+            Ok(path) => {
+                let message = if name.is_empty() {
+                    format!("The header has no {entry} entry, {} is used instead", path.display())
+                } else {
+                    format!("{entry}={name} not found, {} is used instead", path.display())
+                };
+                self.push(Check::MissingFile, Severity::Warning, message);
+                Some(path)
+            }
+            Err(_) => referenced,
+        }
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = |path: &Option<PathBuf>| path.as_ref().map_or_else(|| String::from("none"), |path| path.display().to_string());
+        writeln!(f, "Header:      {}", self.header_file.display())?;
+        writeln!(f, "Data file:   {}", path(&self.data_file))?;
+        writeln!(f, "Marker file: {}", path(&self.marker_file))?;
+        writeln!(f, "Channels:    {} ({})", self.n_channels, self.binary_format)?;
+        if let (Some(bytes), Some(n_samples)) = (self.data_bytes, self.n_samples) {
+            writeln!(f, "Data:        {bytes} bytes, {n_samples} samples per channel")?;
+        }
+        if let Some(n_markers) = self.n_markers {
+            writeln!(f, "Markers:     {n_markers}")?;
+        }
+        if self.findings.is_empty() {
+            return writeln!(f, "No problems found");
+        }
+        for finding in &self.findings {
+            let severity = match finding.severity {
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            };
+            writeln!(f, "{severity}: {}", finding.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// `DataFile=`/`MarkerFile=` of the header, relative to the directory of the header.
+fn referenced_file(vhdr: &Path, name: &str) -> Option<PathBuf> {
+    (!name.is_empty()).then(|| vhdr.with_file_name(name))
+}
+
+/// Checks the header `vhdr_path` against the files it refers to. Only a header that cannot
+/// be read or parsed is an `Err`, every other problem is a finding of the report.
+pub fn validate_brainvision(vhdr_path: &str) -> Result<ValidationReport, Error> {
+    let header = super::get_header(&Some(vhdr_path.to_owned()))?;
+    let eeg_info = super::parse_header(&header)?;
+    Ok(validate(Path::new(vhdr_path), &eeg_info))
+}
+
+fn validate(vhdr: &Path, eeg_info: &EEGInfo) -> ValidationReport {
+    let mut report = ValidationReport {
+        header_file: vhdr.to_path_buf(),
+        data_file: None,
+        marker_file: None,
+        n_channels: eeg_info.channels.len(),
+        binary_format: eeg_info.binary_format.clone(),
+        data_bytes: None,
+        n_samples: None,
+        n_markers: None,
+        findings: Vec::new(),
+    };
+    report.data_file = report.resolve("DataFile", &eeg_info.data_file, "eeg");
+    report.marker_file = report.resolve("MarkerFile", &eeg_info.marker_file, "vmrk");
+
+    check_data_file(&mut report, eeg_info);
+    check_markers(&mut report);
+
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for name in &eeg_info.ch_names {
+        *counts.entry(name).or_default() += 1;
+    }
+    for (name, count) in counts.into_iter().filter(|&(_, count)| count > 1) {
+        report.push(Check::DuplicateChannels, Severity::Warning, format!("Channel name {name:?} is used by {count} channels"));
+    }
+    report
+}
+
+fn check_data_file(report: &mut ValidationReport, eeg_info: &EEGInfo) {
+    let Some(data_file) = report.data_file.clone() else {
+        report.push(Check::MissingFile, Severity::Error, String::from("The header has no DataFile entry"));
+        return;
+    };
+    let bytes = match fs::metadata(&data_file) {
+        Ok(metadata) => metadata.len(),
+        Err(err) => {
+            report.push(Check::MissingFile, Severity::Error, format!("Data file {}: {err}", data_file.display()));
+            return;
+        }
+    };
+    report.data_bytes = Some(bytes);
+
+    let sample_bytes = match super::bytes_per_sample(&eeg_info.binary_format) {
+        Ok(sample_bytes) => sample_bytes as u64,
+        Err(err) => {
+            report.push(Check::DataSize, Severity::Error, err.to_string());
+            return;
+        }
+    };
+    let vectorized = match super::is_vectorized(eeg_info) {
+        Ok(vectorized) => vectorized,
+        Err(err) => {
+            report.push(Check::DataSize, Severity::Error, err.to_string());
+            return;
+        }
+    };
+    let n_channels = report.n_channels as u64;
+    let frame_bytes = sample_bytes * n_channels;
+    let n_samples = bytes / frame_bytes.max(1);
+    report.n_samples = Some(n_samples as usize);
+    if n_samples == 0 {
+        report.push(
+            Check::DataSize,
+            Severity::Error,
+            format!("Data file holds {bytes} bytes, less than one sample of {n_channels} channels of {sample_bytes} bytes"),
+        );
+    }
+    let rest = bytes % frame_bytes.max(1);
+    if rest > 0 {
+        let unit = if vectorized { "channel" } else { "sample frame" };
+        report.push(
+            Check::PartialFrame,
+            Severity::Warning,
+            format!("Data file ends in a partial {unit}: {rest} trailing bytes are not a multiple of {frame_bytes} ({n_channels} channels x {sample_bytes} bytes) and are ignored"),
+        );
+    }
+}
+
+fn check_markers(report: &mut ValidationReport) {
+    let Some(marker_file) = report.marker_file.clone() else { return };
+    if !marker_file.is_file() {
+        report.push(Check::MissingFile, Severity::Error, format!("Marker file {} does not exist", marker_file.display()));
+        return;
+    }
+    let markers = match super::get_vmrk(&Some(marker_file.to_string_lossy().into_owned())).and_then(|vmrk| super::parse_vmrk(&vmrk)) {
+        Ok(markers) => markers,
+        Err(err) => {
+            report.push(Check::Markers, Severity::Error, format!("Marker file {}: {err}", marker_file.display()));
+            return;
+        }
+    };
+    report.n_markers = Some(markers.events.len());
+    let Some(n_samples) = report.n_samples else { return };
+    for event in markers.events.iter().filter(|event| event.position >= n_samples) {
+        report.push(
+            Check::MarkersBeyondData,
+            Severity::Warning,
+            format!(
+                "{} marker {:?} at sample {} is beyond the last sample {}",
+                event.kind,
+                event.description,
+                event.position + 1,
+                n_samples
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{Check, Severity, ValidationReport, validate};
+    use crate::Error;
+    use crate::io::parse_header;
+    use crate::io::tests::{HEADER, temp_path};
+
+    const VMRK: &str = "Brain Vision Data Exchange Marker File, Version 1.0\n\n\
+        [Common Infos]\nDataFile=test.eeg\n\n\
+        [Marker Infos]\nMk1=Stimulus,S  1,51,1,0\nMk2=Stimulus,S  2,101,1,0\n";
+
+    /// Writes `header` as `test.vhdr` next to a `test.eeg` of `eeg_bytes` bytes and `vmrk`
+    /// as `test.vmrk` into the directory `name`, and validates it.
+    fn validate_files(name: &str, header: &str, eeg_bytes: Option<usize>, vmrk: Option<&str>) -> Result<ValidationReport, Error> {
+        let dir = PathBuf::from(temp_path(name));
+        fs::create_dir_all(&dir).map_err(Error::io(name))?;
+        let vhdr = dir.join("test.vhdr");
+        fs::write(&vhdr, header).map_err(Error::io(name))?;
+        if let Some(eeg_bytes) = eeg_bytes {
+            fs::write(dir.join("test.eeg"), vec![0_u8; eeg_bytes]).map_err(Error::io(name))?;
+        }
+        if let Some(vmrk) = vmrk {
+            fs::write(dir.join("test.vmrk"), vmrk).map_err(Error::io(name))?;
+        }
+        let report = validate(&vhdr, &parse_header(&Some(header.to_owned()))?);
+        fs::remove_dir_all(&dir).ok();
+        Ok(report)
+    }
+
+    fn findings(report: &ValidationReport) -> Vec<(Check, Severity)> {
+        report.findings.iter().map(|finding| (finding.check, finding.severity)).collect()
+    }
+
+    #[test]
+    fn accepts_a_consistent_recording() -> Result<(), Error> {
+        // 3 channels of INT_16 are 6 bytes per sample frame
+        let report = validate_files("valid", HEADER, Some(6 * 200), Some(VMRK))?;
+        assert_eq!(findings(&report), []);
+        assert_eq!((report.n_samples, report.n_markers), (Some(200), Some(2)));
+        assert!(report.is_valid());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_a_data_file_shorter_than_one_sample() -> Result<(), Error> {
+        let report = validate_files("short", HEADER, Some(4), Some(VMRK))?;
+        assert_eq!(report.n_samples, Some(0));
+        assert!(findings(&report).contains(&(Check::DataSize, Severity::Error)));
+        assert!(!report.is_valid());
+        Ok(())
+    }
+
+    #[test]
+    fn warns_about_a_trailing_partial_frame() -> Result<(), Error> {
+        let report = validate_files("partial", HEADER, Some(6 * 200 + 2), Some(VMRK))?;
+        assert_eq!(findings(&report), [(Check::PartialFrame, Severity::Warning)]);
+        assert_eq!(report.n_samples, Some(200));
+        assert!(report.is_valid());
+        Ok(())
+    }
+
+    #[test]
+    fn warns_about_markers_beyond_the_last_sample() -> Result<(), Error> {
+        let report = validate_files("beyond", HEADER, Some(6 * 100), Some(VMRK))?;
+        assert_eq!(findings(&report), [(Check::MarkersBeyondData, Severity::Warning)]);
+        assert!(report.findings[0].message.contains("\"S  2\""));
+        Ok(())
+    }
+
+    #[test]
+    fn warns_about_duplicate_channel_names() -> Result<(), Error> {
+        let header = HEADER.replace("Ch2=Cz", "Ch2=Fp1");
+        let report = validate_files("duplicates", &header, Some(6 * 200), Some(VMRK))?;
+        assert_eq!(findings(&report), [(Check::DuplicateChannels, Severity::Warning)]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_missing_data_and_marker_files() -> Result<(), Error> {
+        let report = validate_files("missing", HEADER, None, None)?;
+        assert_eq!(findings(&report), [(Check::MissingFile, Severity::Error), (Check::MissingFile, Severity::Error)]);
+        assert!(!report.is_valid());
+
+        // Without the entries the files named after the header are used
+        let header = HEADER.replace("DataFile=test.eeg\n", "").replace("MarkerFile=test.vmrk\n", "");
+        let report = validate_files("fallback", &header, Some(6 * 200), Some(VMRK))?;
+        assert_eq!(findings(&report), [(Check::MissingFile, Severity::Warning), (Check::MissingFile, Severity::Warning)]);
+        assert!(report.is_valid());
+        Ok(())
+    }
+}
@@ -1,42 +1,358 @@
 #![warn(clippy::all, rust_2018_idioms)]
-use ndarray::{Array2, Array3};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use ndarray::{Array2, Array3, Axis};
 
 pub mod app;
+pub mod error;
 pub mod io;
 pub mod signal;
 pub mod epochs;
+pub mod montage;
+pub mod picks;
 pub mod vis;
+pub mod stream;
 pub use app::TemplateApp;
+pub use error::Error;
+pub use picks::Picks;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EEGInfo {
     pub num_ch: i32,
     pub ch_namesx: Vec<String>,
     pub ch_names: Vec<String>,
-    pub sfreq: i32,
+    pub channels: Vec<ChannelInfo>,
+    /// Sampling rate in Hz, derived from `sampling_interval` and not necessarily integral
+    pub sfreq: f64,
+    pub data_file: String,
+    pub marker_file: String,
+    pub codepage: String,
     pub data_orientation: String,
     pub binary_format: String,
     pub sampling_interval_in: String,
-    pub sampling_interval: i32,
+    /// Time between two samples in `sampling_interval_in` (microseconds)
+    pub sampling_interval: f64,
+    pub comment: String,
+    /// Names of the channels marked bad, e.g. `status` "bad" in a BIDS `_channels.tsv`
+    pub bads: Vec<String>,
+    /// Electrode positions, from the `[Coordinates]` of the header or set with `montage::set_montage`
+    pub montage: Option<montage::Montage>,
+    /// Impedances measured by the recorder before the recording, from the `[Comment]` section
+    pub impedances: Option<ImpedanceTable>,
+}
+
+impl EEGInfo {
+    /// Overrides the inferred type of the channel `name`.
+    pub fn set_channel_type(&mut self, name: &str, kind: ChannelType) -> Result<(), Error> {
+        let channel = self
+            .channels
+            .iter_mut()
+            .find(|channel| channel.name == name)
+            .ok_or_else(|| Error::NotFound(format!("Channel {name:?}")))?;
+        channel.kind = kind;
+        Ok(())
+    }
+}
+
+/// One row of an impedance table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Impedance {
+    /// Electrode name, including `Ref` and `Gnd`
+    pub name: String,
+    /// `None` when the recorder could not measure it ("Out of Range!")
+    pub value: Option<f64>,
+}
+
+impl Impedance {
+    /// `true` above `threshold` or out of range.
+    pub fn exceeds(&self, threshold: f64) -> bool {
+        self.value.is_none_or(|value| value > threshold)
+    }
+}
+
+/// An `Impedance [kOhm] at <time> :` block of a `.vhdr` comment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImpedanceTable {
+    /// Unit of the values, e.g. `kOhm`
+    pub unit: String,
+    /// Time of the measurement as written by the recorder, e.g. `10:46:13`
+    pub time: String,
+    pub impedances: Vec<Impedance>,
+}
+
+impl ImpedanceTable {
+    pub fn get(&self, name: &str) -> Option<&Impedance> {
+        self.impedances.iter().find(|impedance| impedance.name == name)
+    }
+
+    /// Electrodes above `threshold` or out of range.
+    pub fn exceeding(&self, threshold: f64) -> Vec<&Impedance> {
+        self.impedances.iter().filter(|impedance| impedance.exceeds(threshold)).collect()
+    }
+}
+
+/// What a channel records, inferred by `ChannelType::infer` or set with
+/// `EEGInfo::set_channel_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ChannelType {
+    #[default]
+    Eeg,
+    Eog,
+    Emg,
+    Ecg,
+    /// Anything else, e.g. temperature, respiration or accelerometer channels
+    Misc,
+    /// Trigger or status channels
+    Stim,
+}
+
+impl ChannelType {
+    /// Guesses the type from the channel name (`HEOG`, `EMG,R`, `EKG`, `STI 014`, `Status` ...)
+    /// and unit: channels that are not in volts are `Misc`.
+    pub fn infer(name: &str, unit: &str) -> Self {
+        let upper = name.to_uppercase();
+        let volts = matches!(unit.trim(), "" | "V" | "mV" | "µV" | "μV" | "uV" | "nV");
+        if upper.contains("EOG") {
+            Self::Eog
+        } else if upper.contains("EMG") {
+            Self::Emg
+        } else if upper.contains("ECG") || upper.contains("EKG") {
+            Self::Ecg
+        } else if upper.starts_with("TRIG") || upper.starts_with("STI") || upper == "STATUS" {
+            Self::Stim
+        } else if volts {
+            Self::Eeg
+        } else {
+            Self::Misc
+        }
+    }
+
+    /// Value of the `type` column of a BIDS `_channels.tsv`.
+    pub fn bids_name(self) -> &'static str {
+        match self {
+            Self::Eeg => "EEG",
+            Self::Eog => "EOG",
+            Self::Emg => "EMG",
+            Self::Ecg => "ECG",
+            Self::Misc => "MISC",
+            Self::Stim => "TRIG",
+        }
+    }
+}
+
+impl std::fmt::Display for ChannelType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Eeg => "eeg",
+            Self::Eog => "eog",
+            Self::Emg => "emg",
+            Self::Ecg => "ecg",
+            Self::Misc => "misc",
+            Self::Stim => "stim",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Parses the names `Display` writes and the BIDS types, ignoring case.
+impl std::str::FromStr for ChannelType {
+    type Err = Error;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind.trim().to_lowercase().as_str() {
+            "eeg" => Ok(Self::Eeg),
+            "eog" | "heog" | "veog" => Ok(Self::Eog),
+            "emg" => Ok(Self::Emg),
+            "ecg" | "ekg" => Ok(Self::Ecg),
+            "misc" => Ok(Self::Misc),
+            "stim" | "trig" => Ok(Self::Stim),
+            _ => Err(Error::InvalidParameter(format!("Unknown channel type {kind:?}, expected eeg, eog, emg, ecg, misc or stim"))),
+        }
+    }
+}
+
+/// One entry of the `[Channel Infos]` section of a `.vhdr` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelInfo {
+    pub name: String,
+    pub reference: String,
+    /// Size of one raw count in `unit`, only used to convert from and to the raw counts
+    /// of the files
+    pub resolution: f64,
+    pub unit: String,
+    /// Radius, theta and phi from the `[Coordinates]` section, if present
+    pub coordinates: Option<(f64, f64, f64)>,
+    pub kind: ChannelType,
 }
 
 #[derive(Debug)]
 pub struct Markers {
     pub n_markers: usize,
-    pub markers: Vec<f64>
+    /// 0-based sample positions of the trigger events, see [`Marker::is_trigger`]
+    pub markers: Vec<f64>,
+    /// Every entry of the marker file, in file order
+    pub events: Vec<Marker>,
+}
+
+impl Markers {
+    /// Markers from the entries of a marker file, the triggers are taken from the events.
+    pub fn from_events(events: Vec<Marker>) -> Self {
+        let markers: Vec<f64> = events
+            .iter()
+            .filter(|event| event.is_trigger())
+            .map(|event| event.position as f64)
+            .collect();
+        Self { n_markers: markers.len(), markers, events }
+    }
+
+    /// Adds an annotation, the events stay sorted by position.
+    pub fn annotate(&mut self, annotation: Marker) {
+        let idx = self.events.partition_point(|event| event.position <= annotation.position);
+        self.events.insert(idx, annotation);
+        *self = Self::from_events(std::mem::take(&mut self.events));
+    }
+
+    /// The triggers described as `description`, see [`Marker::is_described`].
+    pub fn described<'a>(&'a self, description: &'a str) -> impl Iterator<Item = &'a Marker> + 'a {
+        self.events.iter().filter(move |event| event.is_trigger() && event.is_described(description))
+    }
+
+    /// The bad segments, see [`Marker::is_bad`].
+    pub fn bad_segments(&self) -> impl Iterator<Item = &Marker> {
+        self.events.iter().filter(|event| event.is_bad())
+    }
+
+    /// `true` when `window` overlaps a bad segment of all channels or of one of the 0-based
+    /// `channels`.
+    pub fn is_bad(&self, window: &Range<usize>, channels: &[usize]) -> bool {
+        self.bad_segments().any(|bad| {
+            let span = bad.span();
+            let applies = bad.channel == 0 || channels.contains(&(bad.channel - 1));
+            applies && span.start < window.end && window.start < span.end
+        })
+    }
+}
+
+/// One `Mk<n>=` entry of a `.vmrk` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Marker {
+    /// Marker type, e.g. `Stimulus`, `Response`, `New Segment`, `Comment` or `SyncStatus`.
+    /// EDF+ annotations have the type `Annotation`
+    pub kind: String,
+    /// Marker description, e.g. `S  1` or `R128`
+    pub description: String,
+    /// 0-based sample position
+    pub position: usize,
+    /// Size in samples
+    pub duration: usize,
+    /// 1-based channel number, 0 when the marker applies to all channels
+    pub channel: usize,
+    /// Recording start written with `New Segment` markers
+    pub date: Option<String>,
+}
+
+impl Marker {
+    /// A note or bad segment of `duration` samples at `position`, `channel` is 1-based and 0
+    /// for all channels. Descriptions starting with `BAD` become `Bad Interval` markers like
+    /// `BrainVision` Analyzer writes them, others become `Comment` markers.
+    pub fn annotation(description: &str, position: usize, duration: usize, channel: usize) -> Self {
+        let kind = if is_bad_description(description) { "Bad Interval" } else { "Comment" };
+        Self {
+            kind: kind.to_owned(),
+            description: description.to_owned(),
+            position,
+            duration: duration.max(1),
+            channel,
+            date: None,
+        }
+    }
+
+    /// `true` for the events epochs are time locked to: `Stimulus` and `Response` markers
+    /// of `BrainVision` files and the annotations of EDF+ files, except bad segments.
+    pub fn is_trigger(&self) -> bool {
+        matches!(self.kind.as_str(), "Stimulus" | "Response" | "Annotation") && !self.is_bad()
+    }
+
+    /// `true` for bad segments: `Bad Interval` markers and annotations whose description
+    /// starts with `BAD` in any case, as in EDF+ files and BIDS events written by MNE.
+    pub fn is_bad(&self) -> bool {
+        self.kind == "Bad Interval" || is_bad_description(&self.description)
+    }
+
+    /// Text for formats without marker types like EDF+: the description, or the type when
+    /// there is none. Bad segments get a `BAD_` prefix unless the text already says so.
+    pub fn annotation_text(&self) -> String {
+        let text = if self.description.trim().is_empty() { &self.kind } else { &self.description };
+        if self.is_bad() && !is_bad_description(text) {
+            format!("BAD_{text}")
+        } else {
+            text.clone()
+        }
+    }
+
+    /// `true` when the description is `description`, spaces do not matter so `R128` also
+    /// matches `R 128`.
+    pub fn is_described(&self, description: &str) -> bool {
+        let squeeze = |text: &'_ str| text.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+        squeeze(&self.description) == squeeze(description)
+    }
+
+    /// Samples covered by the marker.
+    pub fn span(&self) -> Range<usize> {
+        self.position..self.position + self.duration.max(1)
+    }
+}
+
+fn is_bad_description(description: &str) -> bool {
+    description.trim_start().get(..3).is_some_and(|prefix| prefix.eq_ignore_ascii_case("bad"))
 }
 
 #[derive(Debug)]
 pub struct EEGData {
-    pub data: Array2<i16>,
+    /// Channels x samples in the `unit` of every channel, calibrated from the raw counts
+    pub data: Array2<f32>,
 }
 
 #[derive(Debug)]
 pub struct EpochsData {
-    pub epochs: Array3<i16>,
+    pub epochs: Array3<f32>,
     pub ch_names: Vec<String>,
     pub tmin: f64,
-    pub tmax: f64
+    pub tmax: f64,
+    /// One row per epoch like the events array of MNE: sample of the event, code of the
+    /// trigger before it (0 for the first) and its code, see `epochs::event_codes`
+    pub events: Array2<i64>,
+    /// Condition names and the event code each one stands for
+    pub event_id: BTreeMap<String, i64>,
+}
+
+impl EpochsData {
+    /// Condition of every epoch, the first name of `event_id` with its code.
+    pub fn conditions(&self) -> Vec<Option<&str>> {
+        self.events
+            .column(2)
+            .iter()
+            .map(|code| self.event_id.iter().find(|(_, id)| *id == code).map(|(name, _)| name.as_str()))
+            .collect()
+    }
+
+    /// The epochs of the named conditions, in their original order.
+    pub fn select(&self, conditions: &[&str]) -> Result<Self, Error> {
+        let codes = conditions
+            .iter()
+            .map(|name| self.event_id.get(*name).copied().ok_or_else(|| Error::NotFound(format!("No condition {name:?} in the event id"))))
+            .collect::<Result<Vec<_>, _>>()?;
+        let kept: Vec<usize> = (0..self.events.nrows()).filter(|&epoch| codes.contains(&self.events[[epoch, 2]])).collect();
+        Ok(Self {
+            epochs: self.epochs.select(Axis(0), &kept),
+            ch_names: self.ch_names.clone(),
+            tmin: self.tmin,
+            tmax: self.tmax,
+            events: self.events.select(Axis(0), &kept),
+            event_id: self.event_id.iter().filter(|(name, _)| conditions.contains(&name.as_str())).map(|(name, &id)| (name.clone(), id)).collect(),
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -1,5 +1,5 @@
 
-use std::{fs, hash::DefaultHasher};
+use std::{collections::BTreeMap, hash::DefaultHasher, path::Path};
 use clap::Parser;
 use ndarray::prelude::*;
 //use plotly::{Plot, Scatter};
@@ -7,14 +7,10 @@ use eframe::NativeOptions;
 
 
 // My stuff
-mod io;
 mod gui;
-mod app;
-mod signal;
-mod epochs;
-mod vis;
 
-use reegui::{EEGInfo, EEGData, Markers, EpochsData, EvokedData};
+use reegui::{EEGInfo, ChannelType, EEGData, Marker, Markers, EpochsData, EvokedData, Error, Picks};
+use reegui::{epochs, io, montage, signal, stream, vis};
 
 
 //use std::any::type_name;
@@ -34,13 +30,13 @@ pub struct Cli {
     #[arg(long, required_if_eq("format", "brainvision"))]
     hfpath: Option<String>,
 
-    /// File path of the .vmrk BV (str)
-    #[arg(long, required_if_eq("format", "brainvision"))]
+    /// File path of the .vmrk if BV, defaults to the marker file named in the .vhdr (str)
+    #[arg(long)]
     mfpath: Option<String>,
 
-    /// File path of the .eeg if BV or .edf if EDF (str)
-    #[arg(long)]
-    dfpath: String,
+    /// File path of the .eeg if BV, .edf if EDF or .bdf if BDF, defaults to the data file named in the .vhdr (str)
+    #[arg(long, required_unless_present = "hfpath")]
+    dfpath: Option<String>,
 
     /// Read and display the metadata
     #[arg(short, long)]
@@ -58,6 +54,10 @@ pub struct Cli {
     #[arg(long, required_if_eq("rmtms", "true"))]
     tmaxcut: Option<f64>,
 
+    /// Description of the TMS pulse markers, other triggers are left alone
+    #[arg(long, default_value = signal::TMS_PULSE)]
+    tmsmarker: String,
+
     /// Bandpass filter data (Butterworth)
     #[arg(long)]
     filter: bool,
@@ -94,7 +94,7 @@ pub struct Cli {
     #[arg(long, required_if_eq("plotevoked", "true"))]
     plotfname: Option<String>,
 
-    /// Select data format (Brainvision or EDF)
+    /// Select data format (brainvision, edf, bdf, bids with the dataset root as --dfpath, or npz to reload saved epochs)
     #[arg(short, long)]
     format: String,
 
@@ -105,6 +105,127 @@ pub struct Cli {
     /// Use fast memory-mapped I/O for large files (requires unsafe code)
     #[arg(long)]
     fastio: bool,
+
+    /// Only read these channels (comma separated names, e.g. Fp1,Cz; write a comma inside a name as \1 like the .vhdr does)
+    #[arg(long, value_delimiter = ',')]
+    channels: Vec<String>,
+
+    /// Only read data from this time on (s)
+    #[arg(long, default_value_t = 0.0)]
+    start: f64,
+
+    /// Only read data up to this time (s)
+    #[arg(long)]
+    stop: Option<f64>,
+
+    /// Process the recording block by block from disk and write it to --savepath (Brainvision only)
+    ///
+    /// Out-of-core version of the continuous processing (TMS removal and filtering), for
+    /// recordings that do not fit in memory
+    #[arg(long, requires = "savepath")]
+    stream: bool,
+
+    /// Block length in seconds for --stream
+    #[arg(long, default_value_t = 10.0)]
+    blocksize: f64,
+
+    /// Filter forwards only when streaming, carrying the filter state across blocks
+    #[arg(long)]
+    causal: bool,
+
+    /// Write the processed continuous data as a Brainvision triplet (path of the .vhdr)
+    #[arg(long)]
+    savepath: Option<String>,
+
+    /// Binary format of the written .eeg file
+    #[arg(long, default_value = "IEEE_FLOAT_32", value_parser = ["INT_16", "INT_32", "IEEE_FLOAT_32"])]
+    saveformat: String,
+
+    /// Write the processed continuous data as EDF+ (.edf) or BDF+ (.bdf) with the markers as annotations
+    #[arg(long)]
+    exportpath: Option<String>,
+
+    /// Write the epochs (and the evoked data when averaging) as a numpy .npz archive
+    #[arg(long, requires = "epoch")]
+    npzpath: Option<String>,
+
+    /// Write the processed continuous data as a table (.csv, or tab separated for .tsv)
+    #[arg(long)]
+    tablepath: Option<String>,
+
+    /// Write the epochs as a table (.csv or .tsv)
+    #[arg(long, requires = "epoch")]
+    epochstablepath: Option<String>,
+
+    /// Write the evoked data as a table (.csv or .tsv), with an event id also one table per condition
+    #[arg(long, requires = "evoked")]
+    evokedtablepath: Option<String>,
+
+    /// Conditions to epoch as JSON, e.g. '{"single": "S 1", "sici": "S 2"}', or the path of a JSON file.
+    /// Without it every trigger is epoched and each description is a condition
+    #[arg(long, requires = "epoch")]
+    eventid: Option<String>,
+
+    /// BIDS subject label (without "sub-")
+    #[arg(long, required_if_eq("format", "bids"))]
+    subject: Option<String>,
+
+    /// BIDS session label
+    #[arg(long)]
+    session: Option<String>,
+
+    /// BIDS task label, when reading any task matches if omitted
+    #[arg(long)]
+    task: Option<String>,
+
+    /// BIDS run label
+    #[arg(long)]
+    run: Option<String>,
+
+    /// Copy the Brainvision recording into this BIDS dataset (needs --subject and --task)
+    #[arg(long, requires_all = ["subject", "task"])]
+    bidsroot: Option<String>,
+
+    /// Power line frequency in Hz written to the BIDS sidecar
+    #[arg(long)]
+    linefreq: Option<f64>,
+
+    /// Electrode positions: a .bvef, .elc or .sfp file, `standard_1020` or `standard_1010`
+    #[arg(long)]
+    montage: Option<String>,
+
+    /// Channels to process, epoch and plot: `all`, names like `Fp1,Cz`, `type:eeg,eog`, `regex:^F` or `index:0,2`
+    #[arg(long, default_value = "all")]
+    picks: String,
+
+    /// Add a note or bad segment as `ONSET,DURATION,DESCRIPTION[,CHANNEL]` in seconds from the start of the
+    /// loaded data, can be repeated. Descriptions starting with BAD mark bad segments, which epochs must not overlap
+    #[arg(long)]
+    annotate: Vec<String>,
+
+    /// Set a channel type as `NAME=TYPE` (eeg, eog, emg, ecg, misc or stim), can be repeated
+    #[arg(long)]
+    chtype: Vec<String>,
+
+    /// Check the Brainvision header against its data and marker files and print a report
+    #[arg(long, requires = "hfpath")]
+    validate: bool,
+
+    /// Format of the validation report
+    #[arg(long, default_value = "text", value_parser = ["text", "json"])]
+    reportformat: String,
+
+    /// Print the electrode impedances measured before the recording
+    #[arg(long)]
+    impedances: bool,
+
+    /// Impedances above this value (kOhm) are flagged
+    #[arg(long, default_value_t = 10.0)]
+    impedancethreshold: f64,
+
+    /// Layout of the written tables: one column per channel (wide) or one row per channel and sample (long)
+    #[arg(long, default_value = "wide", value_parser = ["wide", "long"])]
+    tablelayout: String,
 }
 
 
@@ -118,47 +239,500 @@ fn check(data: &Array2<f32>, eeg_info: &EEGInfo) {
         println!("C {:?} {:?}", c.first(), c.last())
     }
 }
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = Cli::parse();
+impl Cli {
+    fn dfpath(&self) -> &str {
+        self.dfpath.as_deref().unwrap_or_default()
+    }
+}
 
-    match cli.readdata {
-        true => {
-            println!("Reading from fpath {:?} \n", cli.hfpath);
+/// Fills in --dfpath and --mfpath of a Brainvision recording from its header. Paths given
+/// on the command line are kept, with a warning when they are not the files the header names.
+fn resolve_brainvision_files(cli: &mut Cli) -> Result<(), Error> {
+    let Some(vhdr) = cli.hfpath.as_deref().filter(|_| cli.format == "brainvision") else {
+        return Ok(());
+    };
+    if cli.dfpath.is_some() && cli.mfpath.is_some() {
+        return Ok(());
+    }
+    let files = io::resolve_brainvision(vhdr)?;
+    let resolved = [(&mut cli.dfpath, Some(files.data)), (&mut cli.mfpath, files.markers)];
+    for (given, found) in resolved {
+        let found = found.map(|path| path.to_string_lossy().into_owned());
+        match given {
+            Some(path) if found.as_ref().is_some_and(|found| found != path) => {
+                println!("Using {path:?} instead of {:?} named by {vhdr:?}", found.unwrap_or_default());
+            }
+            Some(_) => {}
+            None => *given = found,
+        }
+    }
+    Ok(())
+}
+
+/// Markers of a Brainvision recording, empty when it has no marker file.
+fn read_markers(cli: &Cli) -> Result<Markers, Error> {
+    match &cli.mfpath {
+        Some(_) => io::parse_vmrk(&io::get_vmrk(&cli.mfpath)?),
+        None => Ok(Markers { n_markers: 0, markers: Vec::new(), events: Vec::new() }),
+    }
+}
+
+fn read_selection(cli: &Cli) -> io::Selection {
+    let channels = cli.channels.iter().map(|name| name.replace(r"\1", ",")).collect();
+    io::Selection { channels, start: cli.start, stop: cli.stop }
+}
+
+fn bids_path(cli: &Cli, root: &str) -> io::BidsPath {
+    io::BidsPath {
+        root: root.into(),
+        subject: cli.subject.clone().unwrap_or_default(),
+        session: cli.session.clone(),
+        task: cli.task.clone().unwrap_or_default(),
+        acquisition: None,
+        run: cli.run.clone(),
+    }
+}
+
+fn apply_montage(cli: &Cli, eeg_info: &mut EEGInfo) -> Result<(), Error> {
+    if let Some(path) = &cli.montage {
+        let missing = montage::set_montage(eeg_info, montage::Montage::read(path)?);
+        println!("\n Montage {path:?} applied, channels without a position: {missing:?}");
+    }
+    Ok(())
+}
+
+fn apply_channel_types(cli: &Cli, eeg_info: &mut EEGInfo) -> Result<(), Error> {
+    for setting in &cli.chtype {
+        let (name, kind) = setting
+            .rsplit_once('=')
+            .ok_or_else(|| Error::InvalidParameter(format!("Channel type {setting:?} is not NAME=TYPE")))?;
+        eeg_info.set_channel_type(&name.replace(r"\1", ","), kind.parse::<ChannelType>()?)?;
+    }
+    Ok(())
+}
+
+/// Adds the `--annotate` notes and bad segments to `markers`.
+fn annotate(cli: &Cli, eeg_info: &EEGInfo, markers: &mut Markers) -> Result<(), Error> {
+    for annotation in &cli.annotate {
+        let fields: Vec<String> = annotation.split(',').map(|field| field.trim().replace(r"\1", ",")).collect();
+        let invalid = || Error::InvalidParameter(format!("Annotation {annotation:?} is not ONSET,DURATION,DESCRIPTION[,CHANNEL]"));
+        let [onset, duration, description, channel @ ..] = fields.as_slice() else { return Err(invalid()) };
+        let onset: f64 = onset.parse().map_err(|_err| invalid())?;
+        let duration: f64 = duration.parse().map_err(|_err| invalid())?;
+        let channel = match channel {
+            [] => 0,
+            [name] => eeg_info.ch_names.iter().position(|ch| ch == name).ok_or_else(|| Error::NotFound(format!("Channel {name:?}")))? + 1,
+            _ => return Err(invalid()),
+        };
+        let position = (onset * eeg_info.sfreq).round().max(0.0) as usize;
+        let samples = (duration * eeg_info.sfreq).round().max(0.0) as usize;
+        markers.annotate(Marker::annotation(description, position, samples, channel));
+    }
+    let n_bad = markers.bad_segments().count();
+    if n_bad > 0 {
+        println!("\n {n_bad} bad segments, epochs overlapping them are dropped");
+    }
+    Ok(())
+}
+
+fn print_impedances(cli: &Cli, eeg_info: &EEGInfo) {
+    if !cli.impedances {
+        return;
+    }
+    let Some(table) = &eeg_info.impedances else {
+        println!("\n No impedance measurement found in the header");
+        return;
+    };
+    println!("\n Impedances [{}] at {}", table.unit, table.time);
+    for impedance in &table.impedances {
+        let value = impedance.value.map_or_else(|| String::from("Out of range"), |value| value.to_string());
+        let flag = if impedance.exceeds(cli.impedancethreshold) { "  <-- above threshold" } else { "" };
+        println!("{:>10}: {value:>12}{flag}", impedance.name);
+    }
+    let exceeding: Vec<&str> = table.exceeding(cli.impedancethreshold).iter().map(|impedance| impedance.name.as_str()).collect();
+    println!("{} electrodes above {} {}: {exceeding:?}", exceeding.len(), cli.impedancethreshold, table.unit);
+}
+
+fn stream_processed(cli: &Cli) -> Result<(), Error> {
+    if cli.format != "brainvision" {
+        return Err(Error::UnsupportedFormat(format!("{} for streaming, only brainvision files can be streamed", cli.format)));
+    }
+    if cli.epoch || cli.evoked || !read_selection(cli).is_everything() {
+        return Err(Error::InvalidParameter("Streaming does continuous processing of the whole recording only".into()));
+    }
+    let mut eeg_info = io::parse_header(&io::get_header(&cli.hfpath)?)?;
+    apply_channel_types(cli, &mut eeg_info)?;
+    let mut markers = read_markers(cli)?;
+    annotate(cli, &eeg_info, &mut markers)?;
+    let tms_cut = cli.rmtms.then(|| (cli.tmincut.unwrap_or(0.002), cli.tmaxcut.unwrap_or(0.005)));
+    let options = stream::StreamOptions {
+        block_seconds: cli.blocksize,
+        tms_cut,
+        pulse: cli.tmsmarker.clone(),
+        lfreq: cli.lfreq.filter(|_| cli.filter),
+        hfreq: cli.hfreq.filter(|_| cli.filter),
+        causal: cli.causal,
+        picks: cli.picks.parse()?,
+    };
+    let out = cli.savepath.as_deref().ok_or_else(|| Error::InvalidParameter("--stream needs --savepath".into()))?;
+    stream::process_brainvision(cli.dfpath(), &eeg_info, &markers, &options, out, &cli.saveformat)
+}
+
+fn save_processed(cli: &Cli, eeg_info: &EEGInfo, data: &Array2<f32>, markers: &Markers) -> Result<(), Error> {
+    if let Some(path) = &cli.savepath {
+        println!("\n Saving processed data to {path:?}");
+        io::write_brainvision(path, eeg_info, data, markers, &cli.saveformat)?;
+    }
+    if let Some(path) = &cli.exportpath {
+        let bdf = Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("bdf"));
+        println!("\n Exporting processed data to {path:?}");
+        io::write_edf(path, eeg_info, data, markers, bdf)?;
+    }
+    if let Some(path) = &cli.tablepath {
+        println!("\n Exporting processed data to {path:?}");
+        io::write_raw_table(path, eeg_info, data, cli.tablelayout.parse::<io::TableLayout>()?)?;
+    }
+    Ok(())
+}
+
+/// Event codes of the `--eventid` conditions, or of every trigger description without it.
+fn event_id(cli: &Cli, markers: &Markers) -> Result<BTreeMap<String, i64>, Error> {
+    let conditions = match &cli.eventid {
+        Some(event_id) => epochs::parse_event_id(event_id)?,
+        None => BTreeMap::new(),
+    };
+    let event_id = epochs::event_id(&conditions, markers)?;
+    println!("\n Event id {event_id:?}");
+    Ok(event_id)
+}
+
+/// `path` with `_<condition>` added to the file stem, e.g. `evoked_single.csv`.
+fn condition_path(path: &str, condition: &str) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+    let file_name = match path.extension() {
+        Some(ext) => format!("{stem}_{condition}.{}", ext.to_string_lossy()),
+        None => format!("{stem}_{condition}"),
+    };
+    path.with_file_name(file_name).to_string_lossy().into_owned()
+}
+
+/// Averages and exports every `--eventid` condition on its own.
+fn save_conditions(cli: &Cli, eeg_info: &EEGInfo, epochs_data: &EpochsData) -> Result<(), Error> {
+    if cli.eventid.is_none() {
+        return Ok(());
+    }
+    for condition in epochs_data.event_id.keys() {
+        let selected = epochs_data.select(&[condition])?;
+        println!("\n Condition {condition:?}: {} epochs", selected.events.nrows());
+        if selected.events.nrows() == 0 {
+            continue;
+        }
+        let evoked = epochs::evoked_eeg(&selected, eeg_info)?;
+        let evoked_data = EvokedData { evoked, tmin: selected.tmin, tmax: selected.tmax, ch_names: selected.ch_names };
+        if let Some(path) = &cli.evokedtablepath {
+            io::write_evoked_table(&condition_path(path, condition), eeg_info, &evoked_data, cli.tablelayout.parse()?)?;
+        }
+        if cli.plotevoked {
+            let file_name = format!("{}_{condition}", cli.plotfname.as_deref().unwrap_or("Evoked"));
+            vis::plot_evoked(&evoked_data, Some(eeg_info), &cli.picks.parse()?, &file_name)?;
+        }
+    }
+    Ok(())
+}
+
+fn save_epochs(
+    cli: &Cli,
+    eeg_info: &EEGInfo,
+    epochs_data: &EpochsData,
+    evoked_data: Option<&EvokedData>,
+) -> Result<(), Error> {
+    if let Some(path) = &cli.npzpath {
+        println!("\n Saving epochs to {path:?}");
+        io::write_epochs_npz(path, Some(epochs_data), evoked_data, eeg_info.sfreq)?;
+    }
+    if let Some(path) = &cli.epochstablepath {
+        println!("\n Exporting epochs to {path:?}");
+        io::write_epochs_table(path, eeg_info, epochs_data, cli.tablelayout.parse()?)?;
+    }
+    if let (Some(path), Some(evoked_data)) = (&cli.evokedtablepath, evoked_data) {
+        println!("\n Exporting evoked data to {path:?}");
+        io::write_evoked_table(path, eeg_info, evoked_data, cli.tablelayout.parse()?)?;
+    }
+    save_conditions(cli, eeg_info, epochs_data)
+}
+
+/// Reloads epochs written with --npzpath and plots their average.
+fn reload_epochs(cli: &Cli) -> Result<(), Error> {
+    println!("Reading epochs from {:?} \n", cli.dfpath());
+    let saved = io::npy::read_epochs_npz(cli.dfpath())?;
+    println!("Sampling frequency {:?} Hz, {:?} samples per epoch", saved.sfreq, saved.times.len());
+    let evoked_data = match (saved.evoked, saved.epochs) {
+        (Some(evoked_data), _) => evoked_data,
+        (None, Some(epochs_data)) => {
+            println!("Shape of epochs (epochs, channels, samples): {:?}", epochs_data.epochs.dim());
+            println!("Event id {:?}", epochs_data.event_id);
+            let evoked = epochs_data.epochs.mapv(f64::from).mean_axis(Axis(0)).ok_or_else(|| Error::InvalidParameter("No epochs to average".into()))?;
+            EvokedData { evoked, tmin: epochs_data.tmin, tmax: epochs_data.tmax, ch_names: epochs_data.ch_names }
+        }
+        (None, None) => return Err(Error::NotFound(format!("{:?} holds neither epochs nor evoked data", cli.dfpath()))),
+    };
+    println!("Shape of evoked data (channels, samples): {:?}", evoked_data.evoked.dim());
+    if cli.plotevoked {
+        let file_name = cli.plotfname.clone().unwrap_or_else(|| String::from("Evoked"));
+        vis::plot_evoked(&evoked_data, None, &cli.picks.parse()?, &file_name)?;
+    }
+    Ok(())
+}
+
+fn viewer_options() -> NativeOptions {
+    eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_inner_size([400.0, 300.0])
+            .with_min_inner_size([300.0, 220.0])
+            .with_icon(
+                // NOTE: Adding an icon is optional
+                eframe::icon_data::from_png_bytes(&include_bytes!("../assets/icon-256.png")[..])
+                    .expect("Failed to load icon"),
+            ),
+        ..Default::default()
+    }
+}
+
+/// Applies `--montage`, `--chtype` and `--annotate` like the processing does and returns the
+/// `--picks` the viewer processes.
+fn prepare_viewer(cli: &Cli, eeg_info: &mut EEGInfo, markers: &mut Markers) -> Result<Picks, Error> {
+    apply_montage(cli, eeg_info)?;
+    apply_channel_types(cli, eeg_info)?;
+    annotate(cli, eeg_info, markers)?;
+    let picks: Picks = cli.picks.parse()?;
+    println!("\n Processing channels {:?}", picks.names(eeg_info)?);
+    Ok(picks)
+}
+
+fn run_viewer(eeg_info: EEGInfo, eeg_data: EEGData, markers: Markers, picks: Picks) -> eframe::Result {
+    eframe::run_native(
+        "reegui",
+        viewer_options(),
+        Box::new(|cc| Ok(Box::new(reegui::TemplateApp::new(cc, eeg_info, eeg_data, markers).with_picks(picks)))),
+    )
+}
+
+fn run_mapped_viewer(eeg_info: EEGInfo, mapped: io::MappedEeg, markers: Markers, picks: Picks) -> eframe::Result {
+    eframe::run_native(
+        "reegui",
+        viewer_options(),
+        Box::new(|cc| Ok(Box::new(reegui::TemplateApp::with_mapped(cc, eeg_info, mapped, markers).with_picks(picks)))),
+    )
+}
+
+/// Reads the recording in `cli.format`, restricted to `selection`.
+fn read_recording(cli: &Cli, selection: &io::Selection) -> Result<(EEGInfo, Array2<f32>, Markers), Error> {
+    let recording = if cli.format == "bids" {
+        let recording = io::read_bids(&bids_path(cli, cli.dfpath()))?;
+        println!("Reading BIDS recording {:?} \n", recording.data_path);
+        println!("Entities {:?}", recording.path.basename());
+        println!("Sidecar {:?}", recording.sidecar);
+        println!("Channels {:?}", recording.channels);
+        println!("Electrodes {:?}", recording.electrodes);
+        println!("Metadata {:?} \n", recording.eeg_info);
+        println!("Bad channels {:?}", recording.eeg_info.bads);
+        println!("Shape of loaded data {:?}", recording.data.data.shape());
+        println!("Number of events found {:?}", recording.markers.markers.len());
+        (recording.eeg_info, recording.data.data, recording.markers)
+    } else if cli.format == "edf" || cli.format == "bdf" {
+        println!("Reading from fpath {:?} \n", cli.dfpath());
+        let (eeg_info, eeg_data, markers) = if !selection.is_everything() {
+            println!("Reading selected channels and time window...");
+            io::read_edf_selection(cli.dfpath(), selection)?
+        } else if cli.format == "bdf" {
+            io::parse_bdf(cli.dfpath())?
+        } else {
+            io::parse_edf(cli.dfpath())?
+        };
+        println!("Metadata {:?} \n", eeg_info);
+        println!("Shape of loaded data {:?}", eeg_data.data.shape());
+        println!("Number of events found {:?}", markers.markers.len());
+        (eeg_info, eeg_data.data, markers)
+    } else {
+        println!("Reading from fpath {:?} \n", cli.hfpath);
+        let header = io::get_header(&cli.hfpath)?;
+        //println!("Header: {:?}", header);
+        let eeg_info = io::parse_header(&header)?;
+        println!("Reading data from fpath {:?} \n", cli.dfpath());
+        //let samples = io::parse_bytes(cli.dfpath(), &eeg_info)?;
+       //let times = io::convert_to_seconds(samples, &eeg_info)?;
+        //println!("Recording duration: {:?} s \n", times.len());
+        //let channels = io::demultiplex(times, &eeg_info);
+        let markers = read_markers(cli)?;
+        let (eeg_info, data, markers) = if selection.is_everything() {
+            let data = if cli.fastio {
+                println!("Using fast memory-mapped I/O...");
+                io::MappedEeg::open(cli.dfpath(), &eeg_info)?.to_array()
+            } else {
+                println!("Using standard I/O...");
+                let samples = io::parse_bytes(cli.dfpath(), &eeg_info)?;
+                io::vec_to_ndarray(io::split_channels(samples, &eeg_info)?)?
+            };
+            (eeg_info, data, markers)
+        } else {
+            println!("Reading selected channels and time window...");
+            let (eeg_info, eeg_data, markers) = io::read_brainvision_selection(cli.dfpath(), &eeg_info, &markers, selection)?;
+            (eeg_info, eeg_data.data, markers)
+        };
+        println!("Recording duration: {:?} s \n", data.ncols() as f64 / eeg_info.sfreq);
+        println!("DATA READ");
+        println!("Metadata {:?} \n", eeg_info);
+        println!("Shape of loaded data {:?}", data.shape());
+        println!("\n Reading events from .vmrk file {:?}", &cli.mfpath);
+        println!("Number of events found {:?}", markers.markers.len());
+        (eeg_info, data, markers)
+    };
+    Ok(recording)
+}
+
+/// Opens the recording in `cli.format` in the viewer.
+fn view(cli: &Cli, selection: &io::Selection) -> Result<(), Box<dyn std::error::Error>> {
+    match cli.format.as_str() {
+
+        "brainvision" => {
+
+            println!("Reading header from fpath {:?}", cli.hfpath);
             let header = io::get_header(&cli.hfpath)?;
             //println!("Header: {:?}", header);
-            let eeg_info = io::parse_header(&header)?;
-            println!("Reading data from fpath {:?} \n", cli.dfpath);
-            //let samples = io::parse_bytes(&cli.dfpath, &eeg_info)?;
-           //let times = io::convert_to_seconds(samples, &eeg_info)?;
-            //println!("Recording duration: {:?} s \n", times.len());
-            //let channels = io::demultiplex(times, &eeg_info);
-            let channels = if cli.fastio {
+            let mut eeg_info = io::parse_header(&header)?;
+            println!("Reading markers from {:?}", cli.mfpath);
+            let mut markers = read_markers(cli)?;
+            println!("Reading data from fpath {:?}", cli.dfpath());
+            if !selection.is_everything() {
+                let (mut eeg_info, eeg_data, mut markers) = io::read_brainvision_selection(cli.dfpath(), &eeg_info, &markers, selection)?;
+                println!("SHAPE OF DATA {:?}", eeg_data.data.shape());
+                let picks = prepare_viewer(cli, &mut eeg_info, &mut markers)?;
+                return Ok(run_viewer(eeg_info, eeg_data, markers, picks)?);
+            }
+            if cli.fastio {
                 println!("Using fast memory-mapped I/O...");
-                io::parse_bytes_opt(&cli.dfpath, &eeg_info)?
-            } else {
+                let mapped = io::MappedEeg::open(cli.dfpath(), &eeg_info)?;
+                println!("Mapped {} channels x {} samples", mapped.n_channels(), mapped.n_samples());
+                let picks = prepare_viewer(cli, &mut eeg_info, &mut markers)?;
+                return Ok(run_mapped_viewer(eeg_info, mapped, markers, picks)?);
+            }
+            let channels = {
                 println!("Using standard I/O...");
-                let samples = io::parse_bytes(&cli.dfpath, &eeg_info)?;
-                let times = io::convert_to_seconds(samples, &eeg_info)?;
-                println!("Recording duration: {:?} s \n", times.len());
-                io::demultiplex(times, &eeg_info)?
+                let samples = io::parse_bytes(cli.dfpath(), &eeg_info)?;
+                if let Some(vhdr) = &cli.hfpath {
+                    print!("{}", io::validate_brainvision(vhdr)?);
+                }
+                io::split_channels(samples, &eeg_info)?
             };
+
             println!("DATA READ");
-            println!("Metadata {:?} \n", eeg_info);
-            println!("Metadata read \n");
-            let data = io::vec_to_ndarray(channels);
+            //println!("CHANNELS {:?}", &channels.unwrap().len());
+            let data = io::vec_to_ndarray(channels)?;
+            println!("SHAPE OF DATA {:?}", data.shape());
+            let eeg_data = EEGData { data };
+            let picks = prepare_viewer(cli, &mut eeg_info, &mut markers)?;
 
-            println!("Data converted to ndarray: ");
-            println!("Shape of loaded data {:?}", data.shape());
-            println!("Row {:?}", &data.row(0).len());
-            println!("Column {:?}", &data.column(0).len());
+            run_viewer(eeg_info, eeg_data, markers, picks)
 
-            let vmrk_file = io::get_vmrk(&cli.mfpath)?;
-            let markers = io::parse_vmrk(&vmrk_file)?;
-            println!("\n Reading events from .vmrk file {:?}", &cli.mfpath);
-            println!("Number of events found {:?}", markers.markers.len());
+            }
+
+        "edf" => {
+
+            println!("Reading from fpath {:?}", cli.dfpath());
+            let (mut eeg_info, eeg_data, mut markers) = if selection.is_everything() {
+                io::parse_edf(cli.dfpath())?
+            } else {
+                io::read_edf_selection(cli.dfpath(), selection)?
+            };
+            println!("SHAPE OF DATA {:?}", eeg_data.data.shape());
+            let picks = prepare_viewer(cli, &mut eeg_info, &mut markers)?;
+            run_viewer(eeg_info, eeg_data, markers, picks)
+
+            }
+
+        "bdf" => {
+
+            println!("Reading from fpath {:?}", cli.dfpath());
+            let (mut eeg_info, eeg_data, mut markers) = if selection.is_everything() {
+                io::parse_bdf(cli.dfpath())?
+            } else {
+                io::read_edf_selection(cli.dfpath(), selection)?
+            };
+            println!("SHAPE OF DATA {:?}", eeg_data.data.shape());
+            let picks = prepare_viewer(cli, &mut eeg_info, &mut markers)?;
+            run_viewer(eeg_info, eeg_data, markers, picks)
+
+            }
+        _ => {
+                println!("Error: Unknown format specified: {}", cli.format);
+                Ok(())
+            }
+    }?;
+    Ok(())
+}
+
+fn main() -> std::process::ExitCode {
+    // Report errors with their message rather than their `Debug` form
+    match run() {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            std::process::ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cli = Cli::parse();
+    let selection = read_selection(&cli);
+
+    if cli.validate {
+        let vhdr = cli.hfpath.as_deref().unwrap_or_default();
+        let report = io::validate_brainvision(vhdr)?;
+        match cli.reportformat.as_str() {
+            "json" => println!("{}", report.to_json()?),
+            _ => print!("{report}"),
+        }
+        if !report.is_valid() {
+            return Err(format!("{vhdr} failed validation").into());
+        }
+        return Ok(());
+    }
+    resolve_brainvision_files(&mut cli)?;
+
+    match cli.readdata {
+        true if cli.stream => {
+            stream_processed(&cli)?;
+        }
+        true if cli.format == "npz" => {
+            reload_epochs(&cli)?;
+        }
+        true if cli.bidsroot.is_some() => {
+            let root = cli.bidsroot.as_deref().unwrap_or_default();
+            let vhdr = cli.hfpath.as_deref().ok_or("Writing BIDS needs the Brainvision header (--hfpath)")?;
+            let eeg_info = io::parse_header(&io::get_header(&cli.hfpath)?)?;
+            let mut markers = read_markers(&cli)?;
+            annotate(&cli, &eeg_info, &mut markers)?;
+            io::write_bids(&bids_path(&cli, root), vhdr, &markers, cli.linefreq)?;
+        }
+        true => {
+            let (eeg_info, data, markers) = read_recording(&cli, &selection)?;
+
+            let (mut eeg_info, mut markers) = (eeg_info, markers);
+            apply_montage(&cli, &mut eeg_info)?;
+            apply_channel_types(&cli, &mut eeg_info)?;
+            annotate(&cli, &eeg_info, &mut markers)?;
+            let picks: Picks = cli.picks.parse()?;
+            println!("\n Processing channels {:?}", picks.names(&eeg_info)?);
+            print_impedances(&cli, &eeg_info);
 
             match (cli.rmtms, cli.filter, cli.epoch, cli.evoked) {
 
+            (false, false, false, false) => {
+                save_processed(&cli, &eeg_info, &data, &markers)?;
+            },
+
             (true, false, false, false) => {
                 let default_tmincut = 0.002;
                 let default_tmaxcut = 0.005;
@@ -167,7 +741,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 print!("\n Attempting to remove and interpolate the TMS pulse between {:?}-{:?} ms \n",tmincut * 1000.0, tmaxcut *1000.0);
 
 
-                let rm_tms_data = signal::rm_interp_tms_pulse(tmincut, tmaxcut, &markers, &eeg_info, &data);
+                let rm_tms_data = signal::rm_interp_tms_pulse(tmincut, tmaxcut, &markers, &cli.tmsmarker, &eeg_info, &data, &picks)?;
+                save_processed(&cli, &eeg_info, &rm_tms_data, &markers)?;
 
             },
             (true, true, false, false) => {
@@ -178,7 +753,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 print!("\n Attempting to remove and interpolate the TMS pulse between {:?}-{:?} ms \n",tmincut * 1000.0, tmaxcut *1000.0);
 
 
-                let rm_tms_data = signal::rm_interp_tms_pulse(tmincut, tmaxcut, &markers, &eeg_info, &data);
+                let rm_tms_data = signal::rm_interp_tms_pulse(tmincut, tmaxcut, &markers, &cli.tmsmarker, &eeg_info, &data, &picks);
 
                 let hfreq_default = 40.0;
                 let lfreq_default = 0.1;
@@ -187,8 +762,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("\n Attempting to bandpass filter data between {:?}-{:?} Hz", lfreq, hfreq);
 
 
-                let lp_filtered_data = signal::lp_filter(hfreq, &eeg_info, &rm_tms_data?);
-                let hp_filtered_data = signal::hp_filter(lfreq, &eeg_info, &lp_filtered_data?);
+                let lp_filtered_data = signal::lp_filter(hfreq, &eeg_info, &rm_tms_data?, &picks);
+                let hp_filtered_data = signal::hp_filter(lfreq, &eeg_info, &lp_filtered_data?, &picks)?;
+                save_processed(&cli, &eeg_info, &hp_filtered_data, &markers)?;
 
             },
             (false, true, false, false) => {
@@ -200,8 +776,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("\n Attempting to bandpass filter data between {:?}-{:?} Hz", lfreq, hfreq);
 
 
-                let lp_filtered_data = signal::lp_filter(hfreq, &eeg_info, &data);
-                let hp_filtered_data = signal::hp_filter(lfreq, &eeg_info, &lp_filtered_data.unwrap());
+                let lp_filtered_data = signal::lp_filter(hfreq, &eeg_info, &data, &picks);
+                let hp_filtered_data = signal::hp_filter(lfreq, &eeg_info, &lp_filtered_data?, &picks)?;
+                save_processed(&cli, &eeg_info, &hp_filtered_data, &markers)?;
 
             },
             (true, true, true, false) => {
@@ -212,7 +789,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 print!("\n Attempting to remove and interpolate the TMS pulse between {:?}-{:?} ms \n",tmincut * 1000.0, tmaxcut *1000.0);
 
 
-                let rm_tms_data = signal::rm_interp_tms_pulse(tmincut, tmaxcut, &markers, &eeg_info, &data);
+                let rm_tms_data = signal::rm_interp_tms_pulse(tmincut, tmaxcut, &markers, &cli.tmsmarker, &eeg_info, &data, &picks);
 
                 let hfreq_default = 40.0;
                 let lfreq_default = 0.1;
@@ -220,8 +797,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let hfreq = cli.hfreq.unwrap_or(hfreq_default);
                 let lfreq = cli.lfreq.unwrap_or(lfreq_default);
 
-                let lp_filtered_data = signal::lp_filter(hfreq, &eeg_info, &rm_tms_data?);
-                let hp_filtered_data = signal::hp_filter(lfreq, &eeg_info, &lp_filtered_data?);
+                let lp_filtered_data = signal::lp_filter(hfreq, &eeg_info, &rm_tms_data?, &picks);
+                let hp_filtered_data = signal::hp_filter(lfreq, &eeg_info, &lp_filtered_data?, &picks)?;
+                save_processed(&cli, &eeg_info, &hp_filtered_data, &markers)?;
 
                 let default_tmin = 1.0;
                 let default_tmax = 1.0;
@@ -229,7 +807,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let tmax = cli.tmax.unwrap_or(default_tmax);
                 print!("\n Attempting epoch EEG data between tmin {:?} and tmax {:?} s \n", tmin, tmax);
 
-                let epochs = epochs::epoch_eeg(tmin, tmax, &eeg_info, &data, &markers);
+                let epochs_data = epochs::epoch_eeg(tmin, tmax, &eeg_info, &hp_filtered_data, &markers, &picks, event_id(&cli, &markers)?)?;
+                save_epochs(&cli, &eeg_info, &epochs_data, None)?;
 
             },
             (true, true, true, true) => {
@@ -240,7 +819,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 print!("\n Attempting to remove and interpolate the TMS pulse between {:?}-{:?} ms \n",tmincut * 1000.0, tmaxcut *1000.0);
 
 
-                let rm_tms_data = signal::rm_interp_tms_pulse(tmincut, tmaxcut, &markers, &eeg_info, &data)?;
+                let rm_tms_data = signal::rm_interp_tms_pulse(tmincut, tmaxcut, &markers, &cli.tmsmarker, &eeg_info, &data, &picks)?;
 
                 let hfreq_default = 40.0;
                 let lfreq_default = 0.1;
@@ -248,8 +827,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let lfreq = cli.lfreq.unwrap_or(lfreq_default);
                 println!("\n Attempting to bandpass filter data between {:?}-{:?} Hz", lfreq, hfreq);
 
-                let lp_filtered_data = signal::lp_filter(hfreq, &eeg_info, &rm_tms_data)?;
-                let hp_filtered_data = signal::hp_filter(lfreq, &eeg_info, &lp_filtered_data)?;
+                let lp_filtered_data = signal::lp_filter(hfreq, &eeg_info, &rm_tms_data, &picks)?;
+                let hp_filtered_data = signal::hp_filter(lfreq, &eeg_info, &lp_filtered_data, &picks)?;
+                save_processed(&cli, &eeg_info, &hp_filtered_data, &markers)?;
 
                 let default_tmin = 1.0;
                 let default_tmax = 1.0;
@@ -257,11 +837,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let tmax = cli.tmax.unwrap_or(default_tmax);
                 print!("\n Attempting epoch EEG data between tmin {:?} and tmax{:?} s \n",tmin,tmax);
 
-                let epochs = epochs::epoch_eeg(tmin, tmax, &eeg_info, &hp_filtered_data, &markers)?;
-                let epochs = epochs::epoch_eeg(tmin, tmax, &eeg_info, &hp_filtered_data, &markers)?;
-                println!("Shape of epochs (epochs, channels, samples): {:?}", epochs.dim());
-                let ch_names = eeg_info.ch_names.clone();
-                let epochs_data = EpochsData { epochs, tmin, tmax, ch_names: ch_names.clone() };
+                let epochs_data = epochs::epoch_eeg(tmin, tmax, &eeg_info, &hp_filtered_data, &markers, &picks, event_id(&cli, &markers)?)?;
+                println!("Shape of epochs (epochs, channels, samples): {:?}", epochs_data.epochs.dim());
+                let ch_names = epochs_data.ch_names.clone();
 
                 print!("\n Averageing across epochs..");
                 let evoked = epochs::evoked_eeg(&epochs_data, &eeg_info)?;
@@ -270,11 +848,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("\nShape of evoked data (channels, samples): {:?}", evoked.dim());
 
                 let evoked_data = EvokedData {evoked, tmin, tmax, ch_names: ch_names.clone()};
+                save_epochs(&cli, &eeg_info, &epochs_data, Some(&evoked_data))?;
                 let default_plotfname = String::from("Evoked");
-                let file_name = cli.plotfname.unwrap_or(default_plotfname);
+                let file_name = cli.plotfname.clone().unwrap_or(default_plotfname);
 
                 print!("\n Saving plot!");
-                vis::plot_evoked(evoked_data, &file_name);
+                vis::plot_evoked(&evoked_data, Some(&eeg_info), &picks, &file_name)?;
 
 
             },
@@ -286,42 +865,45 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let tmaxcut = cli.tmaxcut.unwrap_or(default_tmaxcut);
                 print!("\n Attempting to remove and interpolate the TMS pulse between {:?}-{:?} ms \n",tmincut * 1000.0, tmaxcut *1000.0);
 
-                let rm_tms_data = signal::rm_interp_tms_pulse(tmincut, tmaxcut, &markers, &eeg_info, &data)?;
+                let rm_tms_data = signal::rm_interp_tms_pulse(tmincut, tmaxcut, &markers, &cli.tmsmarker, &eeg_info, &data, &picks)?;
+                save_processed(&cli, &eeg_info, &rm_tms_data, &markers)?;
 
                 let default_tmin = 1.0;
                 let default_tmax = 1.0;
                 let tmin = cli.tmin.unwrap_or(default_tmin);
                 let tmax = cli.tmax.unwrap_or(default_tmax);
                 print!("\n Attempting epoch EEG data between tmin {:?} and tmax {:?} s \n",tmin,tmax);
-                let epochs = epochs::epoch_eeg(tmin, tmax, &eeg_info, &rm_tms_data, &markers)?;
+                let epochs_data = epochs::epoch_eeg(tmin, tmax, &eeg_info, &rm_tms_data, &markers, &picks, event_id(&cli, &markers)?)?;
 
-                let ch_names = eeg_info.ch_names.clone();
-                let epochs_data = EpochsData { epochs,tmin, tmax, ch_names: ch_names.clone()};
+                let ch_names = epochs_data.ch_names.clone();
                 let evoked = epochs::evoked_eeg(&epochs_data, &eeg_info)?;
                 let evoked = epochs::evoked_eeg(&epochs_data, &eeg_info)?;
                 let evoked_data = EvokedData {evoked, tmin, tmax, ch_names: ch_names.clone()};
+                save_epochs(&cli, &eeg_info, &epochs_data, Some(&evoked_data))?;
                 let default_plotfname = String::from("Evoked");
-                let file_name = cli.plotfname.unwrap_or(default_plotfname);
-                vis::plot_evoked(evoked_data, &file_name);
+                let file_name = cli.plotfname.clone().unwrap_or(default_plotfname);
+                vis::plot_evoked(&evoked_data, Some(&eeg_info), &picks, &file_name)?;
             },
             (false, false, true, true) => {
- let default_tmin = 1.0;
+                save_processed(&cli, &eeg_info, &data, &markers)?;
+
+                let default_tmin = 1.0;
                 let default_tmax = 1.0;
                 let tmin = cli.tmin.unwrap_or(default_tmin);
                 let tmax = cli.tmax.unwrap_or(default_tmax);
                 print!("\n Attempting epoch EEG data between tmin {:?} and tmax {:?} ms \n",tmin,tmax);
-                let epochs = epochs::epoch_eeg(tmin, tmax, &eeg_info, &data, &markers)?;
-                let ch_names = eeg_info.ch_names.clone();
-                let epochs_data = EpochsData { epochs,tmin, tmax, ch_names: ch_names.clone()};
+                let epochs_data = epochs::epoch_eeg(tmin, tmax, &eeg_info, &data, &markers, &picks, event_id(&cli, &markers)?)?;
+                let ch_names = epochs_data.ch_names.clone();
 
                 let evoked = epochs::evoked_eeg(&epochs_data, &eeg_info)?;
 
                 let evoked = epochs::evoked_eeg(&epochs_data, &eeg_info)?;
 
                 let evoked_data = EvokedData {evoked, tmin, tmax, ch_names};
+                save_epochs(&cli, &eeg_info, &epochs_data, Some(&evoked_data))?;
                 let default_plotfname = String::from("Evoked");
-                let file_name = cli.plotfname.unwrap_or(default_plotfname);
-                vis::plot_evoked(evoked_data, &file_name);
+                let file_name = cli.plotfname.clone().unwrap_or(default_plotfname);
+                vis::plot_evoked(&evoked_data, Some(&eeg_info), &picks, &file_name)?;
             },
             (true, true, false, true) => {
                 eprintln!("Error: Epochs must be constructed before averaging");
@@ -345,133 +927,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         _ => {}
     };
 
-   let _ = match cli.view {
-        true => {
-
-            match cli.format.as_str() {
-
-            "brainvision" => {
-
-                println!("Reading header from fpath {:?}", cli.hfpath);
-                let header = io::get_header(&cli.hfpath)?;
-                //println!("Header: {:?}", header);
-                let eeg_info = io::parse_header(&header)?;
-                println!("Reading markers from {:?}", cli.mfpath);
-                let vmrk_file = io::get_vmrk(&cli.mfpath)?;
-                let markers = io::parse_vmrk(&vmrk_file)?;
-                println!("Reading data from fpath {:?}", cli.dfpath);
-                let samples = io::parse_bytes(&cli.dfpath, &eeg_info)?;
-                let channels = if cli.fastio {
-                    println!("Using fast memory-mapped I/O...");
-                    io::parse_bytes_opt(&cli.dfpath, &eeg_info)?
-                } else {
-                    println!("Using standard I/O...");
-                    let samples = io::parse_bytes(&cli.dfpath, &eeg_info)?;
-
-                    let metadata = fs::metadata(&cli.dfpath)?;
-                    let file_size_bytes = metadata.len();
-                    let expected_size_from_samples = samples.len() * 2; // 2 bytes per i16 sample
-
-                    println!("[Verification] EEG file size on disk: {} bytes", file_size_bytes);
-                    println!("[Verification] Size calculated from parsed samples: {} bytes", expected_size_from_samples);
-                    if file_size_bytes == expected_size_from_samples as u64 {
-                        println!("Total sample count matches file size.");
-                    } else {
-                        println!("ERROR: Mismatch between file size and parsed samples!");
-                    }
-                    let times = io::convert_to_seconds(samples, &eeg_info)?;
-                    println!("TIMES: {:?} seconds", times.len());
-                    io::demultiplex(times, &eeg_info)?
-                };
-
-                println!("DATA READ");
-                //println!("CHANNELS {:?}", &channels.unwrap().len());
-                let data = io::vec_to_ndarray(channels);
-                println!("SHAPE OF DATA {:?}", data.shape());
-                let eeg_data = EEGData { data };
-
-                let native_options = eframe::NativeOptions {
-                    viewport: egui::ViewportBuilder::default()
-                        .with_inner_size([400.0, 300.0])
-                        .with_min_inner_size([300.0, 220.0])
-                        .with_icon(
-                            // NOTE: Adding an icon is optional
-                            eframe::icon_data::from_png_bytes(&include_bytes!("../assets/icon-256.png")[..])
-                                .expect("Failed to load icon"),
-                        ),
-                    ..Default::default()
-                };
-
-                eframe::run_native(
-                    "reegui",
-                    native_options,
-                    Box::new(|cc| Ok(Box::new(reegui::TemplateApp::new(cc, eeg_info, eeg_data, markers)))),
-                )
-
-                }
-
-            "edf" => {
-
-                println!("Reading from fpath {:?}", cli.dfpath);
-                let edf = io::parse_edf(&cli.dfpath.as_str())?;
-                //println!("Header: {:?}", header);
-                /*
-                let eeg_info = io::parse_header(&header)?;
-                println!("Reading from fpath {:?}", cli.dfpath);
-                let samples = io::parse_bytes(&cli.dfpath, &eeg_info)?;
-
-                let metadata = fs::metadata(&cli.dfpath)?;
-                let file_size_bytes = metadata.len();
-                let expected_size_from_samples = samples.len() * 2; // 2 bytes per i16 sample
-
-                println!("[Verification] EEG file size on disk: {} bytes", file_size_bytes);
-                println!("[Verification] Size calculated from parsed samples: {} bytes", expected_size_from_samples);
-
-                if file_size_bytes == expected_size_from_samples as u64 {
-                    println!("Total sample count matches file size.");
-                } else {
-                    println!("ERROR: Mismatch between file size and parsed samples!");
-                }
-                let times = io::convert_to_seconds(samples, &eeg_info)?;
-                println!("TIMES: {:?} seconds", times.len());
-                let channels = io::demultiplex(times, &eeg_info);
-                println!("DATA READ");
-                //println!("CHANNELS {:?}", &channels.unwrap().len());
-                let data = io::vec_to_ndarray(channels.unwrap());
-                println!("SHAPE OF DATA {:?}", data.shape());
-                let eeg_data = EEGData { data };
-
-                let native_options = eframe::NativeOptions {
-                    viewport: egui::ViewportBuilder::default()
-                        .with_inner_size([400.0, 300.0])
-                        .with_min_inner_size([300.0, 220.0])
-                        .with_icon(
-                            // NOTE: Adding an icon is optional
-                            eframe::icon_data::from_png_bytes(&include_bytes!("../assets/icon-256.png")[..])
-                                .expect("Failed to load icon"),
-                        ),
-                    ..Default::default()
-                };
-
-                eframe::run_native(
-                    "reegui",
-                    native_options,
-                    Box::new(|cc| Ok(Box::new(reegui::TemplateApp::new(cc, eeg_info, eeg_data)))),
-                )
-                */
-                Ok({})
-
-                }
-            _ => {
-                    println!("Error: Unknown format specified: {}", cli.format);
-                    Ok(())
-                }
-             }
-
-        }
-        _ => Ok({})
-
-    };
+    if cli.view {
+        view(&cli, &selection)?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,477 @@
+// Electrode positions
+//
+// A montage holds one Cartesian position per electrode in metres, in a head frame with x
+// towards the right ear, y towards the nose and z towards the vertex. Positions come from
+// the `[Coordinates]` of a `.vhdr`, from BrainVision `.bvef`, ASA `.elc` and BESA/EGI
+// `.sfp` files, or from the standard 10-20 and 10-10 templates.
+//
+// BrainVision stores spherical coordinates in degrees: theta is the angle from the vertex,
+// negative on the left hemisphere, and phi the azimuth from the right ear (left hemisphere
+// from the left ear), e.g. Fp1 = (1, -90, -72) and Cz = (1, 0, 0).
+
+use std::fs;
+use std::path::Path;
+
+use crate::{EEGInfo, Error};
+
+/// Head radius in metres used for positions given on a unit sphere
+pub const HEAD_RADIUS: f64 = 0.085;
+
+/// Old 10-20 names and their 10-10 equivalents
+const ALIASES: [(&str, &str); 4] = [("T3", "T7"), ("T4", "T8"), ("T5", "P7"), ("T6", "P8")];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Electrode {
+    pub name: String,
+    /// x, y, z in metres
+    pub position: [f64; 3],
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Montage {
+    pub electrodes: Vec<Electrode>,
+}
+
+/// Cartesian position on a sphere of `radius` of `BrainVision` theta and phi (degrees).
+pub fn spherical_to_cartesian(radius: f64, theta: f64, phi: f64) -> [f64; 3] {
+    let (theta, phi) = (theta.to_radians(), phi.to_radians());
+    [radius * theta.sin() * phi.cos(), radius * theta.sin() * phi.sin(), radius * theta.cos()]
+}
+
+/// `BrainVision` radius, theta and phi (degrees) of a Cartesian position.
+pub fn cartesian_to_spherical([x, y, z]: [f64; 3]) -> (f64, f64, f64) {
+    let radius = (x * x + y * y + z * z).sqrt();
+    if radius == 0.0 {
+        return (0.0, 0.0, 0.0);
+    }
+    let theta = (z / radius).clamp(-1.0, 1.0).acos().to_degrees();
+    let phi = y.atan2(x).to_degrees();
+    if x < 0.0 {
+        // Left hemisphere: negative theta and the azimuth measured from the left ear
+        let phi = phi - 180.0;
+        (radius, -theta, if phi <= -180.0 { phi + 360.0 } else { phi })
+    } else {
+        (radius, theta, phi)
+    }
+}
+
+fn norm([x, y, z]: [f64; 3]) -> f64 {
+    (x * x + y * y + z * z).sqrt()
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn scale(a: [f64; 3], factor: f64) -> [f64; 3] {
+    a.map(|value| value * factor)
+}
+
+/// Rotates `v` by `angle` (radians) around the unit axis `axis` (Rodrigues).
+fn rotate(v: [f64; 3], axis: [f64; 3], angle: f64) -> [f64; 3] {
+    let (sin, cos) = angle.sin_cos();
+    let across = cross(axis, v);
+    let along = scale(axis, dot(axis, v) * (1.0 - cos));
+    [0, 1, 2].map(|i| v[i] * cos + across[i] * sin + along[i])
+}
+
+/// Points dividing the arc from `from` to `to` around `center` (and `axis`) into `parts`
+/// equal steps, without the end point.
+fn arc(center: [f64; 3], axis: [f64; 3], from: [f64; 3], to: [f64; 3], parts: usize) -> Vec<[f64; 3]> {
+    let (u, v) = (sub(from, center), sub(to, center));
+    let angle = (dot(u, v) / (norm(u) * norm(v))).clamp(-1.0, 1.0).acos();
+    (0..parts)
+        .map(|step| {
+            let point = rotate(u, axis, angle * step as f64 / parts as f64);
+            [0, 1, 2].map(|i| point[i] + center[i])
+        })
+        .collect()
+}
+
+/// Centre of the circle through `a`, `b` and `c`.
+fn circumcenter(a: [f64; 3], b: [f64; 3], c: [f64; 3]) -> [f64; 3] {
+    let (ab, ac) = (sub(b, a), sub(c, a));
+    let normal = cross(ab, ac);
+    let offset = cross(normal, ab);
+    let offset = [0, 1, 2].map(|i| offset[i] * dot(ac, ac) + cross(ac, normal)[i] * dot(ab, ab));
+    let denominator = 2.0 * dot(normal, normal);
+    [0, 1, 2].map(|i| a[i] + offset[i] / denominator)
+}
+
+/// Unit sphere positions of the 10-10 system. Every row runs from the left equator
+/// through the midline to the right equator along the circle through these three points,
+/// divided in equal steps (10 % of the head circumference between neighbouring rows).
+fn ten_ten() -> Vec<(String, [f64; 3])> {
+    let rows: [(&str, [&str; 2]); 9] = [
+        ("Fp", ["Fp1", "Fp2"]),
+        ("AF", ["AF7", "AF8"]),
+        ("F", ["F7", "F8"]),
+        ("FC", ["FT7", "FT8"]),
+        ("C", ["T7", "T8"]),
+        ("CP", ["TP7", "TP8"]),
+        ("P", ["P7", "P8"]),
+        ("PO", ["PO7", "PO8"]),
+        ("O", ["O1", "O2"]),
+    ];
+    let mut positions = Vec::new();
+    for (row, (prefix, [left_end, right_end])) in rows.into_iter().enumerate() {
+        // Ends move 18 degrees around the equator per row, the midline 22.5 degrees
+        let side = (18.0 + 18.0 * row as f64).to_radians();
+        let midline = (90.0 - 22.5 * row as f64).to_radians();
+        let left = [-side.sin(), side.cos(), 0.0];
+        let right = [side.sin(), side.cos(), 0.0];
+        let middle = [0.0, midline.sin(), midline.cos()];
+        let center = circumcenter(left, middle, right);
+        let axis = cross(sub(left, center), sub(middle, center));
+        let axis = scale(axis, 1.0 / norm(axis));
+        let labels: Vec<String> = match prefix {
+            "Fp" | "O" => vec![left_end.to_owned(), format!("{prefix}z"), right_end.to_owned()],
+            _ => {
+                let mut labels = vec![left_end.to_owned()];
+                labels.extend(["5", "3", "1", "z", "2", "4", "6"].map(|number| format!("{prefix}{number}")));
+                labels.push(right_end.to_owned());
+                labels
+            }
+        };
+        let half = (labels.len() - 1) / 2;
+        let mut points = arc(center, axis, left, middle, half);
+        points.extend(arc(center, axis, middle, right, half));
+        points.push(right);
+        positions.extend(labels.into_iter().zip(points));
+    }
+    positions
+}
+
+/// Electrodes of the 10-20 system
+const TEN_TWENTY: [&str; 21] = [
+    "Fp1", "Fpz", "Fp2", "F7", "F3", "Fz", "F4", "F8", "T7", "C3", "Cz", "C4", "T8", "P7", "P3", "Pz", "P4", "P8", "O1",
+    "Oz", "O2",
+];
+
+impl Montage {
+    /// Position of electrode `name`, ignoring case and accepting the old names T3-T6.
+    pub fn position(&self, name: &str) -> Option<[f64; 3]> {
+        let find = |wanted: &str| {
+            self.electrodes
+                .iter()
+                .find(|electrode| electrode.name.eq_ignore_ascii_case(wanted))
+                .map(|electrode| electrode.position)
+        };
+        find(name).or_else(|| {
+            ALIASES
+                .iter()
+                .find_map(|&(old, new)| {
+                    if name.eq_ignore_ascii_case(old) {
+                        Some(new)
+                    } else if name.eq_ignore_ascii_case(new) {
+                        Some(old)
+                    } else {
+                        None
+                    }
+                })
+                .and_then(find)
+        })
+    }
+
+    /// Names of `ch_names` without a position.
+    pub fn missing(&self, ch_names: &[String]) -> Vec<String> {
+        ch_names.iter().filter(|name| self.position(name).is_none()).cloned().collect()
+    }
+
+    /// Standard template by name: `standard_1020` or `standard_1010`.
+    pub fn template(name: &str) -> Option<Self> {
+        let all = ten_ten();
+        let keep = |label: &str| match name {
+            "standard_1020" => TEN_TWENTY.contains(&label),
+            _ => true,
+        };
+        if !matches!(name, "standard_1020" | "standard_1010") {
+            return None;
+        }
+        Some(Self {
+            electrodes: all
+                .into_iter()
+                .filter(|(label, _)| keep(label))
+                .map(|(name, position)| Electrode { name, position: scale(position, HEAD_RADIUS) })
+                .collect(),
+        })
+    }
+
+    /// Montage of the `[Coordinates]` of a `.vhdr`, `None` when no channel has a position.
+    /// Channels with a radius of 0 have no position.
+    pub fn from_channels(eeg_info: &EEGInfo) -> Option<Self> {
+        let electrodes: Vec<Electrode> = eeg_info
+            .channels
+            .iter()
+            .filter_map(|channel| {
+                let (radius, theta, phi) = channel.coordinates?;
+                (radius > 0.0).then(|| Electrode {
+                    name: channel.name.clone(),
+                    position: spherical_to_cartesian(HEAD_RADIUS, theta, phi),
+                })
+            })
+            .collect();
+        (!electrodes.is_empty()).then_some(Self { electrodes })
+    }
+
+    /// Reads a `.bvef`, `.elc` or `.sfp` file, or a template name (see `Montage::template`).
+    pub fn read(path: &str) -> Result<Self, Error> {
+        if let Some(template) = Self::template(path) {
+            return Ok(template);
+        }
+        let content = fs::read_to_string(path).map_err(Error::io(path))?;
+        let extension = Path::new(path).extension().and_then(|ext| ext.to_str()).map(str::to_lowercase);
+        let montage = match extension.as_deref() {
+            Some("bvef") => parse_bvef(&content)?,
+            Some("elc") => parse_elc(&content)?,
+            Some("sfp") => parse_sfp(&content)?,
+            _ => return Err(Error::UnsupportedFormat(format!("montage {path}, expected a .bvef, .elc or .sfp file, standard_1020 or standard_1010"))),
+        };
+        if montage.electrodes.is_empty() {
+            return Err(Error::Malformed(format!("No electrodes found in {path}")));
+        }
+        Ok(montage)
+    }
+}
+
+/// Text of every `<tag>` element inside `xml`.
+fn xml_elements<'x>(xml: &'x str, tag: &str) -> Vec<&'x str> {
+    let (open, close) = (format!("<{tag}>"), format!("</{tag}>"));
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        let Some(end) = after.find(&close) else { break };
+        elements.push(after[..end].trim());
+        rest = &after[end + close.len()..];
+    }
+    elements
+}
+
+/// `BrainVision` electrode files: `<Electrode>` elements with `<Name>`, `<Theta>`, `<Phi>`
+/// and `<Radius>`. Positions are on a unit sphere scaled to `HEAD_RADIUS`.
+fn parse_bvef(content: &str) -> Result<Montage, Error> {
+    let mut electrodes = Vec::new();
+    for element in xml_elements(content, "Electrode") {
+        let field = |tag: &str| xml_elements(element, tag).first().copied().unwrap_or_default();
+        let name = field("Name");
+        let radius: f64 = field("Radius").parse().unwrap_or(1.0);
+        let theta: f64 = field("Theta").parse().map_err(|_err| Error::Malformed(format!("Invalid theta of electrode {name}")))?;
+        let phi: f64 = field("Phi").parse().map_err(|_err| Error::Malformed(format!("Invalid phi of electrode {name}")))?;
+        if radius > 0.0 {
+            electrodes.push(Electrode { name: name.to_owned(), position: spherical_to_cartesian(HEAD_RADIUS, theta, phi) });
+        }
+    }
+    Ok(Montage { electrodes })
+}
+
+/// Metres per unit of an `.elc` `UnitPosition`
+fn unit_scale(unit: &str) -> Result<f64, Error> {
+    match unit {
+        "mm" => Ok(0.001),
+        "cm" => Ok(0.01),
+        "m" => Ok(1.0),
+        _ => Err(Error::Malformed(format!("Unknown position unit {unit:?}"))),
+    }
+}
+
+/// Parses one coordinate of the electrode file `line`.
+fn coordinate(value: &str, line: &str) -> Result<f64, Error> {
+    value.parse().map_err(|_err| Error::Malformed(format!("Invalid coordinate {value:?} in {line:?}")))
+}
+
+/// ASA electrode files: `UnitPosition`, a `Positions` section of `x y z` lines (optionally
+/// prefixed by `label :`) and a `Labels` section with one name per line.
+fn parse_elc(content: &str) -> Result<Montage, Error> {
+    let mut unit = 0.001;
+    let mut positions: Vec<(Option<String>, [f64; 3])> = Vec::new();
+    let mut labels: Vec<String> = Vec::new();
+    let mut section = "";
+    for line in content.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        if let Some(value) = line.strip_prefix("UnitPosition") {
+            unit = unit_scale(value.trim())?;
+            continue;
+        }
+        match line {
+            "Positions" | "Labels" => {
+                section = line;
+                continue;
+            }
+            _ if line.contains('=') || line.starts_with("ReferenceLabel") => continue,
+            _ => {}
+        }
+        match section {
+            "Positions" => {
+                let (label, coords) = match line.split_once(':') {
+                    Some((label, coords)) => (Some(label.trim().to_owned()), coords),
+                    None => (None, line),
+                };
+                let values: Vec<f64> = coords.split_whitespace().map(|value| coordinate(value, line)).collect::<Result<_, _>>()?;
+                let [x, y, z] = values[..] else {
+                    return Err(Error::Malformed(format!("Expected x y z, found {line:?}")));
+                };
+                positions.push((label, [x, y, z]));
+            }
+            "Labels" => labels.extend(line.split_whitespace().map(str::to_owned)),
+            _ => {}
+        }
+    }
+    let mut labels = labels.into_iter();
+    let electrodes = positions
+        .into_iter()
+        .filter_map(|(label, position)| {
+            let name = label.or_else(|| labels.next())?;
+            Some(Electrode { name, position: scale(position, unit) })
+        })
+        .collect();
+    Ok(Montage { electrodes })
+}
+
+/// BESA/EGI surface point files: `label x y z` lines in arbitrary units, scaled so the
+/// median distance from the origin is `HEAD_RADIUS`.
+fn parse_sfp(content: &str) -> Result<Montage, Error> {
+    let mut electrodes = Vec::new();
+    for line in content.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [name, x, y, z] = fields[..] else {
+            return Err(Error::Malformed(format!("Expected label x y z, found {line:?}")));
+        };
+        electrodes.push(Electrode { name: name.to_owned(), position: [coordinate(x, line)?, coordinate(y, line)?, coordinate(z, line)?] });
+    }
+    let mut distances: Vec<f64> = electrodes.iter().map(|electrode| norm(electrode.position)).collect();
+    distances.sort_by(f64::total_cmp);
+    if let Some(&median) = distances.get(distances.len() / 2).filter(|&&median| median > 0.0) {
+        for electrode in &mut electrodes {
+            electrode.position = scale(electrode.position, HEAD_RADIUS / median);
+        }
+    }
+    Ok(Montage { electrodes })
+}
+
+/// Attaches `montage` to `eeg_info` and updates the channel coordinates (written to the
+/// `[Coordinates]` of saved headers) of every channel it has a position for.
+/// Returns the channels without a position.
+pub fn set_montage(eeg_info: &mut EEGInfo, montage: Montage) -> Vec<String> {
+    for channel in &mut eeg_info.channels {
+        if let Some(position) = montage.position(&channel.name) {
+            let (_, theta, phi) = cartesian_to_spherical(position);
+            channel.coordinates = Some((1.0, theta, phi));
+        }
+    }
+    let missing = montage.missing(&eeg_info.ch_names);
+    eeg_info.montage = Some(montage);
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HEAD_RADIUS, Montage, cartesian_to_spherical, parse_bvef, parse_elc, parse_sfp, set_montage, spherical_to_cartesian};
+    use crate::Error;
+    use crate::io::parse_header;
+    use crate::io::tests::{HEADER, temp_path};
+
+    fn assert_close(actual: [f64; 3], expected: [f64; 3]) {
+        assert!(actual.iter().zip(expected).all(|(a, e)| (a - e).abs() < 1e-9), "{actual:?} != {expected:?}");
+    }
+
+    fn position(montage: &Montage, name: &str) -> Result<[f64; 3], Error> {
+        montage.position(name).ok_or_else(|| Error::NotFound(format!("No electrode {name} in the montage.")))
+    }
+
+    #[test]
+    fn round_trips_spherical_coordinates() {
+        for (theta, phi) in [(0.0, 0.0), (90.0, 0.0), (-90.0, 0.0), (-90.0, -72.0), (45.0, 45.0), (-60.0, 30.0), (30.0, -60.0)] {
+            let spherical = cartesian_to_spherical(spherical_to_cartesian(2.0, theta, phi));
+            assert_close(spherical.into(), [2.0, theta, phi]);
+        }
+        // x towards the right ear, y towards the nose, z towards the vertex
+        assert_close(spherical_to_cartesian(1.0, 90.0, 0.0), [1.0, 0.0, 0.0]);
+        assert_close(spherical_to_cartesian(1.0, -90.0, 90.0), [0.0, -1.0, 0.0]);
+        assert_close(spherical_to_cartesian(1.0, 0.0, 0.0), [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn parses_bvef_electrodes() -> Result<(), Error> {
+        let bvef = "<?xml version=\"1.0\"?>\n<Electrodes>\n\
+            <Electrode><Name>Fp1</Name><Theta>-90</Theta><Phi>-72</Phi><Radius>1</Radius></Electrode>\n\
+            <Electrode><Name>Cz</Name><Theta>0</Theta><Phi>0</Phi><Radius>1</Radius></Electrode>\n\
+            <Electrode><Name>GND</Name><Theta>0</Theta><Phi>0</Phi><Radius>0</Radius></Electrode>\n\
+            </Electrodes>\n";
+        let montage = parse_bvef(bvef)?;
+
+        assert_eq!(montage.electrodes.iter().map(|electrode| electrode.name.as_str()).collect::<Vec<_>>(), ["Fp1", "Cz"]);
+        assert_close(position(&montage, "Cz")?, [0.0, 0.0, HEAD_RADIUS]);
+        assert_close(position(&montage, "Fp1")?, spherical_to_cartesian(HEAD_RADIUS, -90.0, -72.0));
+        assert!(matches!(parse_bvef("<Electrode><Name>Fp1</Name><Theta>x</Theta><Phi>0</Phi></Electrode>"), Err(Error::Malformed(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn parses_elc_positions_and_labels() -> Result<(), Error> {
+        let elc = "# ASA electrode file\nReferenceLabel\tavg\nUnitPosition\tmm\nNumberPositions=\t2\n\
+            Positions\nFp1 : -27 83 -3\n0 0 85\nLabels\nCz\n";
+        let montage = parse_elc(elc)?;
+
+        assert_eq!(montage.electrodes.len(), 2);
+        assert_close(position(&montage, "Fp1")?, [-0.027, 0.083, -0.003]);
+        assert_close(position(&montage, "Cz")?, [0.0, 0.0, 0.085]);
+        assert!(matches!(parse_elc("UnitPosition\tinch\n"), Err(Error::Malformed(_))));
+        assert!(matches!(parse_elc("Positions\n1 2\n"), Err(Error::Malformed(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn parses_sfp_points_scaled_to_the_head() -> Result<(), Error> {
+        let montage = parse_sfp("Fp1 -2.7 8.3 -0.3\nCz 0 0 8.5\nT7 -8.5 0 0\n")?;
+
+        // The median distance of 8.5 units becomes the head radius
+        assert_close(position(&montage, "Cz")?, [0.0, 0.0, HEAD_RADIUS]);
+        assert_close(position(&montage, "T3")?, [-HEAD_RADIUS, 0.0, 0.0]);
+        assert_close(position(&montage, "fp1")?, [-0.027, 0.083, -0.003]);
+        assert!(matches!(parse_sfp("Fp1 1 2\n"), Err(Error::Malformed(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn reads_montage_files_by_extension() -> Result<(), Error> {
+        let (sfp, txt) = (temp_path("montage.sfp"), temp_path("montage.txt"));
+        for path in [&sfp, &txt] {
+            std::fs::write(path, "Cz 0 0 1\n").map_err(Error::io(path))?;
+        }
+        let (montage, unsupported) = (Montage::read(&sfp), Montage::read(&txt));
+        for path in [&sfp, &txt] {
+            std::fs::remove_file(path).ok();
+        }
+
+        assert_close(position(&montage?, "Cz")?, [0.0, 0.0, HEAD_RADIUS]);
+        assert!(matches!(unsupported, Err(Error::UnsupportedFormat(_))));
+        assert_eq!(Montage::read("standard_1020")?.electrodes.len(), 21);
+        Ok(())
+    }
+
+    #[test]
+    fn sets_the_channel_coordinates() -> Result<(), Error> {
+        let mut eeg_info = parse_header(&Some(HEADER.to_owned()))?;
+        let montage = Montage::template("standard_1020").ok_or_else(|| Error::NotFound("No standard_1020 template.".into()))?;
+
+        let missing = set_montage(&mut eeg_info, montage);
+
+        assert_eq!(missing, ["EMG,R"]);
+        let coordinates: Vec<_> = eeg_info.channels.iter().map(|channel| channel.coordinates).collect();
+        let fp1 = coordinates[0].ok_or_else(|| Error::NotFound("Fp1 has no coordinates.".into()))?;
+        assert_close(fp1.into(), [1.0, -90.0, -72.0]);
+        let (radius, theta, _) = coordinates[1].ok_or_else(|| Error::NotFound("Cz has no coordinates.".into()))?;
+        assert_close([radius, theta, 0.0], [1.0, 0.0, 0.0]);
+        assert_eq!(coordinates[2], None);
+        assert!(eeg_info.montage.is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+// Channel picking
+//
+// Processing, epoching and plotting take a `Picks` that restricts them to some channels,
+// so the EEG can be filtered without touching the EMG or the trigger channel. Channels can
+// be picked by name, by type, by a regular expression on the name or by index. The CLI
+// writes them as `Fp1,Cz`, `name:Fp1,Cz`, `type:eeg,eog`, `regex:^F` or `index:0,2`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use regex::Regex;
+
+use crate::{ChannelType, EEGInfo, Error};
+
+#[derive(Debug, Clone, Default)]
+pub enum Picks {
+    #[default]
+    All,
+    /// Channels with these names, in this order
+    Names(Vec<String>),
+    /// Channels of these types, in channel order
+    Types(Vec<ChannelType>),
+    /// Channels whose name matches, in channel order
+    Regex(Regex),
+    /// Channels at these positions, in this order
+    Indices(Vec<usize>),
+}
+
+impl Picks {
+    pub fn regex(pattern: &str) -> Result<Self, Error> {
+        Regex::new(pattern)
+            .map(Self::Regex)
+            .map_err(|err| Error::InvalidParameter(format!("Invalid channel pattern {pattern:?}: {err}")))
+    }
+
+    /// Positions of the picked channels in `eeg_info.channels`.
+    pub fn indices(&self, eeg_info: &EEGInfo) -> Result<Vec<usize>, Error> {
+        self.indices_in(&eeg_info.ch_names, Some(eeg_info))
+    }
+
+    /// Names of the picked channels of `eeg_info`.
+    pub fn names(&self, eeg_info: &EEGInfo) -> Result<Vec<String>, Error> {
+        Ok(self.indices(eeg_info)?.into_iter().map(|idx| eeg_info.ch_names[idx].clone()).collect())
+    }
+
+    /// Positions of the picked channels in `ch_names`, e.g. the channels of epochs or evoked
+    /// data. Types and indices are looked up in `eeg_info`, types by name and inferred from
+    /// the name for channels it does not describe. An empty pick is an error.
+    pub fn indices_in(&self, ch_names: &[String], eeg_info: Option<&EEGInfo>) -> Result<Vec<usize>, Error> {
+        let kind = |name: &str| {
+            eeg_info
+                .and_then(|eeg_info| eeg_info.channels.iter().find(|channel| channel.name == name))
+                .map_or_else(|| ChannelType::infer(name, ""), |channel| channel.kind)
+        };
+        let indices: Vec<usize> = match self {
+            Self::All => (0..ch_names.len()).collect(),
+            Self::Names(names) => names
+                .iter()
+                .map(|name| ch_names.iter().position(|ch| ch == name).ok_or_else(|| Error::NotFound(format!("No channel {name:?} in the data"))))
+                .collect::<Result<_, _>>()?,
+            Self::Types(types) => (0..ch_names.len()).filter(|&idx| types.contains(&kind(&ch_names[idx]))).collect(),
+            Self::Regex(regex) => (0..ch_names.len()).filter(|&idx| regex.is_match(&ch_names[idx])).collect(),
+            // Positions in `eeg_info` when given, the channels may be a subset of it
+            Self::Indices(indices) => {
+                let all_names = eeg_info.map_or(ch_names, |eeg_info| &eeg_info.ch_names);
+                if let Some(&idx) = indices.iter().find(|&&idx| idx >= all_names.len()) {
+                    return Err(Error::NotFound(format!("No channel {idx}, the data has {} channels", all_names.len())));
+                }
+                indices
+                    .iter()
+                    .map(|&idx| {
+                        let name = &all_names[idx];
+                        ch_names.iter().position(|ch| ch == name).ok_or_else(|| Error::NotFound(format!("No channel {name:?} in the data")))
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+        };
+        if indices.is_empty() {
+            return Err(Error::NotFound(format!("No channel matches {self}")));
+        }
+        Ok(indices)
+    }
+}
+
+/// Regular expressions are compared by their pattern.
+impl PartialEq for Picks {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::All, Self::All) => true,
+            (Self::Names(a), Self::Names(b)) => a == b,
+            (Self::Types(a), Self::Types(b)) => a == b,
+            (Self::Regex(a), Self::Regex(b)) => a.as_str() == b.as_str(),
+            (Self::Indices(a), Self::Indices(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Writes the syntax `FromStr` reads.
+impl fmt::Display for Picks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |items: Vec<String>| items.join(",");
+        match self {
+            Self::All => write!(f, "all"),
+            Self::Names(names) => write!(f, "name:{}", join(names.iter().map(|name| name.replace(',', "\\1")).collect())),
+            Self::Types(types) => write!(f, "type:{}", join(types.iter().map(ToString::to_string).collect())),
+            Self::Regex(regex) => write!(f, "regex:{}", regex.as_str()),
+            Self::Indices(indices) => write!(f, "index:{}", join(indices.iter().map(ToString::to_string).collect())),
+        }
+    }
+}
+
+/// `all`, `type:<types>`, `regex:<pattern>`, `index:<indices>` or `name:<names>`, lists are
+/// comma separated. Anything else is a list of names; commas inside a name are written as
+/// `\1` like the `.vhdr` does.
+impl FromStr for Picks {
+    type Err = Error;
+
+    fn from_str(picks: &str) -> Result<Self, Self::Err> {
+        let list = |list: &str| list.split(',').map(str::trim).filter(|item| !item.is_empty()).map(str::to_owned).collect::<Vec<_>>();
+        let names = |names: &str| Self::Names(list(names).iter().map(|name| name.replace("\\1", ",")).collect());
+        match picks.split_once(':') {
+            _ if picks.trim() == "all" => Ok(Self::All),
+            Some(("type", types)) => Ok(Self::Types(list(types).iter().map(|kind| kind.parse()).collect::<Result<_, _>>()?)),
+            Some(("regex", pattern)) => Self::regex(pattern),
+            Some(("index", indices)) => Ok(Self::Indices(
+                list(indices)
+                    .iter()
+                    .map(|idx| idx.parse().map_err(|_err| Error::InvalidParameter(format!("Invalid channel index {idx:?}"))))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Some(("name", list)) => Ok(names(list)),
+            _ => Ok(names(picks)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Picks;
+    use crate::io::parse_header;
+    use crate::io::tests::HEADER;
+    use crate::{ChannelType, Error};
+
+    #[test]
+    fn parses_every_syntax() -> Result<(), Error> {
+        assert_eq!("all".parse::<Picks>()?, Picks::All);
+        assert_eq!("Fp1, EMG\\1R".parse::<Picks>()?, Picks::Names(vec!["Fp1".to_owned(), "EMG,R".to_owned()]));
+        assert_eq!("name:Cz,Fp1".parse::<Picks>()?, Picks::Names(vec!["Cz".to_owned(), "Fp1".to_owned()]));
+        assert_eq!("type:eeg,emg".parse::<Picks>()?, Picks::Types(vec![ChannelType::Eeg, ChannelType::Emg]));
+        assert_eq!("regex:^F".parse::<Picks>()?, Picks::regex("^F")?);
+        assert_eq!("index:2,0".parse::<Picks>()?, Picks::Indices(vec![2, 0]));
+        for picks in ["name:Cz,EMG\\1R", "type:eeg", "regex:^(Fp|C)", "index:1"] {
+            assert_eq!(picks.parse::<Picks>()?.to_string(), picks);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_picks() {
+        assert!(matches!("regex:(Fp".parse::<Picks>(), Err(Error::InvalidParameter(_))));
+        assert!(matches!("index:0,x".parse::<Picks>(), Err(Error::InvalidParameter(_))));
+    }
+
+    #[test]
+    fn picks_channel_indices() -> Result<(), Error> {
+        let eeg_info = parse_header(&Some(HEADER.to_owned()))?;
+        assert_eq!(Picks::All.indices(&eeg_info)?, vec![0, 1, 2]);
+        assert_eq!("Cz,Fp1".parse::<Picks>()?.indices(&eeg_info)?, vec![1, 0]);
+        assert_eq!("type:eeg".parse::<Picks>()?.indices(&eeg_info)?, vec![0, 1]);
+        assert_eq!("type:emg".parse::<Picks>()?.indices(&eeg_info)?, vec![2]);
+        assert_eq!("regex:^(Fp|EMG)".parse::<Picks>()?.indices(&eeg_info)?, vec![0, 2]);
+        assert_eq!("index:2,0".parse::<Picks>()?.indices(&eeg_info)?, vec![2, 0]);
+        assert_eq!("index:2".parse::<Picks>()?.names(&eeg_info)?, vec!["EMG,R".to_owned()]);
+
+        // Indices refer to the recording, also for a subset of its channels
+        let subset = ["Cz".to_owned(), "EMG,R".to_owned()];
+        assert_eq!("index:2,1".parse::<Picks>()?.indices_in(&subset, Some(&eeg_info))?, vec![1, 0]);
+        assert!(matches!("index:0".parse::<Picks>()?.indices_in(&subset, Some(&eeg_info)), Err(Error::NotFound(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_missing_channels() -> Result<(), Error> {
+        let eeg_info = parse_header(&Some(HEADER.to_owned()))?;
+        assert!(matches!("index:0,3".parse::<Picks>()?.indices(&eeg_info), Err(Error::NotFound(_))));
+        assert!(matches!("Pz".parse::<Picks>()?.indices(&eeg_info), Err(Error::NotFound(_))));
+        assert!(matches!("type:eog".parse::<Picks>()?.indices(&eeg_info), Err(Error::NotFound(_))));
+        assert!(matches!("regex:^O".parse::<Picks>()?.indices(&eeg_info), Err(Error::NotFound(_))));
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use std::iter::Sum;
+use std::ops::Range;
 use rayon::prelude::*;
 use std::sync::Arc;
 use ndarray::{s, Array2, Array3, ArrayBase, ViewRepr};
@@ -9,86 +10,86 @@ use cubic_spline::{Points, Point, SplineOpts,TryInto};
 use nalgebra::Complex;
 use rustfft::FftNum;
 
-use crate::Markers;
-use crate::EEGInfo;
+use crate::{Marker, Markers};
+use crate::{EEGInfo, Error, Picks};
+
+/// Description of the markers the stimulator writes with every TMS pulse
+pub const TMS_PULSE: &str = "R128";
 
 // Helper functions
-pub fn vec_to_ndarray<T: Clone>(v: Vec<Vec<T>>) -> Array2<T> {
-    if v.is_empty() {
-        return Array2::from_shape_vec((0, 0), Vec::new()).unwrap();
-    }
-    let nrows = v.len();
-    let ncols = v[0].len();
-    let mut data = Vec::with_capacity(nrows * ncols);
-    for row in &v {
-        assert_eq!(row.len(), ncols);
-        data.extend_from_slice(&row);
-    }
-    Array2::from_shape_vec((nrows, ncols), data).unwrap()
+pub fn vec_to_ndarray<T: Clone>(v: Vec<Vec<T>>) -> Result<Array2<T>, Error> {
+    crate::io::vec_to_ndarray(v)
 }
 
-pub fn vec_to_ndarray3<T: Clone>(v: Vec<Vec<Vec<T>>>) -> Array3<T> {
-    if v.is_empty() {
-        return Array3::from_shape_vec((0, 0, 0), Vec::new()).unwrap();
-    }
+pub fn vec_to_ndarray3<T: Clone>(v: Vec<Vec<Vec<T>>>) -> Result<Array3<T>, Error> {
     let d1 = v.len();
-    let d2 = v[0].len();
-    let d3 = v[0][0].len();
+    let d2 = v.first().map_or(0, Vec::len);
+    let d3 = v.first().and_then(|d1_vec| d1_vec.first()).map_or(0, Vec::len);
 
     let mut flat_data = Vec::with_capacity(d1 * d2 * d3);
 
     for d1_vec in &v {
-        // Enforce the cuboid shape, just like in your 2D function
-        assert_eq!(d1_vec.len(), d2);
+        // Enforce the cuboid shape, just like in the 2D function
+        if d1_vec.len() != d2 {
+            return Err(Error::size_mismatch("Channels per epoch", d2, d1_vec.len()));
+        }
         for d2_vec in d1_vec {
-            assert_eq!(d2_vec.len(), d3);
+            if d2_vec.len() != d3 {
+                return Err(Error::size_mismatch("Samples per epoch", d3, d2_vec.len()));
+            }
             flat_data.extend_from_slice(d2_vec);
         }
     }
 
-    Array3::from_shape_vec((d1, d2, d3), flat_data).unwrap()
+    Ok(Array3::from_shape_vec((d1, d2, d3), flat_data)?)
 }
 
-pub fn get_one_channel(ch_idx: usize, eeg_data: &Array2<i16>) -> Result<Vec<i16>, Box<dyn std::error::Error>>{
+pub fn get_one_channel(ch_idx: usize, eeg_data: &Array2<f32>) -> Result<Vec<f32>, Error>{
     if eeg_data.is_empty(){
-        return Err("Data is empty..".into());
+        return Err(Error::InvalidParameter("Data is empty..".into()));
+    }
+    if ch_idx >= eeg_data.nrows() {
+        return Err(Error::NotFound(format!("Channel {ch_idx} of {} channels", eeg_data.nrows())));
     }
-    let ch_data = eeg_data.row(ch_idx).clone();
+    let ch_data = eeg_data.row(ch_idx);
     let ch_vec = ch_data.to_vec();
     Ok(ch_vec)
 }
 
 
-// Replace interval around of the TMS pulse with 0
+// Replace interval around of the TMS pulse with 0 in the picked channels, the pulses are
+// the triggers described as `pulse`
 pub fn remove_tms_pulse(
     tmin_cut: f64,
     tmax_cut: f64,
     markers: &Markers,
+    pulse: &str,
     eeg_info: &EEGInfo,
-    eeg_data: &Array2<i16>,
-) -> Result<Array2<i16>, Box<dyn std::error::Error>> {
+    eeg_data: &Array2<f32>,
+    picks: &Picks,
+) -> Result<Array2<f32>, Error> {
     if eeg_data.is_empty() {
-        return Ok(Array2::from_shape_vec((0, 0), Vec::new()).unwrap());
+        return Ok(Array2::zeros((0, 0)));
     }
 
+    let picked = picks.indices(eeg_info)?;
     let mut data_copy = eeg_data.clone();
     let n_samples = data_copy.ncols();
 
-    let min_samples = (tmin_cut * eeg_info.sfreq as f64).round() as usize;
-    let max_samples = (tmax_cut * eeg_info.sfreq as f64).round() as usize;
+    let min_samples = (tmin_cut * eeg_info.sfreq).round() as usize;
+    let max_samples = (tmax_cut * eeg_info.sfreq).round() as usize;
 
-    for &marker_pos in &markers.markers {
-        let marker_idx = marker_pos.round() as usize;
+    for marker_idx in markers.described(pulse).map(|marker| marker.position) {
 
         let start_cut = marker_idx.saturating_sub(min_samples);
         let end_cut = (marker_idx + max_samples).min(n_samples);
         if start_cut >= end_cut {
             continue;
         }
-        for ch_idx in 0..data_copy.nrows() {
+        for &ch_idx in &picked {
             let mut slice = data_copy.slice_mut(s![ch_idx, start_cut..end_cut]);
 
-            slice.fill(0);
+            slice.fill(0.0);
         }
     }
 
@@ -96,35 +97,41 @@ pub fn remove_tms_pulse(
 }
 
 
-// r
+/// Replaces the interval around every TMS pulse of the picked channels with a cubic
+/// spline between the samples on either side.
+///
+/// The pulses are the triggers described as `pulse`, usually [`TMS_PULSE`], other triggers
+/// are left alone.
 pub fn rm_interp_tms_pulse(
     tmin_cut: f64,
     tmax_cut: f64,
     markers: &Markers,
+    pulse: &str,
     eeg_info: &EEGInfo,
-    eeg_data: &Array2<i16>,
-) -> Result<Array2<i16>, Box<dyn std::error::Error>> {
+    eeg_data: &Array2<f32>,
+    picks: &Picks,
+) -> Result<Array2<f32>, Error> {
     if eeg_data.is_empty() {
-        return Ok(Array2::from_shape_vec((0, 0), Vec::new()).unwrap());
+        return Ok(Array2::zeros((0, 0)));
     }
 
+    let picked = picks.indices(eeg_info)?;
     let mut data_copy = eeg_data.clone();
     let n_samples = data_copy.ncols();
 
-    let min_samples = (tmin_cut * eeg_info.sfreq as f64).round() as usize;
-    let max_samples = (tmax_cut * eeg_info.sfreq as f64).round() as usize;
+    let min_samples = (tmin_cut * eeg_info.sfreq).round() as usize;
+    let max_samples = (tmax_cut * eeg_info.sfreq).round() as usize;
 
-    for &marker_pos in &markers.markers {
-        let marker_idx = marker_pos.round() as usize;
+    for marker_idx in markers.described(pulse).map(|marker| marker.position) {
 
         let start_cut = marker_idx.saturating_sub(min_samples);
         let end_cut = (marker_idx + max_samples).min(n_samples);
         if start_cut >= end_cut {
             continue;
         }
-        for ch_idx in 0..data_copy.nrows() {
+        for &ch_idx in &picked {
                     if start_cut == 0 || end_cut >= n_samples {
-                        data_copy.slice_mut(s![ch_idx, start_cut..end_cut]).fill(0);
+                        data_copy.slice_mut(s![ch_idx, start_cut..end_cut]).fill(0.0);
                         continue;
                     }
                     let p1_x = (start_cut - 1) as f64;
@@ -137,12 +144,13 @@ pub fn rm_interp_tms_pulse(
                     if gap_len == 0 { continue; }
                     let opts = SplineOpts::new().num_of_segments(gap_len as u32);
 
-                    let points = <cubic_spline::Points as cubic_spline::TryFrom<_>>::try_from(&source_points)?;
-                    let calculated_points = points.calc_spline(&opts)?;
+                    let spline_error = |err: cubic_spline::Error| Error::InvalidParameter(format!("Interpolation failed: {err}"));
+                    let points = <cubic_spline::Points as cubic_spline::TryFrom<_>>::try_from(&source_points).map_err(spline_error)?;
+                    let calculated_points = points.calc_spline(&opts).map_err(spline_error)?;
 
                     for i in 0..gap_len {
                         let new_y = calculated_points.get_ref()[i + 1].y;
-                        data_copy[[ch_idx, start_cut + i]] = new_y.round() as i16;
+                        data_copy[[ch_idx, start_cut + i]] = new_y as f32;
                     }
                 }
     }
@@ -150,10 +158,21 @@ pub fn rm_interp_tms_pulse(
 }
 
 
-pub fn design_butter_lp<F>(order: usize, lowcut: F, fs: F) -> Vec<Sos<F>>
+/// Checks that a cut-off lies between 0 Hz and the Nyquist frequency of `fs`.
+fn check_cutoff<F: Float>(cutoff: F, fs: F) -> Result<(), Error> {
+    let (cutoff, fs) = (cutoff.to_f64().unwrap_or(f64::NAN), fs.to_f64().unwrap_or(f64::NAN));
+    if cutoff > 0.0 && cutoff < fs / 2.0 {
+        Ok(())
+    } else {
+        Err(Error::InvalidParameter(format!("Cut-off {cutoff} Hz must be between 0 Hz and the Nyquist frequency {} Hz", fs / 2.0)))
+    }
+}
+
+pub fn design_butter_lp<F>(order: usize, lowcut: F, fs: F) -> Result<Vec<Sos<F>>, Error>
 where
     F: Float + RealField + Sum,
 {
+    check_cutoff(lowcut, fs)?;
     //print!("Building Butterworth filter of order {:?} with lowcut {:?}", order, lowcut);
     // Design Second Order Section (SOS) filter
     let filter = butter_dyn(
@@ -165,15 +184,16 @@ where
         Some(fs),
     );
     let DigitalFilter::Sos(SosFormatFilter {sos}) = filter else {
-        panic!("Failed to design filter");
+        return Err(Error::InvalidParameter("Failed to design the Butterworth filter".into()));
     };
-    sos
+    Ok(sos)
 }
 
-pub fn design_butter_hp<F>(order: usize, highcut: F, fs: F) -> Vec<Sos<F>>
+pub fn design_butter_hp<F>(order: usize, highcut: F, fs: F) -> Result<Vec<Sos<F>>, Error>
 where
     F: Float + RealField + Sum,
 {
+    check_cutoff(highcut, fs)?;
     //print!("Building Butterworth filter of order {:?} with highcut {:?}", order, highcut);
     // Design Second Order Section (SOS) filter
     let filter = butter_dyn(
@@ -185,76 +205,86 @@ where
         Some(fs),
     );
     let DigitalFilter::Sos(SosFormatFilter {sos}) = filter else {
-        panic!("Failed to design filter");
+        return Err(Error::InvalidParameter("Failed to design the Butterworth filter".into()));
     };
-    sos
+    Ok(sos)
 }
 
 
+/// Zero-phase Butterworth highpass of the picked channels, the others are copied unchanged.
 pub fn hp_filter(
     lfreq: f64,
     eeg_info: &EEGInfo,
-    eeg_data: &Array2<i16>,
-) -> Result<Array2<i16>, Box<dyn std::error::Error>> {
+    eeg_data: &Array2<f32>,
+    picks: &Picks,
+) -> Result<Array2<f32>, Error> {
     if eeg_data.is_empty() {
-        return Ok(Array2::from_shape_vec((0, 0), Vec::new()).unwrap());
+        return Ok(Array2::zeros((0, 0)));
     }
 
-    let sos = design_butter_hp(2, lfreq, eeg_info.sfreq as f64);
+    let sos = design_butter_hp(2, lfreq, eeg_info.sfreq)?;
+    let picked = picks.indices(eeg_info)?;
     let n_channels = eeg_data.nrows();
 
-    let data_vec_vec: Vec<Vec<i16>> = (0..n_channels)
+    let data_vec_vec: Vec<Vec<f32>> = (0..n_channels)
         .into_par_iter()
         .map(|ch_idx| {
             let channel = eeg_data.row(ch_idx);
+            if !picked.contains(&ch_idx) {
+                return channel.to_vec();
+            }
             let filtered: Vec<f64> = sosfiltfilt_dyn(
                 channel.into_iter().map(|sample| *sample as f64),
                 &sos
             );
             filtered.into_iter()
-                .map(|sample| sample.round() as i16)
+                .map(|sample| sample as f32)
                 .collect()
         })
         .collect();
 
-    let data = vec_to_ndarray(data_vec_vec);
-    Ok(data)
+    vec_to_ndarray(data_vec_vec)
 }
 
+/// Zero-phase Butterworth lowpass of the picked channels, the others are copied unchanged.
 pub fn lp_filter(
     hfreq: f64,
     eeg_info: &EEGInfo,
-    eeg_data: &Array2<i16>,
-) -> Result<Array2<i16>, Box<dyn std::error::Error>> {
+    eeg_data: &Array2<f32>,
+    picks: &Picks,
+) -> Result<Array2<f32>, Error> {
     if eeg_data.is_empty() {
-        return Ok(Array2::from_shape_vec((0, 0), Vec::new()).unwrap());
+        return Ok(Array2::zeros((0, 0)));
     }
 
-    let sos = design_butter_lp(2, hfreq, eeg_info.sfreq as f64);
+    let sos = design_butter_lp(2, hfreq, eeg_info.sfreq)?;
+    let picked = picks.indices(eeg_info)?;
     let n_channels = eeg_data.nrows();
 
-    let data_vec_vec: Vec<Vec<i16>> = (0..n_channels)
+    let data_vec_vec: Vec<Vec<f32>> = (0..n_channels)
         .into_par_iter()
         .map(|ch_idx| {
             let channel = eeg_data.row(ch_idx);
+            if !picked.contains(&ch_idx) {
+                return channel.to_vec();
+            }
             let filtered: Vec<f64> = sosfiltfilt_dyn(
                 channel.into_iter().map(|sample| *sample as f64),
                 &sos
             );
             filtered.into_iter()
-                .map(|sample| sample.round() as i16)
+                .map(|sample| sample as f32)
                 .collect()
         })
         .collect();
 
-    let data = vec_to_ndarray(data_vec_vec);
-    Ok(data)
+    vec_to_ndarray(data_vec_vec)
 }
 
 
 
 fn resample_channel_opt(
-    x: &[i16],
+    x: &[f32],
     target_length: usize,
     fft: &Arc<dyn rustfft::Fft<f64>>,
     ifft: &Arc<dyn rustfft::Fft<f64>>,
@@ -317,88 +347,142 @@ fn resample_channel_opt(
 }
 
 pub fn resample_eeg(
-    target_sfreq: usize,
+    target_sfreq: f64,
     eeg_info: &EEGInfo,
-    eeg_data: &Array2<i16>,
-) -> Result<Array2<i16>, Box<dyn std::error::Error>> {
+    eeg_data: &Array2<f32>,
+) -> Result<Array2<f32>, Error> {
     if eeg_data.is_empty() {
-        return Ok(Array2::from_shape_vec((0, 0), Vec::new()).unwrap());
+        return Ok(Array2::zeros((0, 0)));
+    }
+    if target_sfreq <= 0.0 || eeg_info.sfreq <= 0.0 {
+        return Err(Error::InvalidParameter(format!("Cannot resample from {} Hz to {target_sfreq} Hz", eeg_info.sfreq)));
     }
 
     let n_channels = eeg_data.nrows();
     let original_length = eeg_data.ncols();
-    let original_sfreq = eeg_info.sfreq as f64;
+    let original_sfreq = eeg_info.sfreq;
 
     let duration_seconds = original_length as f64 / original_sfreq;
-    let target_length = (duration_seconds * target_sfreq as f64).round() as usize;
+    let target_length = (duration_seconds * target_sfreq).round() as usize;
 
     println!("Resampling {} channels from {} Hz ({} samples) to {} Hz ({} samples)...",
              n_channels, original_sfreq, original_length, target_sfreq, target_length);
     println!("Duration: {:.2} seconds", duration_seconds);
 
-    let fft_planner = Arc::new(std::sync::Mutex::new(rustfft::FftPlanner::<f64>::new()));
-    let fft = {
-        let mut planner = fft_planner.lock().unwrap();
-        planner.plan_fft_forward(original_length)
-    };
-    let ifft = {
-        let mut planner = fft_planner.lock().unwrap();
-        planner.plan_fft_inverse(target_length)
-    };
+    let mut fft_planner = rustfft::FftPlanner::<f64>::new();
+    let fft = fft_planner.plan_fft_forward(original_length);
+    let ifft = fft_planner.plan_fft_inverse(target_length);
 
     let scratch_length = std::cmp::max(
         fft.get_inplace_scratch_len(),
         ifft.get_inplace_scratch_len(),
     );
 
-    let data_vec_vec: Vec<Vec<i16>> = (0..n_channels)
+    let data_vec_vec: Vec<Vec<f32>> = (0..n_channels)
         .into_par_iter()
         .map(|ch_idx| {
             let mut scratch = vec![nalgebra::Complex::zero(); scratch_length];
 
             let channel = eeg_data.row(ch_idx);
             let resampled_vec = resample_channel_opt(
-                channel.as_slice().unwrap(),
+                &channel.to_vec(),
                 target_length,
                 &fft,
                 &ifft,
                 &mut scratch
             );
 
-            resampled_vec.iter().map(|&i| i as i16).collect()
+            resampled_vec.iter().map(|&i| i as f32).collect()
         })
         .collect();
 
     println!("Resampling completed! New shape should be [{}, {}]", n_channels, target_length);
-    let data = vec_to_ndarray(data_vec_vec);
-    Ok(data)
+    vec_to_ndarray(data_vec_vec)
+}
+
+/// Rate of data resampled from `n_samples` at `sfreq` to `n_resampled` samples. The resampled
+/// length is rounded to whole samples, so this can differ slightly from the requested rate.
+pub fn resampled_sfreq(sfreq: f64, n_samples: usize, n_resampled: usize) -> f64 {
+    if n_samples == 0 {
+        return sfreq;
+    }
+    sfreq * n_resampled as f64 / n_samples as f64
+}
+
+/// Moves markers to the sample grid of `target_sfreq`, to be used together with `resample_eeg`.
+pub fn resample_markers(markers: &Markers, sfreq: f64, target_sfreq: f64) -> Markers {
+    let ratio = target_sfreq / sfreq;
+    let events: Vec<Marker> = markers
+        .events
+        .iter()
+        .map(|event| Marker {
+            position: (event.position as f64 * ratio).round() as usize,
+            duration: ((event.duration as f64 * ratio).round() as usize).max(1),
+            ..event.clone()
+        })
+        .collect();
+    let positions: Vec<f64> = markers.markers.iter().map(|&pos| (pos * ratio).round()).collect();
+    Markers {
+        n_markers: positions.len(),
+        markers: positions,
+        events,
+    }
+}
+
+/// Keeps the markers of `range` relative to its start, to be used together with a cropped
+/// recording.
+///
+/// Triggers and markers without a duration are kept when their onset lies in the range, so
+/// a trigger before the window never becomes an onset at its first sample. Bad segments and
+/// other annotations with a duration are kept when they overlap the range, clipped to it.
+pub fn crop_markers(markers: &Markers, range: Range<usize>) -> Markers {
+    let events: Vec<Marker> = markers
+        .events
+        .iter()
+        .filter_map(|event| {
+            if event.is_trigger() || event.duration <= 1 {
+                return range.contains(&event.position).then(|| Marker { position: event.position - range.start, ..event.clone() });
+            }
+            let span = event.span();
+            let (start, end) = (span.start.max(range.start), span.end.min(range.end));
+            (start < end).then(|| Marker {
+                position: start - range.start,
+                duration: end - start,
+                ..event.clone()
+            })
+        })
+        .collect();
+    Markers::from_events(events)
 }
 
 pub fn resample_eeg_linear(
-    target_sfreq: usize,
+    target_sfreq: f64,
     eeg_info: &EEGInfo,
-    eeg_data: &Array2<i16>,
-) -> Result<Array2<i16>, Box<dyn std::error::Error>> {
+    eeg_data: &Array2<f32>,
+) -> Result<Array2<f32>, Error> {
     if eeg_data.is_empty() {
-        return Ok(Array2::from_shape_vec((0, 0), Vec::new()).unwrap());
+        return Ok(Array2::zeros((0, 0)));
+    }
+    if target_sfreq <= 0.0 || eeg_info.sfreq <= 0.0 {
+        return Err(Error::InvalidParameter(format!("Cannot resample from {} Hz to {target_sfreq} Hz", eeg_info.sfreq)));
     }
 
     let n_channels = eeg_data.nrows();
     let original_length = eeg_data.ncols();
-    let original_sfreq = eeg_info.sfreq as f64;
+    let original_sfreq = eeg_info.sfreq;
 
     // Calculate the correct number of output samples
     let duration_seconds = original_length as f64 / original_sfreq;
-    let target_length = (duration_seconds * target_sfreq as f64).round() as usize;
+    let target_length = (duration_seconds * target_sfreq).round() as usize;
 
     println!("Linear resampling {} channels from {} Hz ({} samples) to {} Hz ({} samples)...",
              n_channels, original_sfreq, original_length, target_sfreq, target_length);
 
-    let data_vec_vec: Vec<Vec<i16>> = (0..n_channels)
+    let data_vec_vec: Vec<Vec<f32>> = (0..n_channels)
         .into_par_iter()
         .map(|ch_idx| {
             let channel = eeg_data.row(ch_idx);
-            let channel_slice = channel.as_slice().unwrap();
+            let channel_slice = channel.to_vec();
 
             // Linear interpolation resampling
             (0..target_length)  // Use target_length, not target_sfreq
@@ -413,13 +497,71 @@ pub fn resample_eeg_linear(
                     } else {
                         let val_floor = channel_slice[idx_floor] as f64;
                         let val_ceil = channel_slice[idx_ceil] as f64;
-                        (val_floor + fraction * (val_ceil - val_floor)) as i16
+                        (val_floor + fraction * (val_ceil - val_floor)) as f32
                     }
                 })
                 .collect()
         })
         .collect();
 
-    let data = vec_to_ndarray(data_vec_vec);
-    Ok(data)
+    vec_to_ndarray(data_vec_vec)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array2;
+
+    use super::{TMS_PULSE, crop_markers, remove_tms_pulse};
+    use crate::io::parse_header;
+    use crate::io::tests::HEADER;
+    use crate::{Error, Marker, Markers, Picks};
+
+    #[test]
+    fn removes_only_the_tms_pulses() -> Result<(), Error> {
+        let eeg_info = parse_header(&Some(HEADER.to_owned()))?;
+        let data = Array2::from_elem((3, 100), 1.0_f32);
+        let trigger = |kind: &str, description: &str, position| Marker {
+            kind: kind.to_owned(),
+            description: description.to_owned(),
+            position,
+            duration: 1,
+            channel: 0,
+            date: None,
+        };
+        let markers = Markers::from_events(vec![trigger("Stimulus", "S  1", 20), trigger("Response", "R 128", 60)]);
+
+        let removed = remove_tms_pulse(0.002, 0.003, &markers, TMS_PULSE, &eeg_info, &data, &Picks::All)?;
+
+        let zeroed: Vec<usize> = (0..100).filter(|&idx| removed[[0, idx]] == 0.0).collect();
+        assert_eq!(zeroed, [58, 59, 60, 61, 62]);
+        Ok(())
+    }
+
+    #[test]
+    fn crops_triggers_by_onset_and_clips_segments() {
+        let marker = |kind: &str, description: &str, position, duration| Marker {
+            kind: kind.to_owned(),
+            description: description.to_owned(),
+            position,
+            duration,
+            channel: 0,
+            date: None,
+        };
+        let markers = Markers::from_events(vec![
+            marker("Bad Interval", "BAD_movement", 0, 30),
+            marker("Stimulus", "S  1", 5, 10),
+            marker("Stimulus", "S  2", 20, 1),
+            marker("Comment", "note", 45, 20),
+            marker("Response", "R128", 50, 1),
+        ]);
+
+        let cropped = crop_markers(&markers, 10..50);
+
+        assert_eq!(cropped.events, [
+            marker("Bad Interval", "BAD_movement", 0, 20),
+            marker("Stimulus", "S  2", 10, 1),
+            marker("Comment", "note", 35, 5),
+        ]);
+        assert_eq!(cropped.markers, [10.0]);
+    }
 }
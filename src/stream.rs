@@ -0,0 +1,224 @@
+// Out-of-core preprocessing
+//
+// Walks a memory-mapped recording block by block, removes the TMS pulse, filters and
+// appends every processed block to the output file, so only a few blocks are ever in
+// memory. Two filter modes are available:
+// - zero-phase (default): every block is read with an overlap on both sides, filtered
+//   forwards and backwards like `signal::hp_filter` and cut back to its own samples. The
+//   overlap is three periods of the slowest cut-off, the edge transients of the Butterworth
+//   filters have decayed by about six orders of magnitude over it, so the result matches
+//   filtering the whole recording at once up to that residue.
+// - causal: a single forward pass whose filter state is carried from one block to the
+//   next, for when the phase shift does not matter and speed does.
+
+use std::ops::Range;
+
+use ndarray::{Array2, s};
+use rayon::prelude::*;
+use sci_rs::signal::filter::{design::Sos, sosfilt_dyn, sosfilt_zi_dyn};
+
+use crate::io::{BrainVisionWriter, MappedEeg};
+use crate::signal;
+use crate::{EEGInfo, Error, Markers, Picks};
+
+/// Butterworth order used by `signal::hp_filter` and `signal::lp_filter`
+const FILTER_ORDER: usize = 2;
+/// Periods of the slowest cut-off frequency covered by the overlap of zero-phase blocks. The
+/// transients of the order 2 Butterworth decay with a time constant of about a quarter period.
+const SETTLING_PERIODS: f64 = 3.0;
+
+/// What to do with every block, see `signal::rm_interp_tms_pulse`, `signal::hp_filter`
+/// and `signal::lp_filter` for the in-memory versions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamOptions {
+    /// Block length in seconds
+    pub block_seconds: f64,
+    /// Seconds before and after every TMS pulse to cut and interpolate
+    pub tms_cut: Option<(f64, f64)>,
+    /// Description of the TMS pulse markers, see `signal::TMS_PULSE`
+    pub pulse: String,
+    /// Highpass cut-off in Hz
+    pub lfreq: Option<f64>,
+    /// Lowpass cut-off in Hz
+    pub hfreq: Option<f64>,
+    /// Forward-only filtering with state carried across blocks instead of zero-phase
+    pub causal: bool,
+    /// Channels that are processed, the others are written unchanged
+    pub picks: Picks,
+}
+
+impl StreamOptions {
+    /// Samples on each side of a block needed to process it like the whole recording.
+    fn overlap(&self, sfreq: f64) -> usize {
+        // The cubic spline needs one sample on either side of the cut
+        let tms = self.tms_cut.map_or(0.0, |(tmin, tmax)| tmin.max(tmax) * sfreq + 2.0);
+        let slowest = [self.lfreq, self.hfreq].into_iter().flatten().reduce(f64::min);
+        let filter = match slowest {
+            Some(freq) if !self.causal => SETTLING_PERIODS * sfreq / freq,
+            _ => 0.0,
+        };
+        (tms + filter).ceil() as usize
+    }
+
+    /// Cascade of the lowpass and highpass sections, in the order the CLI applies them.
+    fn sos(&self, sfreq: f64) -> Result<Vec<Sos<f64>>, Error> {
+        let mut sos = Vec::new();
+        if let Some(hfreq) = self.hfreq {
+            sos.extend(signal::design_butter_lp(FILTER_ORDER, hfreq, sfreq)?);
+        }
+        if let Some(lfreq) = self.lfreq {
+            sos.extend(signal::design_butter_hp(FILTER_ORDER, lfreq, sfreq)?);
+        }
+        Ok(sos)
+    }
+}
+
+/// Forward filter of the `picked` channels, initialised to the steady state of their first
+/// sample.
+fn causal_filter(states: &mut Vec<Vec<Sos<f64>>>, sos: &[Sos<f64>], block: &Array2<f32>, picked: &[usize]) -> Result<Array2<f32>, Error> {
+    if states.is_empty() {
+        *states = block
+            .rows()
+            .into_iter()
+            .map(|row| {
+                let mut state = sos.to_vec();
+                sosfilt_zi_dyn::<f64, _, Sos<f64>>(state.iter_mut());
+                let first = row.first().map_or(0.0, |&x| f64::from(x));
+                for section in &mut state {
+                    section.zi0 *= first;
+                    section.zi1 *= first;
+                }
+                state
+            })
+            .collect();
+    }
+    let rows: Vec<Vec<f32>> = states
+        .par_iter_mut()
+        .zip(block.rows().into_iter().collect::<Vec<_>>())
+        .enumerate()
+        .map(|(ch_idx, (state, row))| {
+            if !picked.contains(&ch_idx) {
+                return row.to_vec();
+            }
+            sosfilt_dyn(row.iter().map(|&x| f64::from(x)), state)
+                .into_iter()
+                .map(|x| x as f32)
+                .collect()
+        })
+        .collect();
+    signal::vec_to_ndarray(rows)
+}
+
+/// Processes the block `range` read with `overlap` extra samples on each side and
+/// returns only the samples of `range`.
+fn process_block(
+    mapped: &MappedEeg,
+    eeg_info: &EEGInfo,
+    markers: &Markers,
+    options: &StreamOptions,
+    range: &Range<usize>,
+    overlap: usize,
+) -> Result<Array2<f32>, Error> {
+    let read = range.start.saturating_sub(overlap)..(range.end + overlap).min(mapped.n_samples());
+    let mut block = mapped.window(read.clone());
+    if let Some((tmin, tmax)) = options.tms_cut {
+        let block_markers = signal::crop_markers(markers, read.clone());
+        block = signal::rm_interp_tms_pulse(tmin, tmax, &block_markers, &options.pulse, eeg_info, &block, &options.picks)?;
+    }
+    if !options.causal {
+        if let Some(hfreq) = options.hfreq {
+            block = signal::lp_filter(hfreq, eeg_info, &block, &options.picks)?;
+        }
+        if let Some(lfreq) = options.lfreq {
+            block = signal::hp_filter(lfreq, eeg_info, &block, &options.picks)?;
+        }
+    }
+    let skip = range.start - read.start;
+    Ok(block.slice(s![.., skip..skip + range.len()]).to_owned())
+}
+
+/// Preprocesses the `BrainVision` data file `path` block by block and writes the result to
+/// the `BrainVision` header `out_vhdr` in `binary_format`.
+pub fn process_brainvision(
+    path: &str,
+    eeg_info: &EEGInfo,
+    markers: &Markers,
+    options: &StreamOptions,
+    out_vhdr: &str,
+    binary_format: &str,
+) -> Result<(), Error> {
+    if options.block_seconds <= 0.0 {
+        return Err(Error::InvalidParameter("Block length must be positive".into()));
+    }
+    let sfreq = eeg_info.sfreq;
+    let mapped = MappedEeg::open(path, eeg_info)?;
+    let block_len = ((options.block_seconds * sfreq).round() as usize).max(1);
+    let overlap = options.overlap(sfreq);
+    let sos = options.sos(sfreq)?;
+    let picked = options.picks.indices(eeg_info)?;
+    let mut states: Vec<Vec<Sos<f64>>> = Vec::new();
+    println!(
+        "Processing {:?} s in blocks of {block_len} samples with {overlap} samples of overlap",
+        mapped.n_samples() as f64 / sfreq
+    );
+
+    let mut writer = BrainVisionWriter::create(out_vhdr, eeg_info, markers, binary_format)?;
+    for start in (0..mapped.n_samples()).step_by(block_len) {
+        let range = start..(start + block_len).min(mapped.n_samples());
+        let mut block = process_block(&mapped, eeg_info, markers, options, &range, overlap)?;
+        if options.causal && !sos.is_empty() {
+            block = causal_filter(&mut states, &sos, &block, &picked)?;
+        }
+        writer.write_block(&block)?;
+        println!("Processed {:?} s", range.end as f64 / sfreq);
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array2;
+
+    use super::{StreamOptions, process_brainvision};
+    use crate::io::tests::{HEADER, temp_path};
+    use crate::io::{parse_header, read_brainvision, write_brainvision};
+    use crate::signal::{hp_filter, lp_filter};
+    use crate::{Error, Markers, Picks};
+
+    #[test]
+    fn zero_phase_blocks_match_filtering_in_memory() -> Result<(), Error> {
+        let eeg_info = parse_header(&Some(HEADER.to_owned()))?;
+        // A slow drift, alpha and line noise on top of an offset
+        let data = Array2::from_shape_fn((3, 12_000), |(ch, idx)| {
+            let time = idx as f32 / 1000.0;
+            let sine = |freq: f32| (std::f32::consts::TAU * freq * time).sin();
+            100.0 * (ch + 1) as f32 + 50.0 * sine(0.2) + 20.0 * sine(10.0) + 5.0 * sine(50.0)
+        });
+        let (input, output) = (temp_path("stream-in.vhdr"), temp_path("stream-out.vhdr"));
+        write_brainvision(&input, &eeg_info, &data, &Markers::from_events(Vec::new()), "IEEE_FLOAT_32")?;
+        let (eeg_info, eeg_data, markers) = read_brainvision(&input)?;
+        let options = StreamOptions {
+            block_seconds: 1.5,
+            tms_cut: None,
+            pulse: String::new(),
+            lfreq: Some(1.0),
+            hfreq: Some(40.0),
+            causal: false,
+            picks: Picks::All,
+        };
+
+        process_brainvision(&input.replace(".vhdr", ".eeg"), &eeg_info, &markers, &options, &output, "IEEE_FLOAT_32")?;
+        let (_, streamed, _) = read_brainvision(&output)?;
+        for path in [&input, &output] {
+            for extension in ["vhdr", "vmrk", "eeg"] {
+                std::fs::remove_file(path.replace(".vhdr", &format!(".{extension}"))).ok();
+            }
+        }
+
+        let in_memory = hp_filter(1.0, &eeg_info, &lp_filter(40.0, &eeg_info, &eeg_data.data, &Picks::All)?, &Picks::All)?;
+        assert_eq!(streamed.data.dim(), in_memory.dim());
+        let error = (&streamed.data - &in_memory).mapv(f32::abs).fold(0.0, |max: f32, &x| max.max(x));
+        assert!(error < 1e-3, "{error}");
+        Ok(())
+    }
+}
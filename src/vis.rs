@@ -5,7 +5,7 @@ use plotly::common::Mode;
 use plotly::ndarray::ArrayTraces;
 use plotly::{Plot, Scatter, Layout};
 
-use crate::EvokedData;
+use crate::{EEGInfo, Error, EvokedData, Picks};
 
 
 
@@ -43,28 +43,32 @@ pub fn plot_single_channel(evoked: EvokedData, channel: usize, file_name: &str)
 
 
 
-pub fn plot_evoked(evoked: EvokedData, file_name: &str) {
-    let (n_channels, n_samples) = evoked.evoked.dim();
+/// Butterfly plot of the picked channels of `evoked`, their types are taken from
+/// `eeg_info` when given and inferred from the names otherwise.
+pub fn plot_evoked(evoked: &EvokedData, eeg_info: Option<&EEGInfo>, picks: &Picks, file_name: &str) -> Result<(), Error> {
+    let n_samples = evoked.evoked.ncols();
     if n_samples == 0 {
-        return;
+        return Ok(());
     }
+    let picked = picks.indices_in(&evoked.ch_names, eeg_info)?;
 
     let t: Array<f64, Ix1> = Array::linspace(-evoked.tmin, evoked.tmax, n_samples);
 
     let mut plot = Plot::new();
 
     // Create one trace per channel with proper names
-    for (ch_idx, channel_name) in evoked.ch_names.iter().enumerate() {
+    for &ch_idx in &picked {
         let ys = evoked.evoked.row(ch_idx).to_owned();
         let trace = Scatter::from_array(t.clone(), ys)
             .mode(Mode::Lines)
-            .name(channel_name);
+            .name(&evoked.ch_names[ch_idx]);
         plot.add_trace(trace);
     }
 
-    let title_text = format!("<b>Evoked</b> ({} Channels)", n_channels);
+    let title_text = format!("<b>Evoked</b> ({} Channels)", picked.len());
     let layout = Layout::new().title(title_text);
     plot.set_layout(layout);
 
     plot.write_html(file_name);
+    Ok(())
 }